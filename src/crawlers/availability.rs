@@ -0,0 +1,64 @@
+//! Detection of sold-out products on product pages.
+
+use scraper::{Html, Selector};
+
+/// Texts marking a product as sold out, matched case-insensitively.
+const OUT_OF_STOCK_MARKERS: &[&str] = &[
+    "нет в наличии",
+    "нет на складе",
+    "распродано",
+    "закончился",
+    "out of stock",
+    "sold out",
+];
+
+/// Returns `true` when `text` carries an out-of-stock marker such as
+/// `Нет в наличии`; `В наличии` does not.
+pub fn is_out_of_stock_text(text: &str) -> bool {
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    OUT_OF_STOCK_MARKERS
+        .iter()
+        .any(|marker| text.contains(marker))
+}
+
+/// Returns `false` when an element matched by `availability` reads as out
+/// of stock.
+///
+/// Pages without such an element count as available, so stores or
+/// selectors without a stock indicator keep every product.
+pub fn is_available(document: &Html, availability: &Selector) -> bool {
+    !document
+        .select(availability)
+        .any(|element| is_out_of_stock_text(&element.text().collect::<String>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawlers::selectors::parse_selector;
+
+    #[test]
+    fn out_of_stock_markers_are_matched_loosely() {
+        assert!(is_out_of_stock_text("  Нет   в\nналичии "));
+        assert!(is_out_of_stock_text("SOLD OUT"));
+        assert!(!is_out_of_stock_text("В наличии"));
+        assert!(!is_out_of_stock_text(""));
+    }
+
+    #[test]
+    fn pages_without_a_stock_element_are_available() {
+        let availability = parse_selector("div.item-stock").unwrap();
+        let sold_out =
+            Html::parse_document(r#"<div class="item-stock"><span>Нет в наличии</span></div>"#);
+        let in_stock = Html::parse_document(r#"<div class="item-stock">В наличии</div>"#);
+        let unmarked = Html::parse_document("<h1>Да Хун Пао</h1>");
+
+        assert!(!is_available(&sold_out, &availability));
+        assert!(is_available(&in_stock, &availability));
+        assert!(is_available(&unmarked, &availability));
+    }
+}
@@ -0,0 +1,152 @@
+//! Extraction of tea brewing parameters from product property tables.
+
+use scraper::{Html, Selector};
+
+/// Brewing parameters listed on a product page.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BrewParameters {
+    pub temperature: Option<String>,
+    pub steep_time: Option<String>,
+    pub leaf_amount: Option<String>,
+}
+
+impl BrewParameters {
+    /// Returns `true` when no parameter was found.
+    pub fn is_empty(&self) -> bool {
+        self.temperature.is_none() && self.steep_time.is_none() && self.leaf_amount.is_none()
+    }
+
+    /// Renders the parameters as a single description line, e.g.
+    /// `Заваривание: температура 85 °C; время 3 мин`.
+    pub fn describe(&self) -> Option<String> {
+        let parts = [
+            ("температура", &self.temperature),
+            ("время", &self.steep_time),
+            ("количество", &self.leaf_amount),
+        ]
+        .into_iter()
+        .filter_map(|(label, value)| value.as_ref().map(|value| format!("{label} {value}")))
+        .collect::<Vec<_>>();
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(format!("Заваривание: {}", parts.join("; ")))
+        }
+    }
+
+    /// Appends the rendered parameters to `description` on a new line so they
+    /// become part of the embedding prompt.
+    pub fn append_to(&self, description: String) -> String {
+        match self.describe() {
+            Some(line) if description.is_empty() => line,
+            Some(line) => format!("{description}\n{line}"),
+            None => description,
+        }
+    }
+}
+
+/// Reads brewing parameters from a property table.
+///
+/// Each element matched by `rows` is a property; its first element matched by
+/// `cells` is the label and the last one is the value. Labels are matched
+/// case-insensitively by their Russian stems.
+pub fn parse_brew_parameters(document: &Html, rows: &Selector, cells: &Selector) -> BrewParameters {
+    let mut parameters = BrewParameters::default();
+
+    for row in document.select(rows) {
+        let texts = row
+            .select(cells)
+            .map(|cell| {
+                cell.text()
+                    .collect::<String>()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>();
+        let (Some(label), Some(value)) = (texts.first(), texts.last()) else {
+            continue;
+        };
+        if texts.len() < 2 || value.is_empty() {
+            continue;
+        }
+
+        let label = label.to_lowercase();
+        let slot = if label.contains("температур") {
+            &mut parameters.temperature
+        } else if label.contains("время") {
+            &mut parameters.steep_time
+        } else if label.contains("дозировк") || label.contains("количеств") {
+            &mut parameters.leaf_amount
+        } else {
+            continue;
+        };
+        slot.get_or_insert_with(|| value.clone());
+    }
+
+    parameters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::embedding::product_embedding_prompt;
+
+    const FIXTURE: &str = r#"
+        <table class="props_list">
+            <tr><td class="char_name">Страна</td><td class="char_value">Китай</td></tr>
+            <tr><td class="char_name">Температура заваривания</td><td class="char_value">85 °C</td></tr>
+            <tr><td class="char_name">Время заваривания</td><td class="char_value"> 3-5
+                мин </td></tr>
+            <tr><td class="char_name">Дозировка</td><td class="char_value">5 г на 150 мл</td></tr>
+        </table>
+    "#;
+
+    fn parse_fixture() -> BrewParameters {
+        let document = Html::parse_document(FIXTURE);
+        let rows = Selector::parse("table.props_list tr").unwrap();
+        let cells = Selector::parse("td").unwrap();
+        parse_brew_parameters(&document, &rows, &cells)
+    }
+
+    #[test]
+    fn extracts_temperature_time_and_amount() {
+        let parameters = parse_fixture();
+
+        assert_eq!(parameters.temperature.as_deref(), Some("85 °C"));
+        assert_eq!(parameters.steep_time.as_deref(), Some("3-5 мин"));
+        assert_eq!(parameters.leaf_amount.as_deref(), Some("5 г на 150 мл"));
+    }
+
+    #[test]
+    fn missing_table_yields_no_parameters() {
+        let document = Html::parse_document("<p>Зелёный чай</p>");
+        let rows = Selector::parse("table.props_list tr").unwrap();
+        let cells = Selector::parse("td").unwrap();
+
+        let parameters = parse_brew_parameters(&document, &rows, &cells);
+
+        assert!(parameters.is_empty());
+        assert_eq!(parameters.append_to("Описание".to_string()), "Описание");
+    }
+
+    #[test]
+    fn parameters_appear_in_embedding_prompt() {
+        let description = parse_fixture().append_to("Мягкий зелёный чай".to_string());
+
+        let prompt = product_embedding_prompt(
+            "Лунцзин",
+            "L-1",
+            "Зелёный чай",
+            "г",
+            500.0,
+            50.0,
+            &description,
+        );
+
+        assert!(prompt.contains(
+            "Description: Мягкий зелёный чай\nЗаваривание: температура 85 °C; время 3-5 мин; количество 5 г на 150 мл"
+        ));
+    }
+}
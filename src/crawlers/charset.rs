@@ -0,0 +1,82 @@
+//! Character set detection for fetched pages.
+
+use encoding_rs::{Encoding, UTF_8};
+
+/// Number of leading body bytes searched for a `<meta>` charset declaration.
+const META_SNIFF_BYTES: usize = 1024;
+
+/// Decodes a page body to text.
+///
+/// The charset comes from the `Content-Type` header, then from a
+/// `<meta charset>` or `<meta http-equiv="Content-Type">` declaration near the
+/// start of the document, and defaults to UTF-8. A byte order mark overrides
+/// both. Unknown labels fall back to UTF-8 and malformed sequences are
+/// replaced rather than rejected.
+pub(crate) fn decode_html(body: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(|value| charset_label(value.as_bytes()))
+        .or_else(|| charset_label(&body[..body.len().min(META_SNIFF_BYTES)]))
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+/// Returns the value following the first `charset=` in `bytes`, without
+/// surrounding quotes.
+fn charset_label(bytes: &[u8]) -> Option<String> {
+    let lowercase = bytes.to_ascii_lowercase();
+    let start = lowercase
+        .windows(b"charset=".len())
+        .position(|window| window == b"charset=")?
+        + b"charset=".len();
+
+    let label: String = lowercase[start..]
+        .iter()
+        .skip_while(|&&byte| byte == b'"' || byte == b'\'')
+        .take_while(|&&byte| {
+            byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b':' | b'.')
+        })
+        .map(|&byte| char::from(byte))
+        .collect();
+    (!label.is_empty()).then_some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1251;
+
+    use super::*;
+
+    fn windows_1251(html: &str) -> Vec<u8> {
+        WINDOWS_1251.encode(html).0.into_owned()
+    }
+
+    #[test]
+    fn meta_charset_decodes_windows_1251() {
+        let body = windows_1251(
+            r#"<html><head><meta charset="windows-1251"></head><body>Пуэр Шу</body></html>"#,
+        );
+
+        assert!(decode_html(&body, Some("text/html")).contains("Пуэр Шу"));
+    }
+
+    #[test]
+    fn content_type_charset_takes_precedence() {
+        let body = windows_1251(
+            r#"<meta http-equiv="Content-Type" content="text/html; charset=utf-8"><p>Улун</p>"#,
+        );
+
+        let text = decode_html(&body, Some("text/html; charset=Windows-1251"));
+
+        assert!(text.contains("Улун"));
+    }
+
+    #[test]
+    fn undeclared_charset_defaults_to_utf8() {
+        let body = "<p>Зелёный чай</p>".as_bytes();
+
+        assert_eq!(decode_html(body, None), "<p>Зелёный чай</p>");
+    }
+}
@@ -0,0 +1,96 @@
+//! Opt-in dumps of fetched pages a crawler could not read anything from.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::Utc;
+use scraper::Html;
+
+/// Distinguishes files written within the same microsecond.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes pages that yielded nothing, e.g. no product links, into a
+/// directory so broken selectors can be checked against the real markup.
+///
+/// Disabled (the default) unless built with a directory; whole pages are
+/// only ever written to files, never logged.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlDumper {
+    dir: Option<PathBuf>,
+}
+
+impl HtmlDumper {
+    /// Creates a dumper writing into `dir`, or a disabled one for `None`.
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    /// Writes `document`, fetched from `url`, on which no `what` (e.g.
+    /// `products`) was found; does nothing when disabled.
+    ///
+    /// Failures to write are logged as a `warn` and never fail the crawl.
+    pub fn dump(&self, what: &str, url: &str, document: &Html) {
+        let Some(dir) = self.dir.as_deref() else {
+            return;
+        };
+        match write_page(dir, url, &document.html()) {
+            Ok(path) => log::info!(
+                "Found no {what} at {url}; wrote the page to {}",
+                path.display()
+            ),
+            Err(e) => log::warn!(
+                "Failed to write the page of {url} to {}: {e}",
+                dir.display()
+            ),
+        }
+    }
+}
+
+/// Writes `html` into its own `<timestamp>-<sequence>.html` file in `dir`,
+/// preceded by a comment with the page URL. `dir` is created if missing.
+fn write_page(dir: &Path, url: &str, html: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+    let path = dir.join(format!("{timestamp}-{sequence}.html"));
+
+    let mut file = fs::File::create_new(&path)?;
+    // `--` cannot appear inside an HTML comment.
+    writeln!(file, "<!-- {} -->", url.replace("--", "%2D%2D"))?;
+    file.write_all(html.as_bytes())?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_written_only_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let document = Html::parse_document("<html><body><p>Нет товаров</p></body></html>");
+
+        HtmlDumper::default().dump("products", "https://store.example/p/1", &document);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let dumps = dir.path().join("dumps");
+        HtmlDumper::new(Some(dumps.clone())).dump(
+            "products",
+            "https://store.example/p/1",
+            &document,
+        );
+
+        let files = fs::read_dir(&dumps)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        let written = fs::read_to_string(files[0].path()).unwrap();
+        assert!(written.starts_with("<!-- https://store.example/p/1 -->\n"));
+        assert!(written.contains("<p>Нет товаров</p>"));
+    }
+}
@@ -0,0 +1,267 @@
+//! Fallback extraction of product fields from schema.org JSON-LD.
+
+use scraper::{Html, Selector};
+use serde_json::Value;
+use url::Url;
+
+use crate::crawlers::CrawlerResult;
+use crate::crawlers::price::{Currency, parse_price};
+use crate::crawlers::selectors::parse_selector;
+
+/// Product fields read from a JSON-LD `Product`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonLdProduct {
+    pub name: Option<String>,
+    pub sku: Option<String>,
+    pub price: Option<f64>,
+    pub images: Vec<String>,
+}
+
+/// Returns `true` when `node` is typed `Product`, as `"Product"` or in a
+/// list of types.
+fn is_product(node: &Value) -> bool {
+    match node.get("@type") {
+        Some(Value::String(kind)) => kind == "Product",
+        Some(Value::Array(kinds)) => kinds.iter().any(|kind| kind == "Product"),
+        _ => false,
+    }
+}
+
+/// Finds the first `Product` in a JSON-LD document, looking into top-level
+/// arrays and `@graph` lists.
+fn find_product(node: &Value) -> Option<&Value> {
+    match node {
+        Value::Array(nodes) => nodes.iter().find_map(find_product),
+        Value::Object(_) if is_product(node) => Some(node),
+        Value::Object(_) => node.get("@graph").and_then(find_product),
+        _ => None,
+    }
+}
+
+/// Reads a non-empty string, or a number written as one.
+fn text(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(text) => text.trim().to_string(),
+        Value::Number(number) => number.to_string(),
+        _ => return None,
+    };
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// Reads the ruble price of an `Offer`, or the `lowPrice` of an
+/// `AggregateOffer`; lists of offers yield the first priced one.
+///
+/// Offers in another `priceCurrency` or with another currency symbol are
+/// skipped, as products have no currency field.
+fn offer_price(offers: &Value) -> Option<f64> {
+    match offers {
+        Value::Array(offers) => offers.iter().find_map(offer_price),
+        Value::Object(_) => {
+            let foreign = offers
+                .get("priceCurrency")
+                .and_then(text)
+                .is_some_and(|currency| !currency.eq_ignore_ascii_case(Currency::Rub.code()));
+            if foreign {
+                return None;
+            }
+            ["price", "lowPrice"]
+                .iter()
+                .filter_map(|key| offers.get(key).and_then(text))
+                .find_map(|price| match parse_price(&price)? {
+                    (price, Currency::Rub) => Some(price),
+                    _ => None,
+                })
+        }
+        _ => None,
+    }
+}
+
+/// Reads image URLs given as a string, an `ImageObject` or a list of either.
+fn images(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(images).collect(),
+        Value::Object(_) => value.get("url").and_then(text).into_iter().collect(),
+        _ => text(value).into_iter().collect(),
+    }
+}
+
+/// Reads product fields from `<script type="application/ld+json">` blocks,
+/// with its selector compiled once per crawler.
+#[derive(Debug, Clone)]
+pub struct JsonLdParser {
+    script: Selector,
+}
+
+impl JsonLdParser {
+    /// Compiles the JSON-LD script selector.
+    pub fn new() -> CrawlerResult<Self> {
+        Ok(Self {
+            script: parse_selector("script[type='application/ld+json']")?,
+        })
+    }
+
+    /// Reads the first schema.org `Product` of the page.
+    ///
+    /// Scripts that are not valid JSON are skipped, and `None` is returned
+    /// when no script describes a product.
+    pub fn parse(&self, document: &Html) -> Option<JsonLdProduct> {
+        document.select(&self.script).find_map(|script| {
+            let json = serde_json::from_str::<Value>(&script.text().collect::<String>()).ok()?;
+            let product = find_product(&json)?;
+            Some(JsonLdProduct {
+                name: product.get("name").and_then(text),
+                sku: product.get("sku").and_then(text),
+                price: product.get("offers").and_then(offer_price),
+                images: product.get("image").map(images).unwrap_or_default(),
+            })
+        })
+    }
+
+    /// Completes the fields the page selectors read from the product at
+    /// `url` with the page's JSON-LD.
+    ///
+    /// The selectors win: JSON-LD is only read when the name is empty or the
+    /// price is zero, and then fills just the empty name and SKU, the zero
+    /// price and missing images. Relative image URLs are resolved against
+    /// `url`.
+    pub fn fill_missing(
+        &self,
+        document: &Html,
+        url: &str,
+        name: String,
+        sku: String,
+        price: f64,
+        images: Vec<String>,
+    ) -> (String, String, f64, Vec<String>) {
+        if !name.trim().is_empty() && price != 0.0 {
+            return (name, sku, price, images);
+        }
+        let Some(product) = self.parse(document) else {
+            return (name, sku, price, images);
+        };
+
+        let name = match product.name {
+            Some(fallback) if name.trim().is_empty() => fallback,
+            _ => name,
+        };
+        let sku = match product.sku {
+            Some(fallback) if sku.trim().is_empty() => fallback,
+            _ => sku,
+        };
+        let price = match product.price {
+            Some(fallback) if price == 0.0 => fallback,
+            _ => price,
+        };
+        let images = if images.is_empty() {
+            let base = Url::parse(url).ok();
+            product
+                .images
+                .into_iter()
+                .filter_map(|image| match &base {
+                    Some(base) => base.join(&image).ok().map(String::from),
+                    None => Some(image),
+                })
+                .collect()
+        } else {
+            images
+        };
+        (name, sku, price, images)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(json_ld: &str) -> Html {
+        Html::parse_document(&format!(
+            r#"<html><head>
+                <script type="application/ld+json">{{ not json</script>
+                <script type="application/ld+json">{json_ld}</script>
+            </head><body></body></html>"#
+        ))
+    }
+
+    #[test]
+    fn reads_a_product_from_a_graph() {
+        let document = page(
+            r#"{"@context": "https://schema.org", "@graph": [
+                {"@type": "BreadcrumbList"},
+                {"@type": ["Product"], "name": " Да Хун Пао ", "sku": 1042,
+                 "image": [{"@type": "ImageObject", "url": "/img/1.jpg"}, "https://cdn.example/2.jpg"],
+                 "offers": [{"@type": "Offer"}, {"@type": "Offer", "price": "1 250,50"}]}
+            ]}"#,
+        );
+
+        assert_eq!(
+            JsonLdParser::new().unwrap().parse(&document),
+            Some(JsonLdProduct {
+                name: Some("Да Хун Пао".to_string()),
+                sku: Some("1042".to_string()),
+                price: Some(1250.5),
+                images: vec![
+                    "/img/1.jpg".to_string(),
+                    "https://cdn.example/2.jpg".to_string()
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn fills_only_what_the_selectors_missed() {
+        let document = page(
+            r#"{"@type": "Product", "name": "Пуэр", "sku": "LD-1", "image": "/img/p.jpg",
+                "offers": {"@type": "AggregateOffer", "lowPrice": 990}}"#,
+        );
+        let parser = JsonLdParser::new().unwrap();
+        let url = "https://store.example/products/puer";
+
+        assert_eq!(
+            parser.fill_missing(&document, url, String::new(), "CSS-1".into(), 0.0, vec![]),
+            (
+                "Пуэр".to_string(),
+                "CSS-1".to_string(),
+                990.0,
+                vec!["https://store.example/img/p.jpg".to_string()]
+            )
+        );
+        assert_eq!(
+            parser.fill_missing(&document, url, "Улун".into(), String::new(), 450.0, vec![]),
+            ("Улун".to_string(), String::new(), 450.0, vec![])
+        );
+    }
+
+    #[test]
+    fn offers_in_another_currency_are_skipped() {
+        let document = page(
+            r#"{"@type": "Product", "name": "Пуэр", "offers": [
+                {"@type": "Offer", "price": "12.99", "priceCurrency": "USD"},
+                {"@type": "Offer", "price": "€ 11"},
+                {"@type": "Offer", "price": "990", "priceCurrency": "RUB"}
+            ]}"#,
+        );
+
+        let product = JsonLdParser::new().unwrap().parse(&document).unwrap();
+
+        assert_eq!(product.price, Some(990.0));
+    }
+
+    #[test]
+    fn pages_without_a_product_keep_their_fields() {
+        let document = page(r#"{"@type": "Organization", "name": "Store"}"#);
+        let parser = JsonLdParser::new().unwrap();
+
+        assert_eq!(parser.parse(&document), None);
+        assert_eq!(
+            parser.fill_missing(
+                &document,
+                "https://store.example/",
+                String::new(),
+                String::new(),
+                0.0,
+                vec![]
+            ),
+            (String::new(), String::new(), 0.0, vec![])
+        );
+    }
+}
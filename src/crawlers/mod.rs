@@ -1,4 +1,11 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use async_trait::async_trait;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use futures::StreamExt;
+use pushkind_common::repository::errors::RepositoryError;
 use pushkind_dantes::domain::product::NewProduct;
 use pushkind_dantes::domain::types::{
     CategoryName, CrawlerId, ImageUrl, ProductAmount, ProductDescription, ProductName,
@@ -6,10 +13,25 @@ use pushkind_dantes::domain::types::{
 };
 use rand::distr::{Alphanumeric, SampleString};
 use regex::Regex;
+use reqwest::header::{AUTHORIZATION, COOKIE, HeaderMap, HeaderValue};
 use thiserror::Error;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{Duration, Instant};
+use url::Url;
 
+pub mod availability;
+pub mod brew;
+pub mod charset;
+pub mod debug_html;
 pub mod gutenberg;
+pub mod json_ld;
+pub mod offers;
+pub mod price;
+pub mod query;
+pub mod robots;
 pub mod rusteaco;
+pub mod selectors;
+pub mod sitemap;
 pub mod tea101;
 pub mod teanadin;
 pub mod wintergreen;
@@ -18,21 +40,510 @@ pub mod wintergreen;
 pub enum CrawlerError {
     #[error("Failed to create a crawler: {0}")]
     Build(String),
+    /// The server answered with a non-success status, e.g. a missing page.
+    #[error("Failed to get URL {url}: {status}")]
+    Http {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    /// No usable response arrived, e.g. the site is down or the connection
+    /// dropped while reading the body.
+    #[error("Failed to get URL {url}: {message}")]
+    Network { url: String, message: String },
+    /// No site crawler is registered under the selector.
+    #[error("Unknown crawler {0}")]
+    UnknownCrawler(String),
+    /// Another run of the crawler holds its `processing` flag.
+    #[error("Crawler is already running")]
+    AlreadyRunning,
+    /// The crawl did not finish within the configured number of seconds.
+    #[error("Crawl did not finish within {0}s")]
+    Timeout(u64),
+    /// Reading or writing the crawler's rows failed.
+    #[error("Repository error: {0}")]
+    Repository(#[from] RepositoryError),
+}
+
+impl CrawlerError {
+    /// Returns `true` when the server answered `404 Not Found`, i.e. the page
+    /// no longer exists rather than failing to load.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, CrawlerError::Http { status, .. } if *status == reqwest::StatusCode::NOT_FOUND)
+    }
 }
 
 pub type CrawlerResult<T> = Result<T, CrawlerError>;
 
+/// Outcome of fetching a single product page, see
+/// [`WebstoreCrawler::get_product_page`].
+#[derive(Debug)]
+pub enum ProductPage {
+    /// Products parsed from the page; empty when the page failed to load or
+    /// matched none of the crawler's selectors.
+    Products(Vec<NewProduct>),
+    /// The store answered `404 Not Found`: the product was removed.
+    NotFound,
+}
+
 /// An abstraction over web store crawlers that produce [`NewProduct`]s.
 #[async_trait]
 pub trait WebstoreCrawler: Send + Sync {
+    /// Discovers the unique links of every product page on the target site.
+    async fn get_product_urls(&self) -> Vec<String>;
+
     /// Crawls the target site and returns every product discovered.
-    async fn get_products(&self) -> Vec<NewProduct>;
+    ///
+    /// Product pages are fetched concurrently with `join_all`, each reported
+    /// to `progress`, and the result is deduplicated with [`dedup_products`].
+    async fn get_products(&self, progress: &CrawlProgress) -> Vec<NewProduct> {
+        let links = self.get_product_urls().await;
+        progress.start(links.len());
+        let products = futures::future::join_all(links.iter().map(|link| async move {
+            let products = self.get_product(link).await;
+            progress.record(products.len());
+            products
+        }))
+        .await;
+
+        dedup_products(products.into_iter().flatten().collect())
+    }
+
+    /// Crawls the target site and sends the products of each page to `sink`
+    /// as soon as the page is parsed.
+    ///
+    /// At most `concurrency` product pages are in flight and a full `sink`
+    /// pauses fetching, so memory use does not grow with the catalog size.
+    /// Each fetched page is reported to `progress`. Products are not
+    /// deduplicated here, as pages are sent independently; the consumer
+    /// drops repeats with [`SeenProducts`]. Returns the number of products
+    /// sent.
+    async fn stream_products(
+        &self,
+        sink: mpsc::Sender<Vec<NewProduct>>,
+        concurrency: usize,
+        progress: &CrawlProgress,
+    ) -> usize {
+        let links = self.get_product_urls().await;
+        progress.start(links.len());
+        let mut pages = futures::stream::iter(links.iter())
+            .map(|link| self.get_product(link))
+            .buffer_unordered(concurrency.max(1));
+
+        let mut sent = 0;
+        while let Some(products) = pages.next().await {
+            progress.record(products.len());
+            if products.is_empty() {
+                continue;
+            }
+            let count = products.len();
+            if sink.send(products).await.is_err() {
+                log::warn!("Product consumer stopped, abandoning the remaining product pages");
+                break;
+            }
+            sent += count;
+        }
+        sent
+    }
 
     /// Fetches product information from a single URL.
     ///
     /// Some pages may describe multiple product variants, therefore the
     /// implementation returns a collection of [`NewProduct`]s.
     async fn get_product(&self, url: &str) -> Vec<NewProduct>;
+
+    /// Like [`Self::get_product`], but reports a page the store answers with
+    /// `404 Not Found` as [`ProductPage::NotFound`], so partial runs can drop
+    /// the stored product instead of keeping it.
+    ///
+    /// Crawlers that cannot tell a missing page from a parse miss keep this
+    /// default, which never reports one.
+    async fn get_product_page(&self, url: &str) -> ProductPage {
+        ProductPage::Products(self.get_product(url).await)
+    }
+
+    /// Returns counters collected by the crawler so far.
+    ///
+    /// Crawlers that do not track progress report all zeroes.
+    fn stats(&self) -> CrawlStats {
+        CrawlStats::default()
+    }
+}
+
+/// Per-run crawl counters, modelled on the category matcher's `MatchStats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CrawlStats {
+    pub categories_discovered: usize,
+    pub pages_visited: usize,
+    pub product_links_found: usize,
+    pub products_parsed: usize,
+    /// Parsed products dropped by the required-field rules before writing.
+    pub products_rejected: usize,
+    /// Product pages marked as out of stock, whether or not they were
+    /// skipped.
+    pub products_unavailable: usize,
+    pub products_written: usize,
+    /// Stored products deleted because their page answered `404 Not Found`.
+    pub products_removed: usize,
+    /// All failed fetches; the sum of `http_failures` and `network_failures`.
+    pub fetch_failures: usize,
+    pub http_failures: usize,
+    pub network_failures: usize,
+}
+
+/// Thread-safe counters shared by the concurrent tasks of one crawler.
+#[derive(Debug, Default)]
+pub(crate) struct CrawlCounters {
+    pub(crate) categories_discovered: AtomicUsize,
+    pub(crate) pages_visited: AtomicUsize,
+    pub(crate) product_links_found: AtomicUsize,
+    pub(crate) products_unavailable: AtomicUsize,
+    pub(crate) fetch_failures: AtomicUsize,
+    pub(crate) http_failures: AtomicUsize,
+    pub(crate) network_failures: AtomicUsize,
+}
+
+impl CrawlCounters {
+    /// Adds `value` to `counter`.
+    pub(crate) fn add(counter: &AtomicUsize, value: usize) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Counts a failed fetch under its [`CrawlerError`] category.
+    pub(crate) fn record_failure(&self, error: &CrawlerError) {
+        Self::add(&self.fetch_failures, 1);
+        match error {
+            CrawlerError::Http { .. } => Self::add(&self.http_failures, 1),
+            CrawlerError::Network { .. } => Self::add(&self.network_failures, 1),
+            CrawlerError::Build(_)
+            | CrawlerError::UnknownCrawler(_)
+            | CrawlerError::AlreadyRunning
+            | CrawlerError::Timeout(_)
+            | CrawlerError::Repository(_) => {}
+        }
+    }
+
+    /// Copies the current counter values into a [`CrawlStats`].
+    ///
+    /// `products_parsed`, `products_rejected`, `products_written` and
+    /// `products_removed` are filled in by the caller, which sees the
+    /// products.
+    pub(crate) fn snapshot(&self) -> CrawlStats {
+        CrawlStats {
+            categories_discovered: self.categories_discovered.load(Ordering::Relaxed),
+            pages_visited: self.pages_visited.load(Ordering::Relaxed),
+            product_links_found: self.product_links_found.load(Ordering::Relaxed),
+            products_parsed: 0,
+            products_rejected: 0,
+            products_unavailable: self.products_unavailable.load(Ordering::Relaxed),
+            products_written: 0,
+            products_removed: 0,
+            fetch_failures: self.fetch_failures.load(Ordering::Relaxed),
+            http_failures: self.http_failures.load(Ordering::Relaxed),
+            network_failures: self.network_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Periodic progress report of the product page fetches of a full crawl.
+///
+/// The concurrent page tasks share one instance; every `every` fetched pages
+/// an `info` line reports the pages fetched and products parsed so far, so
+/// operators can tell a long crawl from a stuck one. `every == 0` (the
+/// default) disables the report.
+#[derive(Debug, Default)]
+pub struct CrawlProgress {
+    every: usize,
+    label: String,
+    total: AtomicUsize,
+    pages: AtomicUsize,
+    products: AtomicUsize,
+}
+
+impl CrawlProgress {
+    /// Reports every `every` pages, starting each line with `label`, e.g. the
+    /// crawl's log context.
+    pub fn new(every: usize, label: impl Into<String>) -> Self {
+        Self {
+            every,
+            label: label.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Records the number of product pages the crawl is about to fetch.
+    pub(crate) fn start(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Counts a fetched page that yielded `products`, logging when a
+    /// reporting interval is complete.
+    pub(crate) fn record(&self, products: usize) {
+        let products = self.products.fetch_add(products, Ordering::Relaxed) + products;
+        let pages = self.pages.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.every > 0 && pages % self.every == 0 {
+            log::info!(
+                "{} Crawl progress: {pages}/{} product pages fetched, {products} products parsed",
+                self.label,
+                self.total.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    /// Returns the pages fetched and products parsed so far.
+    pub fn snapshot(&self) -> (usize, usize) {
+        (
+            self.pages.load(Ordering::Relaxed),
+            self.products.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Optional upper bounds on the size of a full crawl.
+///
+/// Caps protect against broken pagination selectors or crawler traps that
+/// would otherwise enumerate an unbounded number of pages. `None` disables a
+/// cap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CrawlLimits {
+    /// Maximum number of listing pages visited.
+    pub max_pages: Option<usize>,
+    /// Maximum number of product pages fetched.
+    pub max_products: Option<usize>,
+    /// Maximum number of categories walked.
+    pub max_categories: Option<usize>,
+    /// Time category discovery may take before the crawl goes on with the
+    /// categories found so far.
+    pub category_timeout: Option<Duration>,
+}
+
+impl CrawlLimits {
+    /// Keeps at most `max_pages` listing pages, logging a warning for `site`
+    /// when the cap is hit.
+    pub(crate) fn cap_pages(&self, pages: Vec<String>, site: &str) -> Vec<String> {
+        cap_links(pages, self.max_pages, "page", site)
+    }
+
+    /// Keeps at most `max_products` product links, logging a warning for
+    /// `site` when the cap is hit.
+    pub(crate) fn cap_products(&self, links: HashSet<String>, site: &str) -> Vec<String> {
+        cap_links(
+            links.into_iter().collect(),
+            self.max_products,
+            "product",
+            site,
+        )
+    }
+
+    /// Keeps at most `max_categories` category links, logging a warning for
+    /// `site` when the cap is hit.
+    pub(crate) fn cap_categories(&self, categories: Vec<String>, site: &str) -> Vec<String> {
+        cap_links(categories, self.max_categories, "category", site)
+    }
+
+    /// Awaits the category `discovery` of `site`, which adds the categories
+    /// it finds to `found`, for at most `category_timeout` and returns them.
+    ///
+    /// A discovery still running then is dropped with a warning, and the
+    /// categories it had found by then are returned.
+    pub(crate) async fn discover_categories<F>(
+        &self,
+        found: &Mutex<Vec<String>>,
+        discovery: F,
+        site: &str,
+    ) -> Vec<String>
+    where
+        F: Future<Output = ()>,
+    {
+        match self.category_timeout {
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, discovery).await.is_err() {
+                    log::warn!(
+                        "Category discovery of {site} timed out after {}s; crawling the {} categories found so far",
+                        timeout.as_secs(),
+                        found.lock().await.len()
+                    );
+                }
+            }
+            None => discovery.await,
+        }
+        std::mem::take(&mut *found.lock().await)
+    }
+}
+
+/// Truncates `links` to `cap` entries.
+///
+/// Links are sorted before truncation so that repeated runs keep the same
+/// subset regardless of discovery order.
+fn cap_links(mut links: Vec<String>, cap: Option<usize>, kind: &str, site: &str) -> Vec<String> {
+    match cap {
+        Some(cap) if links.len() > cap => {
+            log::warn!(
+                "Crawl of {site} hit the {kind} cap of {cap}; skipping {} {kind} links",
+                links.len() - cap
+            );
+            links.sort();
+            links.truncate(cap);
+            links
+        }
+        _ => links,
+    }
+}
+
+/// Resolves the configured entry page of category discovery against the
+/// store's `base_url`.
+///
+/// Unlike a bad category URL, which is skipped, a bad entry page would leave
+/// the crawl without categories, so one that does not parse or points to
+/// another host fails with [`CrawlerError::Build`].
+pub(crate) fn resolve_entry_url(base_url: &Url, url: &str) -> CrawlerResult<Url> {
+    let resolved = base_url
+        .join(url.trim())
+        .map_err(|e| CrawlerError::Build(format!("Invalid entry URL `{url}`: {e}")))?;
+    if resolved.host_str() != base_url.host_str() {
+        return Err(CrawlerError::Build(format!(
+            "Entry URL {resolved} is outside of {base_url}"
+        )));
+    }
+    Ok(resolved)
+}
+
+/// Resolves configured category URLs against the store's `base_url`.
+///
+/// Relative paths such as `/collections/puer` are joined to `base_url`.
+/// Entries that do not parse or point to another host are logged and skipped,
+/// so a typo cannot send the crawler to an unrelated site.
+pub(crate) fn resolve_category_urls(base_url: &Url, urls: &[String]) -> Vec<String> {
+    urls.iter()
+        .filter_map(|url| match base_url.join(url.trim()) {
+            Ok(resolved) if resolved.host_str() == base_url.host_str() => {
+                Some(resolved.to_string())
+            }
+            Ok(resolved) => {
+                log::warn!("Skipping category URL {resolved} outside of {base_url}");
+                None
+            }
+            Err(e) => {
+                log::warn!("Skipping invalid category URL {url}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the absolute `url` of a listing with its `param` query parameter
+/// set to `page`, e.g. `?page=3`.
+///
+/// Other parameters keep their order and the fragment is dropped, so the
+/// result is a fetchable page URL.
+pub(crate) fn paginated_url(url: &Url, param: &str, page: usize) -> String {
+    let pairs = url
+        .query_pairs()
+        .filter(|(key, _)| key != param)
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    let mut page_url = url.clone();
+    page_url.set_fragment(None);
+    page_url.set_query(None);
+    page_url
+        .query_pairs_mut()
+        .extend_pairs(pairs)
+        .append_pair(param, &page.to_string());
+    page_url.to_string()
+}
+
+/// Issues a GET request and returns the body of a successful response.
+///
+/// The body is decoded with [`charset::decode_html`], so pages declared as
+/// e.g. `windows-1251` are returned as proper text. Non-success statuses become [`CrawlerError::Http`]; transport errors and
+/// unreadable bodies become [`CrawlerError::Network`].
+pub(crate) async fn fetch_text_result(
+    client: &reqwest::Client,
+    url: &str,
+) -> CrawlerResult<String> {
+    let network_error = |e: reqwest::Error| CrawlerError::Network {
+        url: url.to_string(),
+        message: e.to_string(),
+    };
+
+    let res = client.get(url).send().await.map_err(network_error)?;
+    if !res.status().is_success() {
+        return Err(CrawlerError::Http {
+            url: url.to_string(),
+            status: res.status(),
+        });
+    }
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = res.bytes().await.map_err(network_error)?;
+    Ok(charset::decode_html(&body, content_type.as_deref()))
+}
+
+/// Like [`fetch_text_result`], but logs failures and counts them in
+/// `counters` by category.
+pub(crate) async fn fetch_text(
+    client: &reqwest::Client,
+    url: &str,
+    counters: &CrawlCounters,
+) -> Option<String> {
+    fetch_text_counted(client, url, counters).await.ok()
+}
+
+/// Like [`fetch_text`], but returns the failure so callers can react to it,
+/// e.g. to a `404` of a known product page.
+pub(crate) async fn fetch_text_counted(
+    client: &reqwest::Client,
+    url: &str,
+    counters: &CrawlCounters,
+) -> CrawlerResult<String> {
+    fetch_text_result(client, url).await.inspect_err(|e| {
+        log::error!("{e}");
+        counters.record_failure(e);
+    })
+}
+
+/// Drops products whose URL, or whose non-empty SKU within the same crawler,
+/// was already seen, keeping the first occurrence.
+///
+/// Stores may list one SKU under several URLs, e.g. a canonical page and
+/// filtered views. Empty SKUs are never collapsed together.
+pub(crate) fn dedup_products(mut products: Vec<NewProduct>) -> Vec<NewProduct> {
+    let mut seen = SeenProducts::default();
+    products.retain(|p| seen.insert(p));
+    products
+}
+
+/// URLs and non-empty SKUs of the products kept so far, the keys
+/// [`dedup_products`] collapses on, for deduplicating products that arrive
+/// in several pieces.
+#[derive(Debug, Default)]
+pub(crate) struct SeenProducts {
+    urls: HashSet<Option<ProductUrl>>,
+    skus: HashSet<(i32, String)>,
+}
+
+impl SeenProducts {
+    /// Records `product` and returns `true` when neither its URL nor its
+    /// non-empty SKU within its crawler was seen before.
+    pub(crate) fn insert(&mut self, product: &NewProduct) -> bool {
+        if !self.urls.insert(product.url.clone()) {
+            return false;
+        }
+        let sku = product.sku.as_str().trim();
+        if sku.is_empty() {
+            return true;
+        }
+        let duplicate = !self
+            .skus
+            .insert((product.crawler_id.get(), sku.to_string()));
+        if duplicate {
+            log::debug!("Skipping duplicate sku {sku} at {:?}", product.url);
+        }
+        !duplicate
+    }
 }
 
 fn trim_to_option(value: Option<String>) -> Option<String> {
@@ -213,9 +724,336 @@ fn parse_amount_units(input: &str) -> (f64, String) {
     }
 }
 
-fn build_reqwest_client() -> CrawlerResult<reqwest::Client> {
+/// Credentials attached to every request of a crawler, e.g. to crawl a
+/// staging store behind HTTP Basic Auth or a session cookie.
+///
+/// The default sends no credentials and verifies TLS certificates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpCredentials {
+    /// User name and password sent as an `Authorization: Basic` header.
+    pub basic_auth: Option<(String, String)>,
+    /// Value of the `Cookie` header, e.g. `session=abc; locale=ru`.
+    pub cookie: Option<String>,
+    /// Accept any TLS certificate, e.g. a staging store's self-signed one.
+    /// This disables protection against impersonation of the store.
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// Builds the default headers carrying `credentials`.
+///
+/// The values are marked sensitive so they are not printed by `Debug`;
+/// `reqwest` also drops them on redirects to another host.
+fn credential_headers(credentials: &HttpCredentials) -> CrawlerResult<HeaderMap> {
+    let sensitive = |value: String| {
+        let mut value =
+            HeaderValue::from_str(&value).map_err(|e| CrawlerError::Build(e.to_string()))?;
+        value.set_sensitive(true);
+        Ok::<_, CrawlerError>(value)
+    };
+
+    let mut headers = HeaderMap::new();
+    if let Some((username, password)) = &credentials.basic_auth {
+        let token = BASE64_STANDARD.encode(format!("{username}:{password}"));
+        headers.insert(AUTHORIZATION, sensitive(format!("Basic {token}"))?);
+    }
+    if let Some(cookie) = &credentials.cookie {
+        headers.insert(COOKIE, sensitive(cookie.clone())?);
+    }
+    Ok(headers)
+}
+
+/// Builds the HTTP client shared by a crawler's requests.
+///
+/// The client advertises and transparently decodes `gzip`, `deflate` and
+/// `br` (brotli) response bodies, so page text is always plain HTML. Other
+/// encodings (e.g. `zstd`) are not requested. `credentials` are sent with
+/// every request; without them no `Authorization` or `Cookie` header is set.
+/// Certificates are verified unless `danger_accept_invalid_certs` is set.
+fn build_reqwest_client(credentials: &HttpCredentials) -> CrawlerResult<reqwest::Client> {
     reqwest::Client::builder()
         .user_agent(Alphanumeric.sample_string(&mut rand::rng(), 16))
+        .default_headers(credential_headers(credentials)?)
+        .danger_accept_invalid_certs(credentials.danger_accept_invalid_certs)
+        .gzip(true)
+        .deflate(true)
+        .brotli(true)
         .build()
         .map_err(|e| CrawlerError::Build(e.to_string()))
 }
+
+/// Enforces a minimum interval between consecutive requests of one crawler.
+///
+/// Callers wait for their turn after acquiring a concurrency permit, so
+/// requests are spaced by at least `delay` regardless of the permit count.
+pub(crate) struct RequestPacer {
+    delay: Duration,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl RequestPacer {
+    pub(crate) fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            last_request: Mutex::new(None),
+        }
+    }
+
+    /// Sleeps until `delay` has passed since the previous request.
+    pub(crate) async fn wait(&self) {
+        if self.delay.is_zero() {
+            return;
+        }
+
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.delay {
+                tokio::time::sleep(self.delay - elapsed).await;
+            }
+        }
+        *last_request = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_relative_category_hrefs_paginate_to_absolute_urls() {
+        let base_url = Url::parse("https://store.example/").unwrap();
+        let category_url = base_url
+            .join("//store.example/collections/oolong?sort=price&page=1#top")
+            .unwrap();
+
+        assert_eq!(
+            paginated_url(&category_url, "page", 3),
+            "https://store.example/collections/oolong?sort=price&page=3"
+        );
+        assert_eq!(
+            paginated_url(&base_url.join("/catalog/tea/").unwrap(), "PAGEN_1", 2),
+            "https://store.example/catalog/tea/?PAGEN_1=2"
+        );
+    }
+
+    #[test]
+    fn pagination_param_is_set_once_and_other_params_are_kept() {
+        let category_url =
+            Url::parse("https://store.example/catalog/?PAGEN_2=4&brand=a%20b&PAGEN_2=5&in_stock=1")
+                .unwrap();
+
+        for (param, expected) in [
+            (
+                "PAGEN_2",
+                "https://store.example/catalog/?brand=a+b&in_stock=1&PAGEN_2=7",
+            ),
+            (
+                "PAGEN_1",
+                "https://store.example/catalog/?PAGEN_2=4&brand=a+b&PAGEN_2=5&in_stock=1&PAGEN_1=7",
+            ),
+            (
+                "page",
+                "https://store.example/catalog/?PAGEN_2=4&brand=a+b&PAGEN_2=5&in_stock=1&page=7",
+            ),
+        ] {
+            assert_eq!(paginated_url(&category_url, param, 7), expected, "{param}");
+        }
+    }
+
+    #[test]
+    fn category_urls_are_resolved_on_the_store_host() {
+        let base_url = Url::parse("https://store.example/").unwrap();
+        let urls = [
+            "/collections/puer",
+            "https://store.example/collections/oolong?page=2",
+            "https://other.example/collections/puer",
+        ]
+        .map(str::to_string);
+
+        assert_eq!(
+            resolve_category_urls(&base_url, &urls),
+            vec![
+                "https://store.example/collections/puer".to_string(),
+                "https://store.example/collections/oolong?page=2".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_url_must_stay_on_the_store_host() {
+        let base_url = Url::parse("https://store.example/").unwrap();
+
+        assert_eq!(
+            resolve_entry_url(&base_url, " /catalog/ ")
+                .unwrap()
+                .as_str(),
+            "https://store.example/catalog/"
+        );
+        assert!(matches!(
+            resolve_entry_url(&base_url, "https://other.example/catalog/"),
+            Err(CrawlerError::Build(_))
+        ));
+        assert!(matches!(
+            resolve_entry_url(&base_url, "https://"),
+            Err(CrawlerError::Build(_))
+        ));
+    }
+
+    fn links(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|index| format!("https://store.example/p/{index:02}"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetch_failures_are_categorized() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let missing = format!("http://{}/missing", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+        // Nothing listens on a port whose listener was dropped.
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+
+        let client = build_reqwest_client(&HttpCredentials::default()).unwrap();
+        let counters = CrawlCounters::default();
+
+        assert!(matches!(
+            fetch_text_result(&client, &missing).await,
+            Err(CrawlerError::Http { status, .. }) if status == reqwest::StatusCode::NOT_FOUND
+        ));
+        assert!(fetch_text(&client, &down, &counters).await.is_none());
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.fetch_failures, 1);
+        assert_eq!(stats.network_failures, 1);
+        assert_eq!(stats.http_failures, 0);
+    }
+
+    fn product(crawler_id: i32, sku: &str, url: &str) -> NewProduct {
+        build_new_product(
+            crawler_id,
+            sku.to_string(),
+            "Tea".to_string(),
+            None,
+            None,
+            100.0,
+            None,
+            None,
+            url.to_string(),
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dedup_keeps_first_product_per_url_and_sku() {
+        let products = dedup_products(vec![
+            product(1, "A", "https://store.example/p/a"),
+            product(1, "A", "https://store.example/p/a?color=red"),
+            product(1, "B", "https://store.example/p/a"),
+            product(1, "B", "https://store.example/p/b"),
+            product(2, "A", "https://store.example/p/c"),
+        ]);
+
+        let kept = products
+            .iter()
+            .map(|p| (p.crawler_id.get(), p.sku.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![(1, "A"), (1, "B"), (2, "A")]);
+        assert_eq!(
+            products[1].url.as_ref().map(|url| url.as_str()),
+            Some("https://store.example/p/b")
+        );
+    }
+
+    #[test]
+    fn credentials_become_sensitive_default_headers() {
+        let credentials = HttpCredentials {
+            basic_auth: Some(("user".to_string(), "pass".to_string())),
+            cookie: Some("session=abc".to_string()),
+            ..HttpCredentials::default()
+        };
+
+        let headers = credential_headers(&credentials).unwrap();
+
+        assert_eq!(headers[AUTHORIZATION], "Basic dXNlcjpwYXNz");
+        assert!(headers[AUTHORIZATION].is_sensitive());
+        assert_eq!(headers[COOKIE], "session=abc");
+        assert!(
+            credential_headers(&HttpCredentials::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn limits_without_caps_keep_everything() {
+        let limits = CrawlLimits::default();
+
+        assert_eq!(limits.cap_pages(links(50), "store").len(), 50);
+        assert_eq!(
+            limits
+                .cap_products(links(50).into_iter().collect(), "store")
+                .len(),
+            50
+        );
+    }
+
+    #[test]
+    fn limits_truncate_to_a_stable_subset() {
+        let limits = CrawlLimits {
+            max_pages: Some(3),
+            max_products: Some(2),
+            max_categories: Some(1),
+            ..CrawlLimits::default()
+        };
+
+        let mut pages = links(10);
+        pages.reverse();
+        assert_eq!(limits.cap_pages(pages, "store"), links(3));
+        assert_eq!(
+            limits.cap_products(links(10).into_iter().collect(), "store"),
+            links(2)
+        );
+        assert_eq!(limits.cap_categories(links(4), "store"), links(1));
+    }
+
+    #[tokio::test]
+    async fn timed_out_category_discovery_keeps_the_categories_found_so_far() {
+        let limits = CrawlLimits {
+            category_timeout: Some(Duration::from_millis(10)),
+            ..CrawlLimits::default()
+        };
+
+        let found = Mutex::new(Vec::new());
+        let stalled = async {
+            found.lock().await.extend(links(2));
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            found.lock().await.extend(links(3));
+        };
+        assert_eq!(
+            limits.discover_categories(&found, stalled, "store").await,
+            links(2)
+        );
+
+        let found = Mutex::new(Vec::new());
+        let quick = async { found.lock().await.extend(links(3)) };
+        assert_eq!(
+            limits.discover_categories(&found, quick, "store").await,
+            links(3)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Extraction of product variants from schema.org `Offer` microdata.
+
+use std::collections::HashSet;
+
+use scraper::{ElementRef, Html, Selector};
+
+use crate::crawlers::CrawlerResult;
+use crate::crawlers::selectors::parse_selector;
+
+/// A purchasable variant of a product, e.g. one package size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferVariant {
+    pub sku: String,
+    pub price: f64,
+    /// Variant label such as `50 г`, used for amount and units.
+    pub label: Option<String>,
+}
+
+/// Reads the value of the first element matching `property` inside `scope`.
+///
+/// The `content` attribute wins over `href` and the element text, matching
+/// how `<meta>`, `<link>` and visible microdata elements carry values.
+fn property(scope: ElementRef, property: &Selector) -> Option<String> {
+    let element = scope.select(property).next()?;
+    let value = element
+        .value()
+        .attr("content")
+        .or_else(|| element.value().attr("href"))
+        .map(str::to_string)
+        .unwrap_or_else(|| element.text().collect::<String>());
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Reads product variants from `itemprop="offers"` items typed
+/// `schema.org/Offer`, with its selectors compiled once per crawler.
+#[derive(Debug, Clone)]
+pub struct OfferParser {
+    offer: Selector,
+    sku: Selector,
+    price: Selector,
+    name: Selector,
+}
+
+impl OfferParser {
+    /// Compiles the schema.org `Offer` microdata selectors.
+    pub fn new() -> CrawlerResult<Self> {
+        Ok(Self {
+            offer: parse_selector("[itemprop='offers'][itemtype$='/Offer']")?,
+            sku: parse_selector("[itemprop='sku']")?,
+            price: parse_selector("[itemprop='price']")?,
+            name: parse_selector("[itemprop='name']")?,
+        })
+    }
+
+    /// Reads the variants of a product page.
+    ///
+    /// Offers without a SKU or a parsable price are skipped and repeated SKUs
+    /// keep their first offer. A page with fewer than two distinct offers
+    /// describes a single product, so an empty list is returned and callers
+    /// fall back to their single-product selectors.
+    pub fn parse(&self, document: &Html) -> Vec<OfferVariant> {
+        let mut seen = HashSet::new();
+        let variants = document
+            .select(&self.offer)
+            .filter_map(|offer| {
+                let sku = property(offer, &self.sku)?;
+                let price = property(offer, &self.price)?
+                    .replace(',', ".")
+                    .replace(' ', "")
+                    .parse::<f64>()
+                    .ok()?;
+                Some(OfferVariant {
+                    sku,
+                    price,
+                    label: property(offer, &self.name),
+                })
+            })
+            .filter(|variant| seen.insert(variant.sku.clone()))
+            .collect::<Vec<_>>();
+
+        if variants.len() < 2 { vec![] } else { variants }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offers_page(offers: &str) -> Html {
+        Html::parse_document(&format!(
+            r#"<div itemscope itemtype="http://schema.org/Product">
+                <div itemprop="offers" itemscope itemtype="http://schema.org/AggregateOffer">
+                    {offers}
+                </div>
+            </div>"#
+        ))
+    }
+
+    #[test]
+    fn reads_distinct_offers() {
+        let document = offers_page(
+            r#"<div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <meta itemprop="sku" content="A-50">
+                <meta itemprop="price" content="450">
+                <meta itemprop="name" content="50 г">
+            </div>
+            <div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <span itemprop="sku">A-100</span>
+                <span itemprop="price">1 150,50</span>
+            </div>
+            <div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <meta itemprop="sku" content="A-50">
+                <meta itemprop="price" content="999">
+            </div>"#,
+        );
+
+        let variants = OfferParser::new().unwrap().parse(&document);
+
+        assert_eq!(
+            variants,
+            vec![
+                OfferVariant {
+                    sku: "A-50".to_string(),
+                    price: 450.0,
+                    label: Some("50 г".to_string()),
+                },
+                OfferVariant {
+                    sku: "A-100".to_string(),
+                    price: 1150.5,
+                    label: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn single_offer_is_not_a_variant_list() {
+        let document = offers_page(
+            r#"<div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <meta itemprop="sku" content="A-50">
+                <meta itemprop="price" content="450">
+            </div>
+            <div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <meta itemprop="sku" content="A-100">
+                <meta itemprop="price" content="по запросу">
+            </div>"#,
+        );
+
+        assert!(OfferParser::new().unwrap().parse(&document).is_empty());
+    }
+}
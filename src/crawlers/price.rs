@@ -0,0 +1,188 @@
+//! Parsing of scraped prices and the currency they are quoted in.
+
+use std::fmt;
+
+use scraper::{Html, Selector};
+
+/// Currency a scraped price is quoted in.
+///
+/// Stored products carry a bare price in rubles, so crawlers detect the
+/// currency to notice a store quoting another one instead of silently
+/// comparing its prices with ruble prices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Currency {
+    #[default]
+    Rub,
+    Usd,
+    Eur,
+}
+
+impl Currency {
+    /// ISO 4217 code, e.g. `RUB`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Currency::Rub => "RUB",
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Reduces a scraped price to a number `f64` can parse, e.g. `1 250,50 ₽`
+/// to `1250.50`.
+///
+/// Everything but digits, `.` and `,` is dropped, including spaces,
+/// currency symbols and the dot of `руб.` or `р.`. The last separator is
+/// the decimal one when at most two digits follow it; every other separator
+/// groups thousands, so `1.200,50` and `1,200` read as expected. Returns
+/// `None` when the text holds no digit.
+fn sanitize_price(text: &str) -> Option<String> {
+    let kept = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+        .collect::<String>();
+    let kept = kept.trim_matches(['.', ',']);
+    if kept.is_empty() {
+        return None;
+    }
+
+    let decimal = kept
+        .rfind(['.', ','])
+        .filter(|&separator| kept.len() - separator - 1 <= 2);
+    let number = kept
+        .char_indices()
+        .filter_map(|(index, c)| match c {
+            '.' | ',' if Some(index) == decimal => Some('.'),
+            '.' | ',' => None,
+            digit => Some(digit),
+        })
+        .collect();
+    Some(number)
+}
+
+/// Parses a scraped price such as `1 250,50 ₽`, `990 руб.` or `$12.99`.
+///
+/// The number is read by [`sanitize_price`]. The currency is read from `$`,
+/// `€` or their ISO codes anywhere in the text; `₽`, `руб`, `р.`, `RUB` or
+/// no symbol at all mean rubles. Returns `None` when the text holds no
+/// number.
+pub fn parse_price(text: &str) -> Option<(f64, Currency)> {
+    let lower = text.to_lowercase();
+    let currency = if lower.contains('$') || lower.contains("usd") {
+        Currency::Usd
+    } else if lower.contains('€') || lower.contains("eur") {
+        Currency::Eur
+    } else {
+        Currency::Rub
+    };
+
+    let price = sanitize_price(text)?.parse::<f64>().ok()?;
+    Some((price, currency))
+}
+
+/// Parses the price text of the product at `url`, defaulting to `0.0` like
+/// an unparsable price.
+///
+/// Returns `None` for a price in another currency than rubles, logged as a
+/// `warn`: products have no currency field, so the product is skipped
+/// rather than stored with a wrong ruble price.
+pub(crate) fn price_in_rubles(text: &str, url: &str) -> Option<f64> {
+    let (price, currency) = parse_price(text).unwrap_or_default();
+    if currency != Currency::Rub {
+        log::warn!("Price of {url} is in {currency}; skipping the product");
+        return None;
+    }
+    Some(price)
+}
+
+/// Reads the crossed-out regular price a discounted product page shows
+/// next to its sale price, from the first element `old_price` matches.
+///
+/// Returns `None` when the page shows no such price (the product is not
+/// discounted) or its text holds no positive price.
+pub(crate) fn regular_price(document: &Html, old_price: &Selector, url: &str) -> Option<f64> {
+    let text = document
+        .select(old_price)
+        .next()?
+        .text()
+        .collect::<String>();
+    price_in_rubles(&text, url).filter(|price| *price > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ruble_prices_use_spaces_and_decimal_commas() {
+        assert_eq!(parse_price("1 250,50 ₽"), Some((1250.5, Currency::Rub)));
+        assert_eq!(parse_price("990 руб."), Some((990.0, Currency::Rub)));
+        assert_eq!(
+            parse_price("3\u{00A0}700 р."),
+            Some((3700.0, Currency::Rub))
+        );
+        assert_eq!(parse_price("420"), Some((420.0, Currency::Rub)));
+    }
+
+    #[test]
+    fn ruble_markers_are_stripped() {
+        assert_eq!(parse_price("1 200 ₽"), Some((1200.0, Currency::Rub)));
+        assert_eq!(parse_price("450руб."), Some((450.0, Currency::Rub)));
+        assert_eq!(parse_price("99,90 р."), Some((99.9, Currency::Rub)));
+        assert_eq!(parse_price("1500 RUB"), Some((1500.0, Currency::Rub)));
+    }
+
+    #[test]
+    fn the_last_separator_is_decimal_only_before_cents() {
+        assert_eq!(sanitize_price("1.200,50 руб."), Some("1200.50".to_string()));
+        assert_eq!(sanitize_price("1,200"), Some("1200".to_string()));
+        assert_eq!(sanitize_price("12.5"), Some("12.5".to_string()));
+        assert_eq!(sanitize_price("руб."), None);
+    }
+
+    #[test]
+    fn only_ruble_prices_are_read_as_rubles() {
+        assert_eq!(price_in_rubles("1 250 ₽", "u"), Some(1250.0));
+        assert_eq!(price_in_rubles("", "u"), Some(0.0));
+        assert_eq!(price_in_rubles("$12.99", "u"), None);
+        assert_eq!(price_in_rubles("15 EUR", "u"), None);
+    }
+
+    #[test]
+    fn regular_price_is_read_only_from_discounted_pages() {
+        let old_price = crate::crawlers::selectors::parse_selector("span.price-old").unwrap();
+        let discounted = Html::parse_document(
+            r#"<span class="price-old">1 500 ₽</span><span class="price">1 200 ₽</span>"#,
+        );
+        let regular = Html::parse_document(r#"<span class="price">1 200 ₽</span>"#);
+        let blank = Html::parse_document(r#"<span class="price-old"> </span>"#);
+
+        assert_eq!(regular_price(&discounted, &old_price, "u"), Some(1500.0));
+        assert_eq!(regular_price(&regular, &old_price, "u"), None);
+        assert_eq!(regular_price(&blank, &old_price, "u"), None);
+    }
+
+    #[test]
+    fn dollar_and_euro_prices_are_detected() {
+        assert_eq!(parse_price("$12.99"), Some((12.99, Currency::Usd)));
+        assert_eq!(parse_price("15,00 €"), Some((15.0, Currency::Eur)));
+        assert_eq!(parse_price("20 EUR"), Some((20.0, Currency::Eur)));
+        assert_eq!(Currency::Usd.to_string(), "USD");
+    }
+
+    #[test]
+    fn text_without_a_number_is_not_a_price() {
+        assert_eq!(parse_price("по запросу"), None);
+        assert_eq!(parse_price(""), None);
+        assert_eq!(
+            price_in_rubles("нет в наличии", "https://store.example/p/1"),
+            0.0
+        );
+    }
+}
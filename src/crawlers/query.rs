@@ -0,0 +1,107 @@
+//! Query parameter stripping for discovered product URLs.
+
+use url::Url;
+
+/// Wildcard entry in the strip list that removes every parameter not on the
+/// keep list.
+pub const STRIP_ALL: &str = "*";
+
+/// Removes tracking query parameters from product URLs so that the same page
+/// reached through different links is fetched and stored once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryFilter {
+    strip: Vec<String>,
+    keep: Vec<String>,
+}
+
+impl QueryFilter {
+    /// Creates a filter removing the `strip` parameters (or all of them when
+    /// `strip` contains [`STRIP_ALL`]) except those listed in `keep`.
+    pub fn new(strip: Vec<String>, keep: Vec<String>) -> Self {
+        Self { strip, keep }
+    }
+
+    /// Returns `true` when the parameter `name` must be removed.
+    fn strips(&self, name: &str) -> bool {
+        !self.keep.iter().any(|kept| kept == name)
+            && self
+                .strip
+                .iter()
+                .any(|stripped| stripped == STRIP_ALL || stripped == name)
+    }
+
+    /// Returns `link` without the stripped query parameters.
+    ///
+    /// Links that cannot be parsed or have nothing to strip are returned
+    /// unchanged, keeping the original parameter encoding.
+    pub fn apply(&self, link: &str) -> String {
+        if self.strip.is_empty() {
+            return link.to_string();
+        }
+        let Ok(mut url) = Url::parse(link) else {
+            return link.to_string();
+        };
+        if !url.query_pairs().any(|(name, _)| self.strips(&name)) {
+            return link.to_string();
+        }
+
+        let kept: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| !self.strips(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        url.set_query(None);
+        if !kept.is_empty() {
+            url.query_pairs_mut().extend_pairs(kept);
+        }
+        url.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn tracking_variants_collapse_to_one_url() {
+        let filter = QueryFilter::new(strings(&["utm_source", "utm_medium", "from"]), vec![]);
+
+        let links = [
+            "https://store.example/tea/puer/?utm_source=vk&utm_medium=post",
+            "https://store.example/tea/puer/?from=main",
+            "https://store.example/tea/puer/",
+        ];
+
+        for link in links {
+            assert_eq!(filter.apply(link), "https://store.example/tea/puer/");
+        }
+    }
+
+    #[test]
+    fn allowlisted_params_are_preserved() {
+        let filter = QueryFilter::new(strings(&[STRIP_ALL]), strings(&["variant"]));
+
+        assert_eq!(
+            filter.apply("https://store.example/tea/puer/?utm_source=vk&variant=42&from=main"),
+            "https://store.example/tea/puer/?variant=42"
+        );
+        assert_eq!(
+            filter.apply("https://store.example/tea/puer/?variant=42#SKU-1"),
+            "https://store.example/tea/puer/?variant=42#SKU-1"
+        );
+    }
+
+    #[test]
+    fn empty_filter_keeps_links_unchanged() {
+        let filter = QueryFilter::default();
+
+        assert_eq!(
+            filter.apply("https://store.example/tea/?utm_source=vk"),
+            "https://store.example/tea/?utm_source=vk"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Minimal `robots.txt` support shared by the site crawlers.
+
+use url::Url;
+
+/// Product token matched against `User-agent` lines.
+///
+/// The HTTP `User-Agent` header is randomized per client, so robots groups
+/// are selected by this token and fall back to the `*` group.
+pub const ROBOTS_USER_AGENT: &str = "pushkind-crawlers";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// Allow/disallow rules that apply to this crawler.
+///
+/// The default value allows every path, which is used when `robots.txt` is
+/// missing or cannot be fetched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RobotsRules {
+    rules: Vec<Rule>,
+}
+
+impl RobotsRules {
+    /// Fetches and parses `/robots.txt` relative to `base_url`.
+    ///
+    /// Any failure results in allow-all rules so crawling proceeds as before.
+    pub async fn fetch(client: &reqwest::Client, base_url: &Url) -> Self {
+        let Ok(robots_url) = base_url.join("/robots.txt") else {
+            return Self::default();
+        };
+
+        let response = match client.get(robots_url.as_str()).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("Failed to fetch {robots_url}, crawling without restrictions: {e}");
+                return Self::default();
+            }
+        };
+
+        if !response.status().is_success() {
+            log::info!(
+                "No robots.txt at {robots_url} ({}), crawling without restrictions",
+                response.status()
+            );
+            return Self::default();
+        }
+
+        match response.text().await {
+            Ok(body) => Self::parse(&body, ROBOTS_USER_AGENT),
+            Err(e) => {
+                log::warn!("Failed to read {robots_url}, crawling without restrictions: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Parses a `robots.txt` body, keeping the group for `user_agent` when
+    /// present and the `*` group otherwise.
+    pub fn parse(body: &str, user_agent: &str) -> Self {
+        let user_agent = user_agent.to_lowercase();
+        let mut specific: Vec<Rule> = Vec::new();
+        let mut wildcard: Vec<Rule> = Vec::new();
+        let mut has_specific = false;
+
+        // Agents named by the current group and whether its rules started.
+        let mut group_agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let field = field.trim().to_lowercase();
+            let value = value.trim();
+
+            match field.as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        group_agents.clear();
+                        in_rules = false;
+                    }
+                    let agent = value.to_lowercase();
+                    if agent.is_empty() {
+                        continue;
+                    }
+                    if agent != "*" && user_agent.contains(&agent) {
+                        has_specific = true;
+                    }
+                    group_agents.push(agent);
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // An empty `Disallow:` allows everything and adds no rule.
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rule = Rule {
+                        allow: field == "allow",
+                        pattern: value.to_string(),
+                    };
+                    if group_agents
+                        .iter()
+                        .any(|agent| agent != "*" && user_agent.contains(agent.as_str()))
+                    {
+                        specific.push(rule.clone());
+                    }
+                    if group_agents.iter().any(|agent| agent == "*") {
+                        wildcard.push(rule);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            rules: if has_specific { specific } else { wildcard },
+        }
+    }
+
+    /// Returns `true` when the path (and query) of `url` may be crawled.
+    ///
+    /// The longest matching pattern wins; `Allow` wins ties.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        let path = match Url::parse(url) {
+            Ok(url) => match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            },
+            Err(_) => url.to_string(),
+        };
+
+        let mut best: Option<&Rule> = None;
+        for rule in &self.rules {
+            if !pattern_matches(&rule.pattern, &path) {
+                continue;
+            }
+            best = match best {
+                Some(current)
+                    if current.pattern.len() > rule.pattern.len()
+                        || (current.pattern.len() == rule.pattern.len() && current.allow) =>
+                {
+                    Some(current)
+                }
+                _ => Some(rule),
+            };
+        }
+
+        best.is_none_or(|rule| rule.allow)
+    }
+
+    /// Keeps only allowed links, logging each skipped one.
+    pub fn filter_allowed(&self, links: Vec<String>) -> Vec<String> {
+        links
+            .into_iter()
+            .filter(|link| {
+                let allowed = self.is_allowed(link);
+                if !allowed {
+                    log::warn!("Skipping {link}: disallowed by robots.txt");
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+/// Applies `rules` to `links` when `robots.txt` was loaded for the crawl.
+///
+/// Without loaded rules (e.g. targeted product refreshes) links pass through.
+pub fn filter_links(rules: Option<&RobotsRules>, links: Vec<String>) -> Vec<String> {
+    match rules {
+        Some(rules) => rules.filter_allowed(links),
+        None => links,
+    }
+}
+
+/// Matches a robots pattern supporting `*` wildcards and a trailing `$`.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(stripped) => (stripped, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (index, part) in parts.iter().enumerate() {
+        let is_last = index == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "\
+User-agent: *
+Disallow: /cart
+Disallow: /catalog/
+Allow: /catalog/tea/
+Disallow: /*?sort=
+
+User-agent: Yandex
+Disallow: /
+";
+
+    #[test]
+    fn wildcard_group_applies_when_agent_is_not_named() {
+        let rules = RobotsRules::parse(BODY, ROBOTS_USER_AGENT);
+
+        assert!(!rules.is_allowed("https://store.example/cart/"));
+        assert!(!rules.is_allowed("https://store.example/catalog/green/"));
+        assert!(rules.is_allowed("https://store.example/catalog/tea/puer/"));
+        assert!(!rules.is_allowed("https://store.example/about/?sort=price"));
+        assert!(rules.is_allowed("https://store.example/about/"));
+    }
+
+    #[test]
+    fn named_group_overrides_wildcard() {
+        let rules = RobotsRules::parse(BODY, "yandex");
+
+        assert!(!rules.is_allowed("https://store.example/about/"));
+    }
+
+    #[test]
+    fn empty_or_garbage_body_allows_everything() {
+        assert!(RobotsRules::parse("", ROBOTS_USER_AGENT).is_allowed("https://a.example/x"));
+        assert!(
+            RobotsRules::parse("<html>not robots</html>", ROBOTS_USER_AGENT)
+                .is_allowed("https://a.example/x")
+        );
+    }
+
+    #[test]
+    fn end_anchor_matches_only_exact_suffix() {
+        let rules = RobotsRules::parse("User-agent: *\nDisallow: /*.pdf$\n", ROBOTS_USER_AGENT);
+
+        assert!(!rules.is_allowed("https://a.example/files/price.pdf"));
+        assert!(rules.is_allowed("https://a.example/files/price.pdf?v=2"));
+    }
+
+    #[test]
+    fn filter_allowed_drops_disallowed_links() {
+        let rules = RobotsRules::parse(BODY, ROBOTS_USER_AGENT);
+        let links = vec![
+            "https://store.example/catalog/tea/puer/".to_string(),
+            "https://store.example/cart/".to_string(),
+        ];
+
+        assert_eq!(
+            rules.filter_allowed(links),
+            vec!["https://store.example/catalog/tea/puer/".to_string()]
+        );
+    }
+}
@@ -1,21 +1,34 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use html_escape::decode_html_entities;
 use pushkind_dantes::domain::product::NewProduct;
-use scraper::{Html, Selector};
+use scraper::Html;
 use serde::Deserialize;
-use tokio::sync::Semaphore;
+use tokio::sync::{Mutex, OnceCell, Semaphore};
+use tokio::time::Duration;
 use url::Url;
 
+use crate::crawlers::availability::is_available;
+use crate::crawlers::debug_html::HtmlDumper;
+use crate::crawlers::json_ld::JsonLdParser;
+use crate::crawlers::price::price_in_rubles;
+use crate::crawlers::price::regular_price;
+use crate::crawlers::query::QueryFilter;
+use crate::crawlers::robots::{RobotsRules, filter_links};
+use crate::crawlers::selectors::SelectorSet;
 use crate::crawlers::{
-    CrawlerError, CrawlerResult, WebstoreCrawler, build_new_product, build_reqwest_client,
-    parse_amount_units,
+    CrawlCounters, CrawlLimits, CrawlStats, CrawlerError, CrawlerResult, HttpCredentials,
+    ProductPage, RequestPacer, WebstoreCrawler, build_new_product, build_reqwest_client,
+    fetch_text_counted, paginated_url, parse_amount_units, resolve_category_urls,
+    resolve_entry_url,
 };
 
 #[derive(Debug, Deserialize, Clone)]
 struct Variant {
+    #[serde(default)]
     sku: String,
     price: String,
     title: String,
@@ -26,6 +39,31 @@ struct ProductJson {
     variants: Vec<Variant>,
 }
 
+/// Prepares the variants of one product page for conversion.
+///
+/// Variants without a SKU are skipped. A SKU repeated within the page gets a
+/// `-2`, `-3`, ... suffix, so each variant keeps its own SKU and `#sku` URL
+/// and survives [`dedup_products`](crate::crawlers::dedup_products).
+fn distinct_variant_skus(variants: Vec<Variant>, url: &str) -> Vec<Variant> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    variants
+        .into_iter()
+        .filter_map(|mut variant| {
+            variant.sku = variant.sku.trim().to_string();
+            if variant.sku.is_empty() {
+                log::warn!("Skipping variant {:?} without SKU at {url}", variant.title);
+                return None;
+            }
+            let count = seen.entry(variant.sku.clone()).or_default();
+            *count += 1;
+            if *count > 1 {
+                variant.sku = format!("{}-{count}", variant.sku);
+            }
+            Some(variant)
+        })
+        .collect()
+}
+
 /// Converts a [`Variant`] produced by the store into a [`NewProduct`].
 fn variant_to_product(
     v: Variant,
@@ -52,13 +90,45 @@ fn variant_to_product(
     )
 }
 
+/// Query parameter holding the number of a category listing page.
+const PAGINATION_PARAM: &str = "page";
+
+/// Names and default CSS of the selectors this crawler reads pages with;
+/// see [`SelectorSet`].
+pub const DEFAULT_SELECTORS: &[(&str, &str)] = &[
+    ("category_link", "a.header__collections-link"),
+    ("pagination", "div.pagination-items"),
+    ("pagination_link", "a.pagination-link"),
+    ("product_link", "div.product-preview__title > a"),
+    ("name", "h1.product__title"),
+    ("description", "div.product__short-description"),
+    ("category", "ul.breadcrumb li a"),
+    ("product_form", "form.product"),
+    ("sku", "span.sku-value"),
+    ("amount_units", "button.option-value"),
+    ("price", "span.product__price-cur"),
+    ("availability", "div.product__available"),
+    ("old_price", "span.product__price-old"),
+];
+
 /// Crawler for `shop.rusteaco.ru` which limits concurrent HTTP requests
 /// using a [`Semaphore`].
 pub struct WebstoreCrawlerRusteaco {
     crawler_id: i32,
     base_url: Url,
+    entry_url: Url,
     client: reqwest::Client,
     semaphore: Arc<Semaphore>,
+    robots: OnceCell<RobotsRules>,
+    pacer: RequestPacer,
+    counters: CrawlCounters,
+    query_filter: QueryFilter,
+    limits: CrawlLimits,
+    category_urls: Vec<String>,
+    selectors: SelectorSet,
+    json_ld: JsonLdParser,
+    html_dumper: HtmlDumper,
+    skip_unavailable: bool,
 }
 
 impl WebstoreCrawlerRusteaco {
@@ -67,71 +137,177 @@ impl WebstoreCrawlerRusteaco {
     /// `concurrency` controls how many HTTP requests may be in flight at the
     /// same time. The `crawler_id` is attached to each produced product.
     pub fn new(concurrency: usize, crawler_id: i32) -> CrawlerResult<Self> {
+        let base_url = Url::parse("https://shop.rusteaco.ru/")
+            .map_err(|e| CrawlerError::Build(e.to_string()))?;
         Ok(Self {
             crawler_id,
-            base_url: Url::parse("https://shop.rusteaco.ru/")
-                .map_err(|e| CrawlerError::Build(e.to_string()))?,
-            client: build_reqwest_client()?,
+            entry_url: base_url.clone(),
+            base_url,
+            client: build_reqwest_client(&HttpCredentials::default())?,
             semaphore: Arc::new(Semaphore::new(concurrency)),
+            robots: OnceCell::new(),
+            pacer: RequestPacer::new(Duration::ZERO),
+            counters: CrawlCounters::default(),
+            query_filter: QueryFilter::default(),
+            limits: CrawlLimits::default(),
+            category_urls: Vec::new(),
+            selectors: SelectorSet::new(DEFAULT_SELECTORS, &HashMap::new())?,
+            json_ld: JsonLdParser::new()?,
+            html_dumper: HtmlDumper::default(),
+            skip_unavailable: false,
         })
     }
 
+    /// Sets the minimum delay between consecutive requests to the store.
+    ///
+    /// Defaults to zero, i.e. requests are only limited by `concurrency`.
+    pub fn with_request_delay(mut self, delay: Duration) -> Self {
+        self.pacer = RequestPacer::new(delay);
+        self
+    }
+
+    /// Sets the query parameters stripped from product links before they are
+    /// deduplicated, fetched and stored.
+    pub fn with_query_filter(mut self, query_filter: QueryFilter) -> Self {
+        self.query_filter = query_filter;
+        self
+    }
+
+    /// Sets caps on the listing pages and product pages of a full crawl.
+    pub fn with_limits(mut self, limits: CrawlLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Restricts full crawls to these category pages instead of the
+    /// categories discovered on the landing page; empty crawls all of them.
+    ///
+    /// Paths are resolved against the store URL.
+    pub fn with_category_urls(mut self, category_urls: Vec<String>) -> Self {
+        self.category_urls = category_urls;
+        self
+    }
+
+    /// Starts category discovery from `entry_url`, e.g. a catalog page that
+    /// lists categories better than the landing page; `None` keeps the store
+    /// URL.
+    ///
+    /// Paths are resolved against the store URL; an invalid URL or one on
+    /// another host fails with [`CrawlerError::Build`].
+    pub fn with_entry_url(mut self, entry_url: Option<&str>) -> CrawlerResult<Self> {
+        if let Some(entry_url) = entry_url {
+            self.entry_url = resolve_entry_url(&self.base_url, entry_url)?;
+        }
+        Ok(self)
+    }
+
+    /// Drops products whose page marks them as out of stock (the
+    /// `availability` selector) instead of returning them; off by default.
+    pub fn with_skip_unavailable(mut self, skip_unavailable: bool) -> Self {
+        self.skip_unavailable = skip_unavailable;
+        self
+    }
+
+    /// Writes pages on which no product links or products were found into
+    /// `dir` (see [`HtmlDumper`]); `None`, the default, writes nothing.
+    pub fn with_debug_html_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.html_dumper = HtmlDumper::new(dir);
+        self
+    }
+
+    /// Sends `credentials` with every request to the store.
+    pub fn with_credentials(mut self, credentials: &HttpCredentials) -> CrawlerResult<Self> {
+        self.client = build_reqwest_client(credentials)?;
+        Ok(self)
+    }
+
+    /// Replaces the named selectors of [`DEFAULT_SELECTORS`] with `overrides`,
+    /// failing on unknown names and invalid CSS.
+    pub fn with_selectors(mut self, overrides: &HashMap<String, String>) -> CrawlerResult<Self> {
+        self.selectors = SelectorSet::new(DEFAULT_SELECTORS, overrides)?;
+        Ok(self)
+    }
+
     /// Fetches a URL and parses it into [`Html`].
     ///
     /// A permit from the internal [`Semaphore`] is acquired before issuing
-    /// the request, enforcing the configured concurrency limit.
+    /// the request, enforcing the configured concurrency limit, and the
+    /// configured request delay is observed before sending.
     async fn fetch_html(&self, url: &str) -> Option<Html> {
-        let _permit = self.semaphore.acquire().await.ok()?;
-        let res = self.client.get(url).send().await.ok()?;
-        if !res.status().is_success() {
-            log::error!("Failed to get URL {}: {}", url, res.status());
-            return None;
-        }
-        let text = res.text().await.ok()?;
-        Some(Html::parse_document(&text))
+        self.fetch_html_result(url).await.ok()
+    }
+
+    /// Like [`Self::fetch_html`], but returns the failure so a missing page
+    /// can be told apart from other errors.
+    async fn fetch_html_result(&self, url: &str) -> CrawlerResult<Html> {
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .map_err(|e| CrawlerError::Network {
+                url: url.to_string(),
+                message: e.to_string(),
+            })?;
+        self.pacer.wait().await;
+        let text = fetch_text_counted(&self.client, url, &self.counters).await?;
+        Ok(Html::parse_document(&text))
     }
 
-    /// Retrieves all category links from the store's landing page.
-    async fn get_category_links(&self) -> Vec<String> {
-        let document = match self.fetch_html(self.base_url.as_str()).await {
+    /// Adds all category links of the entry page, the store's landing page
+    /// unless configured otherwise, to `found`.
+    async fn get_category_links(&self, found: &Mutex<Vec<String>>) {
+        let document = match self.fetch_html(self.entry_url.as_str()).await {
             Some(doc) => doc,
             None => {
-                log::error!("Failed to parse HTML {}", self.base_url);
-                return vec![];
+                log::error!("Failed to parse HTML {}", self.entry_url);
+                return;
             }
         };
 
-        let selector = Selector::parse("a.header__collections-link").unwrap();
+        let selector = self.selectors.get("category_link");
 
-        document
-            .select(&selector)
+        let links = document
+            .select(selector)
             .filter_map(|link| {
                 let href = link.value().attr("href")?;
-                Some(self.base_url.join(href).ok()?.to_string())
+                Some(self.entry_url.join(href).ok()?.to_string())
             })
-            .collect()
+            .collect();
+        found
+            .lock()
+            .await
+            .extend(filter_links(self.robots.get(), links));
     }
 
     /// For a given category URL, discovers all pagination links, returning
     /// the original URL and any additional pages.
     async fn get_page_links(&self, url: &str) -> Vec<String> {
-        let mut result = vec![url.to_string()];
-        let document = match self.fetch_html(url).await {
+        // Category hrefs may be relative or protocol-relative; resolving them
+        // once keeps the fetched URL and every page URL absolute.
+        let category_url = match self.base_url.join(url) {
+            Ok(category_url) => category_url,
+            Err(e) => {
+                log::error!("Invalid category URL {url}: {e}");
+                return vec![];
+            }
+        };
+        let mut result = vec![category_url.to_string()];
+        let document = match self.fetch_html(category_url.as_str()).await {
             Some(doc) => doc,
             None => {
-                log::error!("Failed to parse HTML {url}");
+                log::error!("Failed to parse HTML {category_url}");
                 return vec![];
             }
         };
 
-        let selector = Selector::parse("div.pagination-items").unwrap();
-        let pagination = match document.select(&selector).next() {
+        let selector = self.selectors.get("pagination");
+        let pagination = match document.select(selector).next() {
             Some(p) => p,
             None => return result,
         };
 
-        let selector = Selector::parse("a.pagination-link").unwrap();
-        let page_links = pagination.select(&selector).collect::<Vec<_>>();
+        let selector = self.selectors.get("pagination_link");
+        let page_links = pagination.select(selector).collect::<Vec<_>>();
         if page_links.is_empty() {
             return result;
         }
@@ -140,28 +316,11 @@ impl WebstoreCrawlerRusteaco {
             .last()
             .map(|e| e.text().collect::<String>().trim().to_string())
             && let Ok(last_page_number) = last_page_text.parse::<usize>()
-            && let Ok(base_url) = self.base_url.join(url)
         {
-            for i in 2..=last_page_number {
-                // Clone the URL and filter out the old `page` parameter
-                let mut page_url = base_url.clone();
-                let mut pairs: Vec<(String, String)> = page_url
-                    .query_pairs()
-                    .filter(|(k, _)| k != "page")
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect();
-
-                // Insert the new page value
-                pairs.push(("page".to_string(), i.to_string()));
-
-                // Clear existing query and re-apply
-                page_url.set_query(None);
-                page_url
-                    .query_pairs_mut()
-                    .extend_pairs(pairs.iter().map(|(k, v)| (&**k, &**v)));
-
-                result.push(page_url.to_string());
-            }
+            result.extend(
+                (2..=last_page_number)
+                    .map(|page| paginated_url(&category_url, PAGINATION_PARAM, page)),
+            );
         }
 
         result
@@ -177,95 +336,83 @@ impl WebstoreCrawlerRusteaco {
             }
         };
 
-        let selector = Selector::parse("div.product-preview__title > a").unwrap();
-        document
-            .select(&selector)
+        let selector = self.selectors.get("product_link");
+        let links = document
+            .select(selector)
             .filter_map(|link| {
                 let href = link.value().attr("href")?;
                 Some(self.base_url.join(href).ok()?.to_string())
             })
-            .collect()
+            .collect::<Vec<_>>();
+        if links.is_empty() {
+            self.html_dumper.dump("product links", url, &document);
+        }
+        filter_links(self.robots.get(), links)
     }
-}
 
-#[async_trait]
-impl WebstoreCrawler for WebstoreCrawlerRusteaco {
-    /// Crawls the entire web store and returns all discovered products.
-    ///
-    /// Category pages, pagination, product links and product details are
-    /// fetched concurrently with `join_all`, while [`fetch_html`] ensures the
-    /// number of simultaneous HTTP requests never exceeds the configured
-    /// limit.
-    async fn get_products(&self) -> Vec<NewProduct> {
-        let categories = self.get_category_links().await;
+    /// Regular price of a discounted product page (the `old_price`
+    /// selector); the products carry the sale price.
+    fn old_price(&self, document: &Html, url: &str) -> Option<f64> {
+        regular_price(document, self.selectors.get("old_price"), url)
+    }
 
-        let mut tasks = vec![];
-        for category in categories.iter() {
-            tasks.push(async { self.get_page_links(category).await });
+    /// Extracts the products of a fetched product page, dumping the page
+    /// when it yields none.
+    ///
+    /// An out-of-stock page is counted, and yields nothing when unavailable
+    /// products are skipped. The regular price of a discounted page is only
+    /// logged, as products have no field for it yet.
+    fn read_product_page(&self, document: &Html, url: &str) -> Vec<NewProduct> {
+        if !is_available(document, self.selectors.get("availability")) {
+            CrawlCounters::add(&self.counters.products_unavailable, 1);
+            if self.skip_unavailable {
+                log::info!("Skipping out-of-stock product {url}");
+                return vec![];
+            }
+            log::debug!("Product {url} is out of stock");
         }
-        let page_links = futures::future::join_all(tasks).await;
-
-        let mut tasks = vec![];
-        for page_link in page_links.iter().flatten() {
-            tasks.push(async { self.get_product_links(page_link).await });
+        let products = self.parse_product(document, url);
+        if let Some(old_price) = self.old_price(document, url) {
+            log::debug!("Product {url} is discounted from a regular price of {old_price}");
         }
-        let product_links = futures::future::join_all(tasks).await;
-
-        // Deduplicate product links to avoid fetching the same page multiple times.
-        let unique_links: HashSet<String> = product_links.into_iter().flatten().collect();
-
-        let mut tasks = vec![];
-        for link in &unique_links {
-            tasks.push(async { self.get_product(link).await });
+        if products.is_empty() {
+            self.html_dumper.dump("products", url, document);
         }
-        let products = futures::future::join_all(tasks).await;
-
-        // Flatten and ensure uniqueness by product URL in the final result.
-        let mut products: Vec<NewProduct> = products.into_iter().flatten().collect();
-        let mut seen_urls = HashSet::new();
-        products.retain(|p| seen_urls.insert(p.url.clone()));
         products
     }
 
-    /// Fetches product information from a single product page.
+    /// Extracts products from a product page.
     ///
-    /// A page may describe multiple variants; each variant is converted into
-    /// its own [`NewProduct`].
-    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
-        let document = match self.fetch_html(url).await {
-            Some(doc) => doc,
-            None => {
-                log::error!("Failed to parse HTML {url}");
-                return vec![];
-            }
-        };
-
+    /// Variants from the `data-product-json` attribute produce one product each.
+    /// When the attribute is missing or cannot be parsed, a single product is
+    /// built from the page markup instead.
+    fn parse_product(&self, document: &Html, url: &str) -> Vec<NewProduct> {
         // Name
-        let name_selector = Selector::parse("h1.product__title").unwrap();
+        let name_selector = self.selectors.get("name");
         let name = document
-            .select(&name_selector)
+            .select(name_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Description
-        let desc_selector = Selector::parse("div.product__short-description").unwrap();
+        let desc_selector = self.selectors.get("description");
         let description = document
-            .select(&desc_selector)
+            .select(desc_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Category from breadcrumbs
-        let category_selector = Selector::parse("ul.breadcrumb li a").unwrap();
+        let category_selector = self.selectors.get("category");
         let category = document
-            .select(&category_selector)
+            .select(category_selector)
             .map(|el| el.text().collect::<String>().trim().to_string())
             .collect::<Vec<_>>()
             .join(" / ");
 
-        let selector = Selector::parse("form.product").unwrap();
-        let Some(product_form) = document.select(&selector).next() else {
+        let selector = self.selectors.get("product_form");
+        let Some(product_form) = document.select(selector).next() else {
             log::error!("Failed to find form.product {url}");
             return vec![];
         };
@@ -274,67 +421,166 @@ impl WebstoreCrawler for WebstoreCrawlerRusteaco {
             // Convert HTML-encoded string to valid JSON
             let json_str = decode_html_entities(json_raw).to_string();
             // Now parse it
-            let parsed: ProductJson = match serde_json::from_str(&json_str) {
-                Ok(p) => p,
+            match serde_json::from_str::<ProductJson>(&json_str) {
+                Ok(parsed) => {
+                    return distinct_variant_skus(parsed.variants, url)
+                        .into_iter()
+                        .filter_map(|v| {
+                            variant_to_product(
+                                v,
+                                &name,
+                                &category,
+                                &description,
+                                url,
+                                self.crawler_id,
+                            )
+                        })
+                        .collect();
+                }
                 Err(e) => {
-                    log::error!("Failed to parse product JSON {url}: {e}");
-                    return vec![];
+                    log::warn!("Malformed product JSON {url}, falling back to page markup: {e}");
                 }
-            };
-
-            parsed
-                .variants
-                .into_iter()
-                .filter_map(|v| {
-                    variant_to_product(v, &name, &category, &description, url, self.crawler_id)
-                })
-                .collect()
+            }
+        }
+
+        // SKU
+        let sku_selector = self.selectors.get("sku");
+        let sku = document
+            .select(sku_selector)
+            .next()
+            .map(|el| el.text().collect::<String>().trim().to_string())
+            .unwrap_or_default();
+
+        // Amount and units are a string like "150 г"
+        let amount_units_selector = self.selectors.get("amount_units");
+        let amount_units = document
+            .select(amount_units_selector)
+            .next()
+            .map(|el| el.text().collect::<String>().trim().to_string())
+            .unwrap_or_default();
+        let (amount, units) = parse_amount_units(&amount_units);
+
+        // Price
+        let price_selector = self.selectors.get("price");
+        let price = document
+            .select(price_selector)
+            .next()
+            .map(|el| el.text().collect::<String>().trim().to_string())
+            .unwrap_or_default();
+
+        let Some(price) = price_in_rubles(&price, url) else {
+            return vec![];
+        };
+        // JSON-LD fills in what the page selectors missed.
+        let (name, sku, price, images) =
+            self.json_ld
+                .fill_missing(document, url, name, sku, price, vec![]);
+        build_new_product(
+            self.crawler_id,
+            sku,
+            name,
+            Some(category),
+            Some(units),
+            price,
+            Some(amount),
+            Some(description),
+            url.to_string(),
+            images,
+        )
+        .into_iter()
+        .collect()
+    }
+}
+
+#[async_trait]
+impl WebstoreCrawler for WebstoreCrawlerRusteaco {
+    /// Walks categories and pagination and returns the unique product links.
+    ///
+    /// Category pages, pagination and listing pages are fetched concurrently
+    /// with `join_all`, while [`fetch_html`] ensures the number of
+    /// simultaneous HTTP requests never exceeds the configured limit.
+    async fn get_product_urls(&self) -> Vec<String> {
+        self.robots
+            .get_or_init(|| RobotsRules::fetch(&self.client, &self.base_url))
+            .await;
+
+        let categories = if self.category_urls.is_empty() {
+            let found = Mutex::new(Vec::new());
+            self.limits
+                .discover_categories(
+                    &found,
+                    self.get_category_links(&found),
+                    self.base_url.as_str(),
+                )
+                .await
         } else {
-            // SKU
-            let sku_selector = Selector::parse("span.sku-value").unwrap();
-            let sku = document
-                .select(&sku_selector)
-                .next()
-                .map(|el| el.text().collect::<String>().trim().to_string())
-                .unwrap_or_default();
-
-            // Amount and units are a string like "150 г"
-            let amount_units_selector = Selector::parse("button.option-value").unwrap();
-            let amount_units = document
-                .select(&amount_units_selector)
-                .next()
-                .map(|el| el.text().collect::<String>().trim().to_string())
-                .unwrap_or_default();
-            let (amount, units) = parse_amount_units(&amount_units);
-
-            // Price
-            let price_selector = Selector::parse("span.product__price-cur").unwrap();
-            let price = document
-                .select(&price_selector)
-                .next()
-                .map(|el| el.text().collect::<String>().trim().to_string())
-                .unwrap_or_default();
-
-            let price = price
-                .replace(',', ".")
-                .replace(" ", "")
-                .parse()
-                .unwrap_or(0.0);
-            build_new_product(
-                self.crawler_id,
-                sku,
-                name,
-                Some(category),
-                Some(units),
-                price,
-                Some(amount),
-                Some(description),
-                url.to_string(),
-                vec![],
+            filter_links(
+                self.robots.get(),
+                resolve_category_urls(&self.base_url, &self.category_urls),
             )
-            .into_iter()
-            .collect()
+        };
+        CrawlCounters::add(&self.counters.categories_discovered, categories.len());
+        let categories = self
+            .limits
+            .cap_categories(categories, self.base_url.as_str());
+
+        let mut tasks = vec![];
+        for category in categories.iter() {
+            tasks.push(async { self.get_page_links(category).await });
+        }
+        let page_links = futures::future::join_all(tasks).await;
+        let page_links = self.limits.cap_pages(
+            page_links.into_iter().flatten().collect(),
+            self.base_url.as_str(),
+        );
+        CrawlCounters::add(&self.counters.pages_visited, page_links.len());
+
+        let mut tasks = vec![];
+        for page_link in page_links.iter() {
+            tasks.push(async { self.get_product_links(page_link).await });
         }
+        let product_links = futures::future::join_all(tasks).await;
+
+        // Deduplicate product links to avoid fetching the same page multiple times.
+        let unique_links: HashSet<String> = product_links
+            .iter()
+            .flatten()
+            .map(|link| self.query_filter.apply(link))
+            .collect();
+        CrawlCounters::add(&self.counters.product_links_found, unique_links.len());
+        let unique_links = self
+            .limits
+            .cap_products(unique_links, self.base_url.as_str());
+
+        unique_links
+    }
+
+    /// Fetches product information from a single product page.
+    ///
+    /// A page may describe multiple variants; each variant is converted into
+    /// its own [`NewProduct`].
+    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+        let document = match self.fetch_html(url).await {
+            Some(doc) => doc,
+            None => {
+                log::error!("Failed to parse HTML {url}");
+                return vec![];
+            }
+        };
+
+        self.read_product_page(&document, url)
+    }
+
+    async fn get_product_page(&self, url: &str) -> ProductPage {
+        match self.fetch_html_result(url).await {
+            Ok(document) => ProductPage::Products(self.read_product_page(&document, url)),
+            Err(e) if e.is_not_found() => ProductPage::NotFound,
+            Err(_) => ProductPage::Products(vec![]),
+        }
+    }
+
+    fn stats(&self) -> CrawlStats {
+        self.counters.snapshot()
     }
 }
 
@@ -342,6 +588,24 @@ impl WebstoreCrawler for WebstoreCrawlerRusteaco {
 mod tests {
     use super::*;
 
+    #[test]
+    fn listing_pages_replace_the_pagination_param() {
+        let category_url = Url::parse("https://shop.rusteaco.ru/")
+            .unwrap()
+            .join("/collections/puer?page=1&sort=price")
+            .unwrap();
+
+        assert_eq!(
+            paginated_url(&category_url, PAGINATION_PARAM, 2),
+            "https://shop.rusteaco.ru/collections/puer?sort=price&page=2"
+        );
+    }
+
+    const PRODUCT_PAGE: &str = include_str!("../../tests/fixtures/rusteaco/product.html");
+    const SALE_PAGE: &str = include_str!("../../tests/fixtures/rusteaco/product_sale.html");
+    const OUT_OF_STOCK_PAGE: &str =
+        include_str!("../../tests/fixtures/rusteaco/product_out_of_stock.html");
+
     fn dummy_product_fields() -> (&'static str, &'static str, &'static str, &'static str) {
         ("Name", "Category", "Description", "http://example.com")
     }
@@ -385,4 +649,205 @@ mod tests {
         assert_eq!(product.units.as_deref(), Some("шт"));
         assert!((product.amount.unwrap().get() - 1.0).abs() < f64::EPSILON);
     }
+
+    #[test]
+    fn malformed_product_json_falls_back_to_markup() {
+        let html = r#"
+            <h1 class="product__title">Да Хун Пао</h1>
+            <ul class="breadcrumb"><li><a>Улун</a></li></ul>
+            <form class="product" data-product-json="{&quot;variants&quot;: [">
+                <span class="sku-value">DHP-50</span>
+                <button class="option-value">50 г</button>
+                <span class="product__price-cur">1 250</span>
+            </form>
+        "#;
+        let document = Html::parse_document(html);
+
+        let products = WebstoreCrawlerRusteaco::new(1, 1)
+            .unwrap()
+            .parse_product(&document, "http://example.com/dhp");
+
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].sku.as_str(), "DHP-50");
+        assert_eq!(products[0].units.as_deref(), Some("г"));
+        assert!((products[0].price.get() - 1250.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn products_priced_in_another_currency_are_skipped() {
+        let html = r#"
+            <h1 class="product__title">Да Хун Пао</h1>
+            <form class="product">
+                <span class="sku-value">DHP-50</span>
+                <span class="product__price-cur">$12.99</span>
+            </form>
+        "#;
+        let document = Html::parse_document(html);
+
+        let products = WebstoreCrawlerRusteaco::new(1, 1)
+            .unwrap()
+            .parse_product(&document, "http://example.com/dhp");
+
+        assert!(products.is_empty());
+    }
+
+    #[test]
+    fn repeated_and_empty_variant_skus_keep_distinct_weights() {
+        let html = r#"
+            <h1 class="product__title">Шу Пуэр</h1>
+            <form class="product" data-product-json="{&quot;variants&quot;: [
+                {&quot;sku&quot;: &quot;SP&quot;, &quot;price&quot;: &quot;300&quot;, &quot;title&quot;: &quot;50 г&quot;},
+                {&quot;sku&quot;: &quot;SP&quot;, &quot;price&quot;: &quot;550&quot;, &quot;title&quot;: &quot;100 г&quot;},
+                {&quot;sku&quot;: &quot; &quot;, &quot;price&quot;: &quot;990&quot;, &quot;title&quot;: &quot;200 г&quot;}
+            ]}"></form>
+        "#;
+        let document = Html::parse_document(html);
+
+        let products = WebstoreCrawlerRusteaco::new(1, 1)
+            .unwrap()
+            .parse_product(&document, "http://example.com/sp");
+        let products = crate::crawlers::dedup_products(products);
+
+        let keys = products
+            .iter()
+            .map(|p| {
+                (
+                    p.sku.as_str(),
+                    p.url.as_ref().map(|url| url.as_str()).unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                ("SP", "http://example.com/sp#SP"),
+                ("SP-2", "http://example.com/sp#SP-2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn selector_overrides_follow_changed_markup() {
+        let html = r#"
+            <h2 class="product-title">Да Хун Пао</h2>
+            <form class="product">
+                <span class="sku-value">DHP-50</span>
+                <span class="product__price-cur">1 250</span>
+            </form>
+        "#;
+        let document = Html::parse_document(html);
+        let overrides = HashMap::from([("name".to_string(), "h2.product-title".to_string())]);
+
+        let products = WebstoreCrawlerRusteaco::new(1, 1)
+            .unwrap()
+            .with_selectors(&overrides)
+            .unwrap()
+            .parse_product(&document, "http://example.com/dhp");
+
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].name.as_str(), "Да Хун Пао");
+    }
+
+    #[test]
+    fn parses_product_page_fixture() {
+        let document = Html::parse_document(PRODUCT_PAGE);
+
+        let products = WebstoreCrawlerRusteaco::new(1, 1)
+            .unwrap()
+            .parse_product(&document, "https://shop.rusteaco.ru/product/tgy");
+
+        assert_eq!(products.len(), 2);
+        assert!(products.iter().all(|p| p.name.as_str() == "Те Гуань Инь"));
+        assert!(
+            products
+                .iter()
+                .all(|p| p.category.as_ref().map(|c| c.as_str()) == Some("Улун / Фуцзянь"))
+        );
+        assert_eq!(products[0].sku.as_str(), "TGY-50");
+        assert!((products[0].price.get() - 420.0).abs() < f64::EPSILON);
+        assert_eq!(products[1].sku.as_str(), "TGY-100");
+        assert!((products[1].price.get() - 790.5).abs() < f64::EPSILON);
+        assert_eq!(
+            products[1].url.as_ref().map(|url| url.as_str()),
+            Some("https://shop.rusteaco.ru/product/tgy#TGY-100")
+        );
+    }
+
+    #[test]
+    fn out_of_stock_products_are_counted_and_skipped_on_request() {
+        let in_stock = Html::parse_document(PRODUCT_PAGE);
+        let sold_out = Html::parse_document(OUT_OF_STOCK_PAGE);
+        let crawler = WebstoreCrawlerRusteaco::new(1, 1).unwrap();
+
+        assert_eq!(
+            crawler
+                .read_product_page(&in_stock, "https://shop.rusteaco.ru/product/tgy")
+                .len(),
+            2
+        );
+        assert_eq!(
+            crawler
+                .read_product_page(&sold_out, "https://shop.rusteaco.ru/product/tgy")
+                .len(),
+            2
+        );
+        assert_eq!(crawler.stats().products_unavailable, 1);
+
+        let crawler = crawler.with_skip_unavailable(true);
+        assert_eq!(
+            crawler
+                .read_product_page(&in_stock, "https://shop.rusteaco.ru/product/tgy")
+                .len(),
+            2
+        );
+        assert!(
+            crawler
+                .read_product_page(&sold_out, "https://shop.rusteaco.ru/product/tgy")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn sale_pages_keep_the_sale_price_and_read_the_regular_one() {
+        let regular = Html::parse_document(PRODUCT_PAGE);
+        let sale = Html::parse_document(SALE_PAGE);
+        let crawler = WebstoreCrawlerRusteaco::new(1, 1).unwrap();
+
+        assert_eq!(
+            crawler.old_price(&regular, "https://shop.rusteaco.ru/product/tgy"),
+            None
+        );
+        assert_eq!(
+            crawler.old_price(&sale, "https://shop.rusteaco.ru/product/tgy"),
+            Some(420.0)
+        );
+        let products = crawler.parse_product(&sale, "https://shop.rusteaco.ru/product/tgy");
+        assert!((products[0].price.get() - 357.0).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn missing_product_page_is_reported_as_not_found() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let missing = format!("http://{}/product/gone", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+        let crawler = WebstoreCrawlerRusteaco::new(1, 1).unwrap();
+
+        assert!(matches!(
+            crawler.get_product_page(&missing).await,
+            ProductPage::NotFound
+        ));
+        assert_eq!(crawler.stats().http_failures, 1);
+    }
 }
@@ -0,0 +1,112 @@
+//! Named CSS selectors of a site crawler, overridable from configuration.
+
+use std::collections::HashMap;
+
+use scraper::Selector;
+
+use crate::crawlers::{CrawlerError, CrawlerResult};
+
+/// Compiles a CSS selector, failing with [`CrawlerError::Build`] instead of
+/// panicking so crawlers can reject bad selectors when they are created.
+pub fn parse_selector(css: &str) -> CrawlerResult<Selector> {
+    Selector::parse(css).map_err(|e| CrawlerError::Build(format!("`{css}`: {e}")))
+}
+
+/// The compiled CSS selectors a site crawler reads pages with, keyed by name
+/// (e.g. `product_link`).
+///
+/// Each crawler ships its selectors as defaults; operators can replace some
+/// of them from `crawlers.<selector>.selectors` when a store changes its
+/// markup, without a rebuild.
+#[derive(Debug, Clone)]
+pub struct SelectorSet {
+    selectors: HashMap<&'static str, Selector>,
+}
+
+impl SelectorSet {
+    /// Compiles `defaults`, replacing the entries named in `overrides`.
+    ///
+    /// An override with a name the crawler does not use, or any selector that
+    /// is not valid CSS, fails with [`CrawlerError::Build`] so a typo is
+    /// reported when the crawler is created rather than yielding no products.
+    pub fn new(
+        defaults: &[(&'static str, &str)],
+        overrides: &HashMap<String, String>,
+    ) -> CrawlerResult<Self> {
+        if let Some(unknown) = overrides
+            .keys()
+            .find(|name| !defaults.iter().any(|(default, _)| default == name))
+        {
+            return Err(CrawlerError::Build(format!(
+                "Unknown selector name `{unknown}`"
+            )));
+        }
+
+        let selectors = defaults
+            .iter()
+            .map(|&(name, default)| {
+                let css = overrides.get(name).map(String::as_str).unwrap_or(default);
+                let selector = parse_selector(css)
+                    .map_err(|e| CrawlerError::Build(format!("Invalid selector `{name}`: {e}")))?;
+                Ok((name, selector))
+            })
+            .collect::<CrawlerResult<_>>()?;
+
+        Ok(Self { selectors })
+    }
+
+    /// Returns the selector called `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `name` is not one of the crawler's defaults, which is a
+    /// bug in the crawler rather than a configuration error.
+    pub fn get(&self, name: &str) -> &Selector {
+        self.selectors
+            .get(name)
+            .unwrap_or_else(|| panic!("selector `{name}` is not defined"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::Html;
+
+    use super::*;
+
+    const DEFAULTS: &[(&str, &str)] = &[("name", "h1.title"), ("price", "span.price")];
+
+    #[test]
+    fn overrides_replace_only_the_named_selector() {
+        let overrides = HashMap::from([("name".to_string(), "h2.title".to_string())]);
+        let selectors = SelectorSet::new(DEFAULTS, &overrides).unwrap();
+        let document = Html::parse_document(
+            r#"<h1 class="title">Old</h1><h2 class="title">New</h2><span class="price">10</span>"#,
+        );
+
+        let text = |name| {
+            document
+                .select(selectors.get(name))
+                .next()
+                .map(|el| el.text().collect::<String>())
+        };
+
+        assert_eq!(text("name").as_deref(), Some("New"));
+        assert_eq!(text("price").as_deref(), Some("10"));
+    }
+
+    #[test]
+    fn unknown_names_and_invalid_css_are_rejected() {
+        let unknown = HashMap::from([("title".to_string(), "h1".to_string())]);
+        let invalid = HashMap::from([("price".to_string(), "span[".to_string())]);
+
+        assert!(matches!(
+            SelectorSet::new(DEFAULTS, &unknown),
+            Err(CrawlerError::Build(_))
+        ));
+        assert!(matches!(
+            SelectorSet::new(DEFAULTS, &invalid),
+            Err(CrawlerError::Build(_))
+        ));
+    }
+}
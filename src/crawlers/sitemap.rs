@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use pushkind_dantes::domain::product::NewProduct;
+use scraper::{Html, Selector};
+use tokio::sync::{OnceCell, Semaphore};
+use tokio::time::Duration;
+use url::Url;
+
+use crate::crawlers::RequestPacer;
+use crate::crawlers::query::QueryFilter;
+use crate::crawlers::robots::{RobotsRules, filter_links};
+use crate::crawlers::selectors::parse_selector;
+use crate::crawlers::{CrawlCounters, CrawlLimits, CrawlStats, ProductPage, fetch_text};
+use crate::crawlers::{
+    CrawlerError, CrawlerResult, HttpCredentials, WebstoreCrawler, build_reqwest_client,
+};
+
+/// Crawler that discovers product pages through `sitemap.xml` instead of
+/// category listings and pagination.
+///
+/// Product pages are parsed by the wrapped site crawler, so this type only
+/// replaces URL discovery and stays independent of listing markup.
+pub struct SitemapCrawler {
+    sitemap_url: Url,
+    path_prefix: String,
+    product_crawler: Box<dyn WebstoreCrawler + Send + Sync>,
+    client: reqwest::Client,
+    semaphore: Arc<Semaphore>,
+    robots: OnceCell<RobotsRules>,
+    pacer: RequestPacer,
+    counters: CrawlCounters,
+    query_filter: QueryFilter,
+    limits: CrawlLimits,
+    sitemap_selector: Selector,
+    url_selector: Selector,
+}
+
+impl SitemapCrawler {
+    /// Creates a sitemap crawler that reads `sitemap_url` and keeps page URLs
+    /// whose path starts with `path_prefix`.
+    ///
+    /// `concurrency` limits in-flight sitemap requests; product page requests
+    /// are limited by `product_crawler` itself.
+    pub fn new(
+        concurrency: usize,
+        sitemap_url: &str,
+        path_prefix: &str,
+        product_crawler: Box<dyn WebstoreCrawler + Send + Sync>,
+    ) -> CrawlerResult<Self> {
+        Ok(Self {
+            sitemap_url: Url::parse(sitemap_url).map_err(|e| CrawlerError::Build(e.to_string()))?,
+            path_prefix: path_prefix.to_string(),
+            product_crawler,
+            client: build_reqwest_client(&HttpCredentials::default())?,
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+            robots: OnceCell::new(),
+            pacer: RequestPacer::new(Duration::ZERO),
+            counters: CrawlCounters::default(),
+            query_filter: QueryFilter::default(),
+            limits: CrawlLimits::default(),
+            sitemap_selector: parse_selector("sitemap > loc")?,
+            url_selector: parse_selector("url > loc")?,
+        })
+    }
+
+    /// Sets the minimum delay between consecutive sitemap requests.
+    pub fn with_request_delay(mut self, delay: Duration) -> Self {
+        self.pacer = RequestPacer::new(delay);
+        self
+    }
+
+    /// Sets the query parameters stripped from product links before they are
+    /// deduplicated, fetched and stored.
+    pub fn with_query_filter(mut self, query_filter: QueryFilter) -> Self {
+        self.query_filter = query_filter;
+        self
+    }
+
+    /// Sends `credentials` with every sitemap request; product pages use the
+    /// wrapped crawler's own credentials.
+    pub fn with_credentials(mut self, credentials: &HttpCredentials) -> CrawlerResult<Self> {
+        self.client = build_reqwest_client(credentials)?;
+        Ok(self)
+    }
+
+    /// Sets caps on the sitemap documents and product pages of a crawl.
+    ///
+    /// `max_pages` counts sitemap documents, including nested indexes.
+    pub fn with_limits(mut self, limits: CrawlLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Fetches a URL and parses it into [`Html`].
+    ///
+    /// Sitemap XML is read with the HTML parser, which keeps unknown elements
+    /// such as `<loc>` selectable.
+    async fn fetch_html(&self, url: &str) -> Option<Html> {
+        let _permit = self.semaphore.acquire().await.ok()?;
+        self.pacer.wait().await;
+        let text = fetch_text(&self.client, url, &self.counters).await?;
+        Some(Html::parse_document(&text))
+    }
+
+    /// Walks the sitemap and any nested sitemap indexes, returning the page
+    /// URLs under `path_prefix`.
+    async fn get_product_links(&self) -> Vec<String> {
+        let mut pending = vec![self.sitemap_url.to_string()];
+        let mut visited = HashSet::new();
+        let mut links = Vec::new();
+
+        while let Some(sitemap) = pending.pop() {
+            if visited.contains(&sitemap) {
+                continue;
+            }
+            if self
+                .limits
+                .max_pages
+                .is_some_and(|max_pages| visited.len() >= max_pages)
+            {
+                log::warn!(
+                    "Crawl of {} hit the page cap of {}; skipping remaining sitemaps",
+                    self.sitemap_url,
+                    visited.len()
+                );
+                break;
+            }
+            visited.insert(sitemap.clone());
+
+            let document = match self.fetch_html(&sitemap).await {
+                Some(doc) => doc,
+                None => {
+                    log::error!("Failed to parse sitemap {sitemap}");
+                    continue;
+                }
+            };
+
+            pending.extend(
+                document
+                    .select(&self.sitemap_selector)
+                    .map(|loc| loc.text().collect::<String>().trim().to_string())
+                    .filter(|loc| !loc.is_empty()),
+            );
+
+            links.extend(
+                document
+                    .select(&self.url_selector)
+                    .map(|loc| loc.text().collect::<String>().trim().to_string())
+                    .filter(|loc| self.matches_prefix(loc)),
+            );
+        }
+
+        CrawlCounters::add(&self.counters.pages_visited, visited.len());
+        filter_links(self.robots.get(), links)
+    }
+
+    /// Returns `true` when the URL path starts with the configured prefix.
+    fn matches_prefix(&self, url: &str) -> bool {
+        Url::parse(url).is_ok_and(|url| url.path().starts_with(&self.path_prefix))
+    }
+}
+
+#[async_trait]
+impl WebstoreCrawler for SitemapCrawler {
+    /// Returns the unique product links listed in the sitemap.
+    ///
+    /// Product pages are then fetched through the wrapped crawler, which
+    /// enforces its own concurrency limit.
+    async fn get_product_urls(&self) -> Vec<String> {
+        self.robots
+            .get_or_init(|| RobotsRules::fetch(&self.client, &self.sitemap_url))
+            .await;
+
+        // Deduplicate product links to avoid fetching the same page multiple times.
+        let unique_links: HashSet<String> = self
+            .get_product_links()
+            .await
+            .iter()
+            .map(|link| self.query_filter.apply(link))
+            .collect();
+        CrawlCounters::add(&self.counters.product_links_found, unique_links.len());
+        let unique_links = self
+            .limits
+            .cap_products(unique_links, self.sitemap_url.as_str());
+        log::info!(
+            "Discovered {} product links in sitemap {}",
+            unique_links.len(),
+            self.sitemap_url
+        );
+
+        unique_links
+    }
+
+    /// Delegates to the wrapped site crawler.
+    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+        self.product_crawler.get_product(url).await
+    }
+
+    async fn get_product_page(&self, url: &str) -> ProductPage {
+        self.product_crawler.get_product_page(url).await
+    }
+
+    /// Combines sitemap counters with the product page counters of the
+    /// wrapped crawler.
+    fn stats(&self) -> CrawlStats {
+        let mut stats = self.counters.snapshot();
+        let product_stats = self.product_crawler.stats();
+        stats.products_unavailable += product_stats.products_unavailable;
+        stats.fetch_failures += product_stats.fetch_failures;
+        stats.http_failures += product_stats.http_failures;
+        stats.network_failures += product_stats.network_failures;
+        stats
+    }
+}
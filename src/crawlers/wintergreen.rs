@@ -1,25 +1,74 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use pushkind_dantes::domain::product::NewProduct;
-use scraper::{Html, Selector};
-use tokio::sync::Semaphore;
+use scraper::Html;
+use tokio::sync::{Mutex, OnceCell, Semaphore};
+use tokio::time::Duration;
 use url::Url;
 
 use crate::crawlers::CrawlerError;
 use crate::crawlers::CrawlerResult;
+use crate::crawlers::HttpCredentials;
+use crate::crawlers::RequestPacer;
 use crate::crawlers::WebstoreCrawler;
+use crate::crawlers::availability::is_available;
 use crate::crawlers::build_new_product;
 use crate::crawlers::build_reqwest_client;
+use crate::crawlers::debug_html::HtmlDumper;
+use crate::crawlers::json_ld::JsonLdParser;
+use crate::crawlers::price::price_in_rubles;
+use crate::crawlers::price::regular_price;
+use crate::crawlers::query::QueryFilter;
+use crate::crawlers::robots::{RobotsRules, filter_links};
+use crate::crawlers::selectors::SelectorSet;
+use crate::crawlers::{
+    CrawlCounters, CrawlLimits, CrawlStats, ProductPage, fetch_text_counted, paginated_url,
+    resolve_category_urls, resolve_entry_url,
+};
+
+/// Query parameter holding the number of a category listing page.
+const PAGINATION_PARAM: &str = "PAGEN_1";
+
+/// Names and default CSS of the selectors this crawler reads pages with;
+/// see [`SelectorSet`].
+pub const DEFAULT_SELECTORS: &[(&str, &str)] = &[
+    ("category_link", "a.menu-navigation__sections-item-link"),
+    ("pagination", "div.module-pagination"),
+    ("pagination_link", "a"),
+    ("product_link", "div.item-title > a"),
+    ("name", "h1"),
+    ("description", "div.detail-text-wrap"),
+    ("category", "a.breadcrumbs__link"),
+    ("price", "div.price"),
+    ("sku", "span.article__value"),
+    ("units", "span.product-card__calculus-unit"),
+    ("amount", "span.js-product-calc-value"),
+    ("images", "img.product-detail-gallery__picture"),
+    ("availability", "div.item-stock"),
+    ("old_price", "div.old-price"),
+];
 
 /// Crawler for `wintergreen.ru` which limits concurrent HTTP requests
 /// using a [`Semaphore`].
 pub struct WebstoreCrawlerWintergreen {
     crawler_id: i32,
     base_url: Url,
+    entry_url: Url,
     client: reqwest::Client,
     semaphore: Arc<Semaphore>,
+    robots: OnceCell<RobotsRules>,
+    pacer: RequestPacer,
+    counters: CrawlCounters,
+    query_filter: QueryFilter,
+    limits: CrawlLimits,
+    category_urls: Vec<String>,
+    selectors: SelectorSet,
+    json_ld: JsonLdParser,
+    html_dumper: HtmlDumper,
+    skip_unavailable: bool,
 }
 
 impl WebstoreCrawlerWintergreen {
@@ -28,71 +77,177 @@ impl WebstoreCrawlerWintergreen {
     /// `concurrency` controls how many HTTP requests may be in flight at the
     /// same time. The `crawler_id` is attached to each produced product.
     pub fn new(concurrency: usize, crawler_id: i32) -> CrawlerResult<Self> {
+        let base_url = Url::parse("https://wintergreen.ru/")
+            .map_err(|e| CrawlerError::Build(e.to_string()))?;
         Ok(Self {
             crawler_id,
-            base_url: Url::parse("https://wintergreen.ru/")
-                .map_err(|e| CrawlerError::Build(e.to_string()))?,
-            client: build_reqwest_client()?,
+            entry_url: base_url.clone(),
+            base_url,
+            client: build_reqwest_client(&HttpCredentials::default())?,
             semaphore: Arc::new(Semaphore::new(concurrency)),
+            robots: OnceCell::new(),
+            pacer: RequestPacer::new(Duration::ZERO),
+            counters: CrawlCounters::default(),
+            query_filter: QueryFilter::default(),
+            limits: CrawlLimits::default(),
+            category_urls: Vec::new(),
+            selectors: SelectorSet::new(DEFAULT_SELECTORS, &HashMap::new())?,
+            json_ld: JsonLdParser::new()?,
+            html_dumper: HtmlDumper::default(),
+            skip_unavailable: false,
         })
     }
 
+    /// Sets the minimum delay between consecutive requests to the store.
+    ///
+    /// Defaults to zero, i.e. requests are only limited by `concurrency`.
+    pub fn with_request_delay(mut self, delay: Duration) -> Self {
+        self.pacer = RequestPacer::new(delay);
+        self
+    }
+
+    /// Sets the query parameters stripped from product links before they are
+    /// deduplicated, fetched and stored.
+    pub fn with_query_filter(mut self, query_filter: QueryFilter) -> Self {
+        self.query_filter = query_filter;
+        self
+    }
+
+    /// Sets caps on the listing pages and product pages of a full crawl.
+    pub fn with_limits(mut self, limits: CrawlLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Restricts full crawls to these category pages instead of the
+    /// categories discovered on the landing page; empty crawls all of them.
+    ///
+    /// Paths are resolved against the store URL.
+    pub fn with_category_urls(mut self, category_urls: Vec<String>) -> Self {
+        self.category_urls = category_urls;
+        self
+    }
+
+    /// Starts category discovery from `entry_url`, e.g. a catalog page that
+    /// lists categories better than the landing page; `None` keeps the store
+    /// URL.
+    ///
+    /// Paths are resolved against the store URL; an invalid URL or one on
+    /// another host fails with [`CrawlerError::Build`].
+    pub fn with_entry_url(mut self, entry_url: Option<&str>) -> CrawlerResult<Self> {
+        if let Some(entry_url) = entry_url {
+            self.entry_url = resolve_entry_url(&self.base_url, entry_url)?;
+        }
+        Ok(self)
+    }
+
+    /// Drops products whose page marks them as out of stock (the
+    /// `availability` selector) instead of returning them; off by default.
+    pub fn with_skip_unavailable(mut self, skip_unavailable: bool) -> Self {
+        self.skip_unavailable = skip_unavailable;
+        self
+    }
+
+    /// Writes pages on which no product links or products were found into
+    /// `dir` (see [`HtmlDumper`]); `None`, the default, writes nothing.
+    pub fn with_debug_html_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.html_dumper = HtmlDumper::new(dir);
+        self
+    }
+
+    /// Sends `credentials` with every request to the store.
+    pub fn with_credentials(mut self, credentials: &HttpCredentials) -> CrawlerResult<Self> {
+        self.client = build_reqwest_client(credentials)?;
+        Ok(self)
+    }
+
+    /// Replaces the named selectors of [`DEFAULT_SELECTORS`] with `overrides`,
+    /// failing on unknown names and invalid CSS.
+    pub fn with_selectors(mut self, overrides: &HashMap<String, String>) -> CrawlerResult<Self> {
+        self.selectors = SelectorSet::new(DEFAULT_SELECTORS, overrides)?;
+        Ok(self)
+    }
+
     /// Fetches a URL and parses it into [`Html`].
     ///
     /// A permit from the internal [`Semaphore`] is acquired before issuing
-    /// the request, enforcing the configured concurrency limit.
+    /// the request, enforcing the configured concurrency limit, and the
+    /// configured request delay is observed before sending.
     async fn fetch_html(&self, url: &str) -> Option<Html> {
-        let _permit = self.semaphore.acquire().await.ok()?;
-        let res = self.client.get(url).send().await.ok()?;
-        if !res.status().is_success() {
-            log::error!("Failed to get URL {}: {}", url, res.status());
-            return None;
-        }
-        let text = res.text().await.ok()?;
-        Some(Html::parse_document(&text))
+        self.fetch_html_result(url).await.ok()
     }
 
-    /// Retrieves all category links from the store's landing page.
-    async fn get_category_links(&self) -> Vec<String> {
-        let document = match self.fetch_html(self.base_url.as_str()).await {
+    /// Like [`Self::fetch_html`], but returns the failure so a missing page
+    /// can be told apart from other errors.
+    async fn fetch_html_result(&self, url: &str) -> CrawlerResult<Html> {
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .map_err(|e| CrawlerError::Network {
+                url: url.to_string(),
+                message: e.to_string(),
+            })?;
+        self.pacer.wait().await;
+        let text = fetch_text_counted(&self.client, url, &self.counters).await?;
+        Ok(Html::parse_document(&text))
+    }
+
+    /// Adds all category links of the entry page, the store's landing page
+    /// unless configured otherwise, to `found`.
+    async fn get_category_links(&self, found: &Mutex<Vec<String>>) {
+        let document = match self.fetch_html(self.entry_url.as_str()).await {
             Some(doc) => doc,
             None => {
-                log::error!("Failed to parse HTML {}", self.base_url);
-                return vec![];
+                log::error!("Failed to parse HTML {}", self.entry_url);
+                return;
             }
         };
 
-        let selector = Selector::parse("a.menu-navigation__sections-item-link").unwrap();
+        let selector = self.selectors.get("category_link");
 
-        document
-            .select(&selector)
+        let links = document
+            .select(selector)
             .filter_map(|link| {
                 let href = link.value().attr("href")?;
-                Some(self.base_url.join(href).ok()?.to_string())
+                Some(self.entry_url.join(href).ok()?.to_string())
             })
-            .collect()
+            .collect();
+        found
+            .lock()
+            .await
+            .extend(filter_links(self.robots.get(), links));
     }
 
     /// For a given category URL, discovers all pagination links, returning
     /// the original URL and any additional pages.
     async fn get_page_links(&self, url: &str) -> Vec<String> {
-        let mut result = vec![url.to_string()];
-        let document = match self.fetch_html(url).await {
+        // Category hrefs may be relative or protocol-relative; resolving them
+        // once keeps the fetched URL and every page URL absolute.
+        let category_url = match self.base_url.join(url) {
+            Ok(category_url) => category_url,
+            Err(e) => {
+                log::error!("Invalid category URL {url}: {e}");
+                return vec![];
+            }
+        };
+        let mut result = vec![category_url.to_string()];
+        let document = match self.fetch_html(category_url.as_str()).await {
             Some(doc) => doc,
             None => {
-                log::error!("Failed to parse HTML {url}");
+                log::error!("Failed to parse HTML {category_url}");
                 return vec![];
             }
         };
 
-        let selector = Selector::parse("div.module-pagination").unwrap();
-        let pagination = match document.select(&selector).next() {
+        let selector = self.selectors.get("pagination");
+        let pagination = match document.select(selector).next() {
             Some(p) => p,
             None => return result,
         };
 
-        let selector = Selector::parse("a").unwrap();
-        let page_links = pagination.select(&selector).collect::<Vec<_>>();
+        let selector = self.selectors.get("pagination_link");
+        let page_links = pagination.select(selector).collect::<Vec<_>>();
         if page_links.is_empty() {
             return result;
         }
@@ -101,28 +256,11 @@ impl WebstoreCrawlerWintergreen {
             .last()
             .map(|e| e.text().collect::<String>().trim().to_string())
             && let Ok(last_page_number) = last_page_text.parse::<usize>()
-            && let Ok(base_url) = self.base_url.join(url)
         {
-            for i in 2..=last_page_number {
-                // Clone the URL and filter out the old `page` parameter
-                let mut page_url = base_url.clone();
-                let mut pairs: Vec<(String, String)> = page_url
-                    .query_pairs()
-                    .filter(|(k, _)| k != "PAGEN_1")
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect();
-
-                // Insert the new page value
-                pairs.push(("PAGEN_1".to_string(), i.to_string()));
-
-                // Clear existing query and re-apply
-                page_url.set_query(None);
-                page_url
-                    .query_pairs_mut()
-                    .extend_pairs(pairs.iter().map(|(k, v)| (&**k, &**v)));
-
-                result.push(page_url.to_string());
-            }
+            result.extend(
+                (2..=last_page_number)
+                    .map(|page| paginated_url(&category_url, PAGINATION_PARAM, page)),
+            );
         }
 
         result
@@ -138,129 +276,111 @@ impl WebstoreCrawlerWintergreen {
             }
         };
 
-        let selector = Selector::parse("div.item-title > a").unwrap();
-        document
-            .select(&selector)
+        let selector = self.selectors.get("product_link");
+        let links = document
+            .select(selector)
             .filter_map(|link| {
                 let href = link.value().attr("href")?;
                 Some(self.base_url.join(href).ok()?.to_string())
             })
-            .collect()
+            .collect::<Vec<_>>();
+        if links.is_empty() {
+            self.html_dumper.dump("product links", url, &document);
+        }
+        filter_links(self.robots.get(), links)
     }
-}
 
-#[async_trait]
-impl WebstoreCrawler for WebstoreCrawlerWintergreen {
-    /// Crawls the entire web store and returns all discovered products.
-    ///
-    /// Category pages, pagination, product links and product details are
-    /// fetched concurrently with `join_all`, while [`fetch_html`] ensures the
-    /// number of simultaneous HTTP requests never exceeds the configured
-    /// limit.
-    async fn get_products(&self) -> Vec<NewProduct> {
-        let categories = self.get_category_links().await;
+    /// Regular price of a discounted product page (the `old_price`
+    /// selector); the products carry the sale price.
+    fn old_price(&self, document: &Html, url: &str) -> Option<f64> {
+        regular_price(document, self.selectors.get("old_price"), url)
+    }
 
-        let mut tasks = vec![];
-        for category in categories.iter() {
-            tasks.push(async { self.get_page_links(category).await });
+    /// Extracts the products of a fetched product page, dumping the page
+    /// when it yields none.
+    ///
+    /// An out-of-stock page is counted, and yields nothing when unavailable
+    /// products are skipped. The regular price of a discounted page is only
+    /// logged, as products have no field for it yet.
+    fn read_product_page(&self, document: &Html, url: &str) -> Vec<NewProduct> {
+        if !is_available(document, self.selectors.get("availability")) {
+            CrawlCounters::add(&self.counters.products_unavailable, 1);
+            if self.skip_unavailable {
+                log::info!("Skipping out-of-stock product {url}");
+                return vec![];
+            }
+            log::debug!("Product {url} is out of stock");
         }
-        let page_links = futures::future::join_all(tasks).await;
-
-        let mut tasks = vec![];
-        for page_link in page_links.iter().flatten() {
-            tasks.push(async { self.get_product_links(page_link).await });
+        let products = self.parse_product(document, url);
+        if let Some(old_price) = self.old_price(document, url) {
+            log::debug!("Product {url} is discounted from a regular price of {old_price}");
         }
-        let product_links = futures::future::join_all(tasks).await;
-
-        // Deduplicate product links to avoid fetching the same page multiple times.
-        let unique_links: HashSet<String> = product_links.into_iter().flatten().collect();
-
-        let mut tasks = vec![];
-        for link in &unique_links {
-            tasks.push(async { self.get_product(link).await });
+        if products.is_empty() {
+            self.html_dumper.dump("products", url, document);
         }
-        let products = futures::future::join_all(tasks).await;
-
-        // Flatten and ensure uniqueness by product URL in the final result.
-        let mut products: Vec<NewProduct> = products.into_iter().flatten().collect();
-        let mut seen_urls = HashSet::new();
-        products.retain(|p| seen_urls.insert(p.url.clone()));
         products
     }
 
-    /// Fetches product information from a single product page.
+    /// Extracts the product from a product page.
     ///
-    /// A page may describe multiple variants; each variant is converted into
-    /// its own [`NewProduct`].
-    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
-        let document = match self.fetch_html(url).await {
-            Some(doc) => doc,
-            None => {
-                log::error!("Failed to parse HTML {url}");
-                return vec![];
-            }
-        };
-
+    /// Wintergreen pages describe a single product, so at most one is
+    /// returned. Gallery images are resolved against the store URL.
+    fn parse_product(&self, document: &Html, url: &str) -> Vec<NewProduct> {
         // Name
-        let name_selector = Selector::parse("h1").unwrap();
+        let name_selector = self.selectors.get("name");
         let name = document
-            .select(&name_selector)
+            .select(name_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Description
-        let desc_selector = Selector::parse("div.detail-text-wrap").unwrap();
+        let desc_selector = self.selectors.get("description");
         let description = document
-            .select(&desc_selector)
+            .select(desc_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Category from breadcrumbs
-        let category_selector = Selector::parse("a.breadcrumbs__link").unwrap();
+        let category_selector = self.selectors.get("category");
         let category = document
-            .select(&category_selector)
+            .select(category_selector)
             .map(|el| el.text().collect::<String>().trim().to_string())
             .collect::<Vec<_>>()
             .join(" / ");
 
         // Price
-        let price_selector = Selector::parse("div.price").unwrap();
+        let price_selector = self.selectors.get("price");
         let price = document
-            .select(&price_selector)
+            .select(price_selector)
             .next()
-            .map(|el| {
-                el.text()
-                    .collect::<String>()
-                    .trim()
-                    .to_string()
-                    .replace(",", ".")
-                    .replace(" ", "")
-            })
-            .and_then(|s| s.parse::<f64>().ok())
+            .map(|el| el.text().collect::<String>())
             .unwrap_or_default();
+        let Some(price) = price_in_rubles(&price, url) else {
+            return vec![];
+        };
 
         // SKU
-        let sku_selector = Selector::parse("span.article__value").unwrap();
+        let sku_selector = self.selectors.get("sku");
         let sku = document
-            .select(&sku_selector)
+            .select(sku_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Units
-        let units_selector = Selector::parse("span.product-card__calculus-unit").unwrap();
+        let units_selector = self.selectors.get("units");
         let units = document
-            .select(&units_selector)
+            .select(units_selector)
             .next()
             .map(|el| el.text().collect::<String>().trim().to_string())
             .unwrap_or_default();
 
         // Amount
-        let amount_selector = Selector::parse("span.js-product-calc-value").unwrap();
+        let amount_selector = self.selectors.get("amount");
         let amount = document
-            .select(&amount_selector)
+            .select(amount_selector)
             .next()
             .map(|el| {
                 el.text()
@@ -273,9 +393,9 @@ impl WebstoreCrawler for WebstoreCrawlerWintergreen {
             .and_then(|s| s.parse::<f64>().ok())
             .unwrap_or_default();
 
-        let images_selector = Selector::parse("img.product-detail-gallery__picture").unwrap();
+        let images_selector = self.selectors.get("images");
         let images = document
-            .select(&images_selector)
+            .select(images_selector)
             .map(|el| {
                 self.base_url
                     .join(el.value().attr("data-src").unwrap_or_default())
@@ -284,6 +404,10 @@ impl WebstoreCrawler for WebstoreCrawlerWintergreen {
             })
             .collect::<Vec<_>>();
 
+        // JSON-LD fills in what the page selectors missed.
+        let (name, sku, price, images) = self
+            .json_ld
+            .fill_missing(document, url, name, sku, price, images);
         build_new_product(
             self.crawler_id,
             sku,
@@ -300,3 +424,95 @@ impl WebstoreCrawler for WebstoreCrawlerWintergreen {
         .collect()
     }
 }
+
+#[async_trait]
+impl WebstoreCrawler for WebstoreCrawlerWintergreen {
+    /// Walks categories and pagination and returns the unique product links.
+    ///
+    /// Category pages, pagination and listing pages are fetched concurrently
+    /// with `join_all`, while [`fetch_html`] ensures the number of
+    /// simultaneous HTTP requests never exceeds the configured limit.
+    async fn get_product_urls(&self) -> Vec<String> {
+        self.robots
+            .get_or_init(|| RobotsRules::fetch(&self.client, &self.base_url))
+            .await;
+
+        let categories = if self.category_urls.is_empty() {
+            let found = Mutex::new(Vec::new());
+            self.limits
+                .discover_categories(
+                    &found,
+                    self.get_category_links(&found),
+                    self.base_url.as_str(),
+                )
+                .await
+        } else {
+            filter_links(
+                self.robots.get(),
+                resolve_category_urls(&self.base_url, &self.category_urls),
+            )
+        };
+        CrawlCounters::add(&self.counters.categories_discovered, categories.len());
+        let categories = self
+            .limits
+            .cap_categories(categories, self.base_url.as_str());
+
+        let mut tasks = vec![];
+        for category in categories.iter() {
+            tasks.push(async { self.get_page_links(category).await });
+        }
+        let page_links = futures::future::join_all(tasks).await;
+        let page_links = self.limits.cap_pages(
+            page_links.into_iter().flatten().collect(),
+            self.base_url.as_str(),
+        );
+        CrawlCounters::add(&self.counters.pages_visited, page_links.len());
+
+        let mut tasks = vec![];
+        for page_link in page_links.iter() {
+            tasks.push(async { self.get_product_links(page_link).await });
+        }
+        let product_links = futures::future::join_all(tasks).await;
+
+        // Deduplicate product links to avoid fetching the same page multiple times.
+        let unique_links: HashSet<String> = product_links
+            .iter()
+            .flatten()
+            .map(|link| self.query_filter.apply(link))
+            .collect();
+        CrawlCounters::add(&self.counters.product_links_found, unique_links.len());
+        let unique_links = self
+            .limits
+            .cap_products(unique_links, self.base_url.as_str());
+
+        unique_links
+    }
+
+    /// Fetches product information from a single product page.
+    ///
+    /// A page may describe multiple variants; each variant is converted into
+    /// its own [`NewProduct`].
+    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+        let document = match self.fetch_html(url).await {
+            Some(doc) => doc,
+            None => {
+                log::error!("Failed to parse HTML {url}");
+                return vec![];
+            }
+        };
+
+        self.read_product_page(&document, url)
+    }
+
+    async fn get_product_page(&self, url: &str) -> ProductPage {
+        match self.fetch_html_result(url).await {
+            Ok(document) => ProductPage::Products(self.read_product_page(&document, url)),
+            Err(e) if e.is_not_found() => ProductPage::NotFound,
+            Err(_) => ProductPage::Products(vec![]),
+        }
+    }
+
+    fn stats(&self) -> CrawlStats {
+        self.counters.snapshot()
+    }
+}
@@ -0,0 +1,42 @@
+//! SQLite connection pool construction.
+
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PoolError};
+use diesel::sqlite::SqliteConnection;
+use pushkind_common::db::DbPool;
+
+use crate::models::config::DbPoolConfig;
+
+/// Per-connection SQLite settings applied when the pool opens a connection:
+/// WAL journaling, enforced foreign keys and a busy timeout.
+#[derive(Debug)]
+struct SqliteOptions {
+    busy_timeout: Duration,
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqliteOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!(
+            "PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON; PRAGMA busy_timeout = {};",
+            self.busy_timeout.as_millis()
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Opens a pool of at most `config.max_size` connections to `database_url`.
+///
+/// Checking out a connection waits up to `config.connection_timeout_secs`
+/// before failing; SQLite waits as long for a locked database.
+pub fn establish_pool(database_url: &str, config: &DbPoolConfig) -> Result<DbPool, PoolError> {
+    let connection_timeout = Duration::from_secs(config.connection_timeout_secs);
+    Pool::builder()
+        .max_size(config.max_size)
+        .connection_timeout(connection_timeout)
+        .connection_customizer(Box::new(SqliteOptions {
+            busy_timeout: connection_timeout,
+        }))
+        .build(ConnectionManager::<SqliteConnection>::new(database_url))
+}
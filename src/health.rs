@@ -0,0 +1,109 @@
+//! Minimal HTTP liveness probe for container orchestration.
+
+use pushkind_common::db::DbPool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Path answered by the probe; every other path gets `404`.
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Largest request head read before answering.
+const MAX_REQUEST_BYTES: usize = 1024;
+
+/// Serves `GET /healthz` on `listener` until the process exits.
+///
+/// The probe answers `200 OK` when a database connection can be checked out
+/// of `pool` and `503 Service Unavailable` otherwise. Each connection gets a
+/// single response and is then closed.
+pub async fn serve_health(listener: TcpListener, pool: DbPool) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::warn!("Failed to accept health check connection: {e}");
+                continue;
+            }
+        };
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, pool).await {
+                log::warn!("Failed to answer health check from {peer}: {e}");
+            }
+        });
+    }
+}
+
+/// Reads the request line and writes the probe response.
+async fn respond(mut stream: TcpStream, pool: DbPool) -> std::io::Result<()> {
+    let mut buffer = vec![0; MAX_REQUEST_BYTES];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(HEALTH_PATH)) => {
+            // Checking out a connection blocks, so keep it off the runtime.
+            let healthy = tokio::task::spawn_blocking(move || pool.get().is_ok())
+                .await
+                .unwrap_or(false);
+            if healthy {
+                ("200 OK", "ok")
+            } else {
+                log::warn!("Health check failed: database pool is unavailable");
+                ("503 Service Unavailable", "database unavailable")
+            }
+        }
+        _ => ("404 Not Found", "not found"),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_pool;
+    use crate::models::config::DbPoolConfig;
+
+    async fn request(path: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("health.db");
+        let pool = establish_pool(database.to_str().unwrap(), &DbPoolConfig::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_health(listener, pool));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_ok_with_reachable_database() {
+        let response = request(HEALTH_PATH).await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[tokio::test]
+    async fn other_paths_are_not_found() {
+        let response = request("/metrics").await;
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
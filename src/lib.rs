@@ -1,4 +1,6 @@
 pub mod crawlers;
+pub mod db;
+pub mod health;
 pub mod models;
 pub mod processing;
 pub mod repository;
@@ -1,14 +1,101 @@
 use std::env;
+use std::path::Path;
+use std::time::Duration;
 
 use config::Config;
 use dotenvy::dotenv;
-use pushkind_common::db::establish_connection_pool;
-use pushkind_crawlers::models::config::ServerConfig;
+use pushkind_crawlers::crawlers::CrawlerError;
+use pushkind_crawlers::db::establish_pool;
+use pushkind_crawlers::health::serve_health;
+use pushkind_crawlers::models::config::{ServerConfig, ZmqSocketType, environment_source};
 use pushkind_crawlers::processing::benchmark::process_benchmark_message;
-use pushkind_crawlers::processing::category::process_product_category_match_message;
+use pushkind_crawlers::processing::category::{
+    process_crawler_product_category_match_message, process_product_category_match_message,
+};
 use pushkind_crawlers::processing::crawler::process_crawler_message;
+use pushkind_crawlers::processing::dead_letter::write_dead_letter;
+use pushkind_crawlers::processing::embedding::SharedEmbedder;
+use pushkind_crawlers::processing::maintenance::process_maintenance_message;
+use pushkind_crawlers::processing::message::{
+    IncomingMessage, MatchMessage, parse_crawler_message,
+};
+use pushkind_crawlers::processing::precompute::process_embed_hub_products_message;
 use pushkind_crawlers::repository::DieselRepository;
 use pushkind_dantes::domain::zmq::ZMQCrawlerMessage;
+use tokio::net::TcpListener;
+
+/// Delay between attempts to recreate the ZMQ socket after a receive error.
+const REBIND_DELAY: Duration = Duration::from_secs(1);
+
+/// How the receive loop reacts to a ZMQ error.
+enum RecvErrorAction {
+    /// The call was interrupted; receive again on the same socket.
+    Retry,
+    /// The socket is unusable; recreate and bind it again.
+    Rebind,
+    /// The context is gone; no socket can be created anymore.
+    Exit,
+}
+
+fn recv_error_action(err: zmq::Error) -> RecvErrorAction {
+    match err {
+        zmq::Error::EINTR | zmq::Error::EAGAIN => RecvErrorAction::Retry,
+        zmq::Error::ETERM => RecvErrorAction::Exit,
+        _ => RecvErrorAction::Rebind,
+    }
+}
+
+/// Creates the receiving socket, binds it and subscribes to the topic for
+/// `SUB` sockets.
+fn bind_socket(
+    context: &zmq::Context,
+    server_config: &ServerConfig,
+) -> Result<zmq::Socket, String> {
+    let socket_type = match server_config.zmq_socket_type {
+        ZmqSocketType::Pull => zmq::PULL,
+        ZmqSocketType::Sub => zmq::SUB,
+    };
+    let socket = context
+        .socket(socket_type)
+        .map_err(|err| format!("Cannot create zmq socket: {err}"))?;
+
+    socket
+        .bind(&server_config.zmq_crawlers_sub)
+        .map_err(|err| {
+            format!(
+                "Cannot bind to zmq port {}: {err}",
+                server_config.zmq_crawlers_sub
+            )
+        })?;
+
+    if server_config.zmq_socket_type == ZmqSocketType::Sub {
+        socket
+            .set_subscribe(server_config.zmq_topic.as_bytes())
+            .map_err(|err| {
+                format!(
+                    "Cannot subscribe to zmq topic {:?}: {err}",
+                    server_config.zmq_topic
+                )
+            })?;
+    }
+
+    Ok(socket)
+}
+
+/// Recreates the receiving socket, retrying every [`REBIND_DELAY`] until it
+/// is bound again.
+async fn rebind_socket(context: &zmq::Context, server_config: &ServerConfig) -> zmq::Socket {
+    loop {
+        tokio::time::sleep(REBIND_DELAY).await;
+        match bind_socket(context, server_config) {
+            Ok(socket) => {
+                log::info!("Rebound zmq socket at {}", server_config.zmq_crawlers_sub);
+                return socket;
+            }
+            Err(err) => log::warn!("{err}, retrying"),
+        }
+    }
+}
 
 /// Entry point for the crawler service.
 #[tokio::main]
@@ -27,7 +114,7 @@ async fn main() {
         // Add environment-specific overrides
         .add_source(config::File::with_name(&format!("config/{}", app_env)).required(false))
         // Add settings from the environment (with a prefix of APP)
-        .add_source(config::Environment::with_prefix("APP"))
+        .add_source(environment_source())
         .build();
 
     let settings = match settings {
@@ -46,7 +133,7 @@ async fn main() {
         }
     };
 
-    let pool = match establish_connection_pool(&server_config.database_url) {
+    let pool = match establish_pool(&server_config.database_url, &server_config.db_pool) {
         Ok(pool) => pool,
         Err(e) => {
             log::error!("Failed to establish database connection: {e}");
@@ -54,50 +141,154 @@ async fn main() {
         }
     };
 
+    if let Some(port) = server_config.health_port {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Failed to bind health check port {port}: {e}");
+                std::process::exit(1);
+            }
+        };
+        log::info!("Serving health checks on port {port}");
+        tokio::spawn(serve_health(listener, pool.clone()));
+    }
+
+    // Loaded on the first matching run and reused by every later one.
+    let embedder = SharedEmbedder::new(server_config.embedding_model)
+        .with_prompt_template(server_config.embedding_prompt)
+        .with_description_max_words(server_config.embedding_description_max_words);
+    log::info!(
+        "Embedding prompts use template v{}",
+        server_config.embedding_prompt.version()
+    );
+
     let context = zmq::Context::new();
-    let responder = match context.socket(zmq::PULL) {
+    let mut responder = match bind_socket(&context, &server_config) {
         Ok(socket) => socket,
         Err(err) => {
-            log::error!("Cannot create zmq socket: {err}");
+            log::error!("{err}");
             std::process::exit(1);
         }
     };
 
-    if let Err(err) = responder.bind(&server_config.zmq_crawlers_sub) {
-        log::error!(
-            "Cannot bind to zmq port {}: {err}",
-            server_config.zmq_crawlers_sub
-        );
-        std::process::exit(1);
-    }
+    // Only SUB sockets carry a topic; PULL payloads are parsed as-is.
+    let topic = match server_config.zmq_socket_type {
+        ZmqSocketType::Pull => String::new(),
+        ZmqSocketType::Sub => server_config.zmq_topic.clone(),
+    };
 
     loop {
-        let msg = match responder.recv_bytes(0) {
-            Ok(msg) => msg,
-            Err(err) => {
-                log::error!("Failed to receive ZMQ message: {err}");
-                continue;
-            }
+        let msg = match responder.recv_multipart(0) {
+            Ok(frames) => frames.concat(),
+            Err(err) => match recv_error_action(err) {
+                RecvErrorAction::Retry => {
+                    log::warn!("Interrupted while receiving ZMQ message: {err}");
+                    continue;
+                }
+                RecvErrorAction::Rebind => {
+                    log::error!("Failed to receive ZMQ message, rebinding socket: {err}");
+                    drop(responder);
+                    responder = rebind_socket(&context, &server_config).await;
+                    continue;
+                }
+                RecvErrorAction::Exit => {
+                    log::error!("ZMQ context terminated, shutting down: {err}");
+                    break;
+                }
+            },
         };
-        match serde_json::from_slice::<ZMQCrawlerMessage>(&msg) {
+        match parse_crawler_message(&msg, &topic) {
             Ok(parsed) => {
                 let pool_clone = pool.clone();
+                let benchmark_match_config = server_config.benchmark_match.clone();
+                let category_match_config = server_config.category_match.clone();
+                let crawlers_config = server_config.crawlers.clone();
+                let embedder = embedder.clone();
                 tokio::spawn(async move {
                     let repo = DieselRepository::new(pool_clone);
                     match parsed {
-                        ZMQCrawlerMessage::Crawler(crawler) => {
-                            process_crawler_message(crawler, repo).await
+                        IncomingMessage::Crawler(ZMQCrawlerMessage::Crawler(crawler)) => {
+                            match process_crawler_message(crawler, repo, crawlers_config).await {
+                                Ok(_) => {}
+                                Err(e @ CrawlerError::AlreadyRunning) => {
+                                    log::warn!("Crawler message was skipped: {e}");
+                                }
+                                Err(e) => log::error!("Crawler message was not processed: {e}"),
+                            }
+                        }
+                        IncomingMessage::Crawler(ZMQCrawlerMessage::Benchmark(benchmark)) => {
+                            process_benchmark_message(
+                                benchmark,
+                                repo,
+                                benchmark_match_config,
+                                embedder,
+                                false,
+                            )
+                            .await
+                        }
+                        IncomingMessage::Crawler(ZMQCrawlerMessage::ProductCategoryMatch(
+                            hub_id,
+                        )) => {
+                            process_product_category_match_message(
+                                hub_id,
+                                repo,
+                                category_match_config,
+                                embedder,
+                                false,
+                            )
+                            .await;
+                        }
+                        IncomingMessage::Maintenance(maintenance) => {
+                            process_maintenance_message(maintenance, repo).await
                         }
-                        ZMQCrawlerMessage::Benchmark(benchmark) => {
-                            process_benchmark_message(benchmark, repo).await
+                        IncomingMessage::Match(MatchMessage::ReembedBenchmark(benchmark)) => {
+                            process_benchmark_message(
+                                benchmark,
+                                repo,
+                                benchmark_match_config,
+                                embedder,
+                                true,
+                            )
+                            .await
                         }
-                        ZMQCrawlerMessage::ProductCategoryMatch(hub_id) => {
-                            process_product_category_match_message(hub_id, repo).await
+                        IncomingMessage::Match(MatchMessage::ReembedProductCategoryMatch(
+                            hub_id,
+                        )) => {
+                            process_product_category_match_message(
+                                hub_id,
+                                repo,
+                                category_match_config,
+                                embedder,
+                                true,
+                            )
+                            .await;
+                        }
+                        IncomingMessage::Match(MatchMessage::CrawlerProductCategoryMatch(
+                            selector,
+                        )) => {
+                            process_crawler_product_category_match_message(
+                                selector,
+                                repo,
+                                category_match_config,
+                                embedder,
+                            )
+                            .await;
+                        }
+                        IncomingMessage::Match(MatchMessage::EmbedHubProducts(hub_id)) => {
+                            process_embed_hub_products_message(hub_id, repo, embedder).await;
                         }
                     }
                 });
             }
-            Err(e) => log::error!("Failed to parse JSON: {e}"),
+            Err(e) => {
+                log::error!("Failed to parse message: {e}");
+                if let Some(dir) = server_config.dead_letter_dir.as_deref() {
+                    match write_dead_letter(Path::new(dir), &msg, &e) {
+                        Ok(path) => log::warn!("Wrote malformed message to {}", path.display()),
+                        Err(err) => log::error!("Failed to write dead letter to {dir}: {err}"),
+                    }
+                }
+            }
         }
     }
 }
@@ -1,10 +1,487 @@
 //! Configuration model loaded from external sources.
 
+use std::collections::HashMap;
+
 use serde::Deserialize;
 
+use crate::SIMILARITY_THRESHOLD;
+
+/// Settings source reading `APP_`-prefixed environment variables, e.g.
+/// `APP_DATABASE_URL`; nested sections use `__`, e.g.
+/// `APP_CATEGORY_MATCH__MAX_CATEGORIES`.
+pub fn environment_source() -> config::Environment {
+    config::Environment::with_prefix("APP")
+        .prefix_separator("_")
+        .separator("__")
+}
+
 #[derive(Clone, Debug, Deserialize)]
 /// Basic configuration shared across handlers.
 pub struct ServerConfig {
     pub database_url: String,
+    /// Size and checkout timeout of the database connection pool.
+    #[serde(default)]
+    pub db_pool: DbPoolConfig,
     pub zmq_crawlers_sub: String,
+    /// Socket type bound at `zmq_crawlers_sub`.
+    #[serde(default)]
+    pub zmq_socket_type: ZmqSocketType,
+    /// Topic prefix subscribed to and stripped from messages on `SUB` sockets.
+    #[serde(default)]
+    pub zmq_topic: String,
+    /// Directory receiving messages that fail to parse; unset only logs them.
+    #[serde(default)]
+    pub dead_letter_dir: Option<String>,
+    /// Port of the HTTP `/healthz` liveness probe; unset disables it.
+    #[serde(default)]
+    pub health_port: Option<u16>,
+    /// Text embedding model shared by benchmark and category matching.
+    #[serde(default)]
+    pub embedding_model: EmbeddingModelName,
+    /// Template of the product and benchmark text that gets embedded.
+    #[serde(default)]
+    pub embedding_prompt: PromptTemplate,
+    /// Number of whitespace-delimited words of a product or benchmark
+    /// description kept in its embedding prompt; unset keeps all of them.
+    #[serde(default)]
+    pub embedding_description_max_words: Option<usize>,
+    /// Settings for benchmark-to-product matching runs.
+    #[serde(default)]
+    pub benchmark_match: BenchmarkMatchConfig,
+    /// Settings for product-to-category matching runs.
+    #[serde(default)]
+    pub category_match: CategoryMatchConfig,
+    /// Per-crawler settings keyed by crawler selector (e.g. `rusteaco`).
+    #[serde(default)]
+    pub crawlers: HashMap<String, CrawlerConfig>,
+}
+
+/// Settings applied to a single webstore crawler.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CrawlerConfig {
+    /// Minimum delay between consecutive requests to the store.
+    #[serde(default)]
+    pub request_delay_ms: u64,
+    /// When set, full crawls discover product pages from this `sitemap.xml`
+    /// instead of category listings.
+    #[serde(default)]
+    pub sitemap_url: Option<String>,
+    /// Path prefix a sitemap URL must have to be crawled as a product page.
+    #[serde(default = "default_sitemap_path_prefix")]
+    pub sitemap_path_prefix: String,
+    /// Minimum share of the previous product count a full crawl must find
+    /// before it replaces the stored catalog. Empty crawls never replace it.
+    #[serde(default = "default_min_catalog_ratio")]
+    pub min_catalog_ratio: f64,
+    /// Query parameters removed from product URLs before deduplication,
+    /// fetching and storage; `*` removes all of them.
+    #[serde(default)]
+    pub strip_query_params: Vec<String>,
+    /// Query parameters that are never removed, e.g. variant selectors.
+    #[serde(default)]
+    pub keep_query_params: Vec<String>,
+    /// Maximum number of listing pages (or sitemap documents) visited by a
+    /// full crawl; `None` disables the cap.
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+    /// Maximum number of product pages fetched by a full crawl; `None`
+    /// disables the cap.
+    #[serde(default)]
+    pub max_products: Option<usize>,
+    /// Maximum number of categories a full crawl walks; `None` disables the
+    /// cap.
+    #[serde(default)]
+    pub max_categories: Option<usize>,
+    /// Seconds category discovery on the entry page may take before the
+    /// crawl goes on with the categories found so far; `None` waits for it.
+    #[serde(default)]
+    pub category_discovery_timeout_secs: Option<u64>,
+    /// When set, full crawls stream products to the database in batches of
+    /// this size instead of buffering the whole catalog.
+    #[serde(default)]
+    pub stream_batch_size: Option<usize>,
+    /// Carry embeddings of unchanged products over a full (non-streaming)
+    /// re-crawl instead of regenerating them.
+    #[serde(default)]
+    pub preserve_embeddings: bool,
+    /// File each crawl's products are written to; replaced on every run.
+    #[serde(default)]
+    pub export_path: Option<String>,
+    /// Format of `export_path`.
+    #[serde(default)]
+    pub export_format: ExportFormat,
+    /// Crawl without writing products to the database, e.g. together with
+    /// `export_path` to inspect a store.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Upper bound on the crawling part of a run; a crawl still running
+    /// after this many seconds is abandoned without replacing the catalog.
+    #[serde(default = "default_crawl_timeout_secs")]
+    pub crawl_timeout_secs: u64,
+    /// Seconds between scheduled full crawls, counted from the crawler's
+    /// `updated_at` set by its last successful run; `None` never schedules
+    /// it.
+    #[serde(default)]
+    pub crawl_interval_secs: Option<u64>,
+    /// CSS selectors replacing the crawler's defaults by name, e.g.
+    /// `product_link`; unknown names and invalid CSS fail the crawl.
+    #[serde(default)]
+    pub selectors: HashMap<String, String>,
+    /// User name sent with HTTP Basic Auth on every request, e.g. to crawl a
+    /// staging store; unset sends no `Authorization` header.
+    #[serde(default)]
+    pub basic_auth_username: Option<String>,
+    /// Password sent with `basic_auth_username`; empty when unset.
+    #[serde(default)]
+    pub basic_auth_password: Option<String>,
+    /// `Cookie` header sent on every request, e.g. `session=abc`.
+    #[serde(default)]
+    pub cookie: Option<String>,
+    /// Accept invalid TLS certificates, e.g. a staging store's self-signed
+    /// one. Off by default; never enable it for production stores.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// Number of fetched product pages between progress log lines of a full
+    /// crawl; `0` disables them.
+    #[serde(default = "default_progress_log_pages")]
+    pub progress_log_pages: usize,
+    /// Category pages a full crawl starts from instead of the categories
+    /// found on the landing page, e.g. `/collections/puer`. The crawled
+    /// products are upserted and the rest of the catalog is kept; empty
+    /// crawls the whole store.
+    #[serde(default)]
+    pub category_urls: Vec<String>,
+    /// Page category discovery starts from instead of the store's landing
+    /// page, e.g. `/catalog/`; resolved against the store URL.
+    #[serde(default)]
+    pub entry_url: Option<String>,
+    /// Whether a full crawl replaces the catalog or only upserts, keeping
+    /// products it did not see (e.g. seasonal items delisted for a while).
+    #[serde(default)]
+    pub full_crawl_policy: FullCrawlPolicy,
+    /// Directory listing and product pages are written to when no product
+    /// links or products are found on them, to debug broken selectors;
+    /// unset (the default) writes nothing.
+    #[serde(default)]
+    pub debug_html_dir: Option<String>,
+    /// Leave products whose page marks them as out of stock (e.g. `Нет в
+    /// наличии`) out of the crawl; off by default, which keeps them.
+    #[serde(default)]
+    pub skip_unavailable: bool,
+    /// Minimum length, in characters after trimming, of a product name to be
+    /// stored; shorter products are rejected as broken pages.
+    #[serde(default = "default_min_field_length")]
+    pub min_name_length: usize,
+    /// Minimum length, in characters after trimming, of a product SKU to be
+    /// stored.
+    #[serde(default = "default_min_field_length")]
+    pub min_sku_length: usize,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            request_delay_ms: 0,
+            sitemap_url: None,
+            sitemap_path_prefix: default_sitemap_path_prefix(),
+            min_catalog_ratio: default_min_catalog_ratio(),
+            strip_query_params: Vec::new(),
+            keep_query_params: Vec::new(),
+            max_pages: None,
+            max_products: None,
+            max_categories: None,
+            category_discovery_timeout_secs: None,
+            stream_batch_size: None,
+            preserve_embeddings: false,
+            export_path: None,
+            export_format: ExportFormat::default(),
+            dry_run: false,
+            crawl_timeout_secs: default_crawl_timeout_secs(),
+            crawl_interval_secs: None,
+            selectors: HashMap::new(),
+            basic_auth_username: None,
+            basic_auth_password: None,
+            cookie: None,
+            danger_accept_invalid_certs: false,
+            progress_log_pages: default_progress_log_pages(),
+            category_urls: Vec::new(),
+            entry_url: None,
+            full_crawl_policy: FullCrawlPolicy::default(),
+            debug_html_dir: None,
+            skip_unavailable: false,
+            min_name_length: default_min_field_length(),
+            min_sku_length: default_min_field_length(),
+        }
+    }
+}
+
+/// File format of crawl exports.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// One JSON object per line.
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+/// What a full crawl does with stored products it did not see.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FullCrawlPolicy {
+    /// Replace the catalog, deleting unseen products.
+    #[default]
+    Replace,
+    /// Upsert the crawled products and keep unseen ones.
+    Merge,
+}
+
+fn default_sitemap_path_prefix() -> String {
+    "/".to_string()
+}
+
+fn default_min_catalog_ratio() -> f64 {
+    0.5
+}
+
+fn default_crawl_timeout_secs() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_progress_log_pages() -> usize {
+    500
+}
+
+fn default_min_field_length() -> usize {
+    1
+}
+
+/// Text embedding model used to embed products, benchmarks and categories.
+///
+/// Models differ in output dimension, so switching models makes stored
+/// embeddings of the other size stale; they are regenerated on next use.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingModelName {
+    /// `intfloat/multilingual-e5-small`, 384 dimensions; fast, for development.
+    MultilingualE5Small,
+    /// `intfloat/multilingual-e5-base`, 768 dimensions.
+    MultilingualE5Base,
+    /// `intfloat/multilingual-e5-large`, 1024 dimensions.
+    #[default]
+    MultilingualE5Large,
+}
+
+/// Versioned template turning product fields into the text that is embedded.
+///
+/// Embeddings built from different templates are not comparable, and stored
+/// embeddings do not record their template; after switching, send the
+/// `Reembed*` matching messages to regenerate them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptTemplate {
+    /// English field labels, e.g. `Name: ...`.
+    #[default]
+    V1,
+    /// Russian field labels, e.g. `Название: ...`, for Russian catalogs.
+    V2,
+    /// Field values only, one per line, without labels; empty fields are
+    /// skipped.
+    V3,
+}
+
+impl PromptTemplate {
+    /// Version number of the template, logged with matching runs.
+    pub fn version(self) -> u8 {
+        match self {
+            PromptTemplate::V1 => 1,
+            PromptTemplate::V2 => 2,
+            PromptTemplate::V3 => 3,
+        }
+    }
+}
+
+/// Settings of the SQLite connection pool.
+///
+/// Repository calls hold a connection only while their query runs, so the
+/// number of connections in use is bounded by the Tokio worker threads
+/// running handlers plus the health probe, not by the number of spawned
+/// message tasks.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DbPoolConfig {
+    /// Maximum number of open connections.
+    #[serde(default = "default_db_pool_max_size")]
+    pub max_size: u32,
+    /// Seconds to wait for a free connection (and for SQLite locks) before
+    /// failing.
+    #[serde(default = "default_db_pool_connection_timeout_secs")]
+    pub connection_timeout_secs: u64,
+}
+
+impl Default for DbPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_db_pool_max_size(),
+            connection_timeout_secs: default_db_pool_connection_timeout_secs(),
+        }
+    }
+}
+
+fn default_db_pool_max_size() -> u32 {
+    10
+}
+
+fn default_db_pool_connection_timeout_secs() -> u64 {
+    30
+}
+
+/// Settings for benchmark-to-product matching runs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BenchmarkMatchConfig {
+    /// Number of nearest products per crawler considered for association
+    /// before the similarity threshold is applied.
+    #[serde(default = "default_benchmark_top_k")]
+    pub top_k: usize,
+    /// Leave products without images out of matching; such listings are
+    /// often incomplete. Off by default.
+    #[serde(default)]
+    pub skip_products_without_images: bool,
+    /// Lower bounds of the similarity bands whose top-K candidate counts are
+    /// logged after each run, e.g. `[0.9, 0.8, 0.7]`; empty disables the
+    /// report. Only candidates at or above the threshold are associated.
+    #[serde(default = "default_similarity_bands")]
+    pub similarity_bands: Vec<f32>,
+    /// Most associations written per benchmark across all crawlers; the
+    /// most similar candidates are kept. Unset writes every candidate at or
+    /// above the threshold.
+    #[serde(default)]
+    pub max_associations: Option<usize>,
+    /// Fewest associations written per benchmark: when fewer candidates pass
+    /// the threshold, the best ones below it are associated as well and
+    /// logged as a `warn`. `0` (the default) never goes below the threshold.
+    #[serde(default)]
+    pub min_associations: usize,
+}
+
+impl Default for BenchmarkMatchConfig {
+    fn default() -> Self {
+        Self {
+            top_k: default_benchmark_top_k(),
+            skip_products_without_images: false,
+            similarity_bands: default_similarity_bands(),
+            max_associations: None,
+            min_associations: 0,
+        }
+    }
+}
+
+fn default_benchmark_top_k() -> usize {
+    10
+}
+
+fn default_similarity_bands() -> Vec<f32> {
+    vec![0.9, 0.8, 0.7]
+}
+
+/// Settings for product-to-category matching runs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CategoryMatchConfig {
+    /// Maximum number of hub categories loaded into the similarity index.
+    ///
+    /// `None` disables the check.
+    #[serde(default)]
+    pub max_categories: Option<usize>,
+    /// What to do when a hub exceeds `max_categories`.
+    #[serde(default)]
+    pub max_categories_action: CategoryLimitAction,
+    /// Minimum cosine similarity for a product to be assigned its nearest
+    /// category, independent of benchmark matching.
+    #[serde(default = "default_category_similarity_threshold")]
+    pub similarity_threshold: f32,
+    /// Number of nearest categories fetched per product. The closest one at
+    /// or above `similarity_threshold` is assigned; the others are logged at
+    /// `debug` for diagnosis.
+    #[serde(default = "default_category_top_k")]
+    pub top_k: usize,
+    /// Number of threads searching the category index in parallel. Defaults
+    /// to the available parallelism.
+    #[serde(default = "default_category_match_workers")]
+    pub match_workers: usize,
+}
+
+impl Default for CategoryMatchConfig {
+    fn default() -> Self {
+        Self {
+            max_categories: None,
+            max_categories_action: CategoryLimitAction::default(),
+            similarity_threshold: default_category_similarity_threshold(),
+            top_k: default_category_top_k(),
+            match_workers: default_category_match_workers(),
+        }
+    }
+}
+
+fn default_category_similarity_threshold() -> f32 {
+    SIMILARITY_THRESHOLD
+}
+
+fn default_category_top_k() -> usize {
+    1
+}
+
+fn default_category_match_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+}
+
+/// Reaction to a hub category directory exceeding the configured cap.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CategoryLimitAction {
+    /// Log a warning and keep matching against the full directory.
+    #[default]
+    Warn,
+    /// Log an error and abort the matching run.
+    Abort,
+}
+
+/// ZeroMQ socket pattern used to receive crawler messages.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ZmqSocketType {
+    /// `PULL` socket fed by a `PUSH` producer.
+    #[default]
+    Pull,
+    /// `SUB` socket fed by a `PUB` producer, filtered by `zmq_topic`.
+    Sub,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_source_reads_top_level_and_nested_variables() {
+        let variables = [
+            ("APP_DATABASE_URL", "prod.db"),
+            ("APP_ZMQ_CRAWLERS_SUB", "tcp://127.0.0.1:5555"),
+            ("APP_CATEGORY_MATCH__MAX_CATEGORIES", "5"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+        let config = config::Config::builder()
+            .add_source(environment_source().source(Some(variables)))
+            .build()
+            .unwrap()
+            .try_deserialize::<ServerConfig>()
+            .unwrap();
+
+        assert_eq!(config.database_url, "prod.db");
+        assert_eq!(config.zmq_crawlers_sub, "tcp://127.0.0.1:5555");
+        assert_eq!(config.category_match.max_categories, Some(5));
+    }
 }
@@ -1,79 +1,270 @@
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
 use pushkind_dantes::domain::benchmark::Benchmark;
 use pushkind_dantes::domain::types::{BenchmarkId, ProductId, SimilarityDistance};
 
 use crate::SIMILARITY_THRESHOLD;
+use crate::models::config::BenchmarkMatchConfig;
 use crate::processing::embedding::{
-    load_or_generate_embedding, product_embedding_prompt, search_top_k,
+    INDEX_METRIC, SharedEmbedder, distance_to_similarity, is_zero_embedding,
+    load_or_generate_embedding, render_product_prompt, search_top_k, truncate_description,
 };
+use crate::processing::log_context::LogContext;
 use crate::repository::{
     BenchmarkReader, BenchmarkWriter, CrawlerReader, ProductReader, ProductWriter,
 };
 
+/// Counts of top-K candidates per similarity band, for judging whether the
+/// association threshold fits the hub's data.
+///
+/// Bands are delimited by descending lower bounds, e.g. `[0.9, 0.8, 0.7]`
+/// gives `>=0.9`, `0.8-0.9`, `0.7-0.8` and `<0.7`.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct SimilarityBands {
+    bounds: Vec<f32>,
+    /// One count per bound plus the count below the lowest bound.
+    counts: Vec<usize>,
+}
+
+impl SimilarityBands {
+    fn new(bounds: &[f32]) -> Self {
+        let mut bounds: Vec<f32> = bounds.iter().copied().filter(|b| !b.is_nan()).collect();
+        bounds.sort_by(|a, b| b.total_cmp(a));
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self { bounds, counts }
+    }
+
+    fn record(&mut self, similarity: f32) {
+        let band = self
+            .bounds
+            .iter()
+            .position(|bound| similarity >= *bound)
+            .unwrap_or(self.bounds.len());
+        if let Some(count) = self.counts.get_mut(band) {
+            *count += 1;
+        }
+    }
+}
+
+impl fmt::Display for SimilarityBands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, bound) in self.bounds.iter().enumerate() {
+            match index {
+                0 => write!(f, ">={bound}={}", self.counts[index])?,
+                _ => write!(
+                    f,
+                    " {bound}-{}={}",
+                    self.bounds[index - 1],
+                    self.counts[index]
+                )?,
+            }
+        }
+        if let Some(lowest) = self.bounds.last() {
+            write!(f, " <{lowest}={}", self.counts[self.bounds.len()])?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters collected while matching a benchmark against hub products.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct BenchmarkMatchStats {
+    benchmark_embedding_generated: bool,
+    products_loaded: usize,
+    product_embeddings_generated: usize,
+    matched: usize,
+    skipped_below_threshold: usize,
+    /// Candidates above the threshold left out by `max_associations`.
+    capped: usize,
+    /// Associations below the threshold written for `min_associations`.
+    below_threshold_matched: usize,
+    skipped_invalid: usize,
+    skipped_without_images: usize,
+    /// Generated benchmark and product embeddings that are all zeros.
+    zero_embeddings: usize,
+    similarity_bands: SimilarityBands,
+}
+
+/// Picks the associations to write from the candidates of all crawlers.
+///
+/// `passed` holds candidates at or above the threshold and `below` those
+/// under it, each as `(product, similarity)`. The most similar `passed`
+/// candidates are kept up to `max`; when fewer than `min` remain, the most
+/// similar `below` candidates fill up to `min` (never beyond `max`).
+/// Returns the kept `passed` and `below` candidates and how many `passed`
+/// ones were capped.
+fn select_associations(
+    mut passed: Vec<(ProductId, f32)>,
+    mut below: Vec<(ProductId, f32)>,
+    min: usize,
+    max: Option<usize>,
+) -> (Vec<(ProductId, f32)>, Vec<(ProductId, f32)>, usize) {
+    let by_similarity = |a: &(ProductId, f32), b: &(ProductId, f32)| b.1.total_cmp(&a.1);
+    let max = max.unwrap_or(usize::MAX);
+
+    passed.sort_by(by_similarity);
+    let capped = passed.len().saturating_sub(max);
+    passed.truncate(max);
+
+    below.sort_by(by_similarity);
+    below.truncate(min.min(max).saturating_sub(passed.len()));
+
+    (passed, below, capped)
+}
+
 /// Generate embeddings for a benchmark and related products, build a search
 /// index and update benchmark-product associations.
 ///
 /// The function fetches the benchmark and all products for the same hub,
-/// generates missing embeddings using the multilingual E5 model, persists
+/// generates missing embeddings using the shared `embedder`, persists
 /// them, then builds a cosine index with `usearch` to find the closest
-/// products. Associations in the database are replaced with the top results
-/// and the benchmark processing flag is updated when complete.
-pub async fn process_benchmark_message<R>(benchmark_id: BenchmarkId, repo: R)
-where
+/// products. Associations in the database are replaced with the
+/// `config.top_k` closest products of each crawler that pass the similarity
+/// threshold, and the benchmark processing flag is updated when complete.
+///
+/// With `force_reembed` the benchmark and product embeddings are regenerated
+/// instead of reusing the stored ones.
+pub async fn process_benchmark_message<R>(
+    benchmark_id: BenchmarkId,
+    repo: R,
+    config: BenchmarkMatchConfig,
+    embedder: SharedEmbedder,
+    force_reembed: bool,
+) where
     R: BenchmarkReader + BenchmarkWriter + ProductReader + ProductWriter + CrawlerReader,
 {
-    log::info!("Received benchmark: {benchmark_id:?}");
+    log::info!("Received benchmark: {benchmark_id:?} (force_reembed={force_reembed})");
 
     let benchmark = match repo.get_benchmark(benchmark_id) {
         Ok(benchmark) => benchmark,
         Err(e) => {
-            log::error!("Failed to fetch benchmark: {e:?}");
+            log::error!(
+                "{} Failed to fetch benchmark: {e:?}",
+                LogContext::default().with("benchmark_id", benchmark_id)
+            );
             return;
         }
     };
 
+    let ctx = LogContext::benchmark(benchmark_id, benchmark.hub_id);
+
     if benchmark.processing {
-        log::warn!("Benchmark {benchmark_id} is already running");
+        log::warn!("{ctx} Benchmark is already running");
         return;
     }
 
+    match run_with_benchmark_processing_guard(benchmark_id, &ctx, &repo, || {
+        process_benchmark(benchmark, &repo, &config, &embedder, force_reembed)
+    }) {
+        Ok(stats) => {
+            log::info!(
+                "{ctx} Benchmark matched: benchmark_embedding_generated={}, products_loaded={}, product_embeddings_generated={}, matched={}, skipped_below_threshold={}, capped={}, below_threshold_matched={}, skipped_invalid={}, skipped_without_images={}, zero_embeddings={}",
+                stats.benchmark_embedding_generated,
+                stats.products_loaded,
+                stats.product_embeddings_generated,
+                stats.matched,
+                stats.skipped_below_threshold,
+                stats.capped,
+                stats.below_threshold_matched,
+                stats.skipped_invalid,
+                stats.skipped_without_images,
+                stats.zero_embeddings
+            );
+            if !config.similarity_bands.is_empty() {
+                log::info!(
+                    "{ctx} Similarity of top-{} candidates per band: {}",
+                    config.top_k,
+                    stats.similarity_bands
+                );
+            }
+            if stats.matched == 0 && stats.products_loaded > 0 {
+                log::warn!(
+                    "{ctx} Benchmark matched no products; {} candidates were below the similarity threshold {SIMILARITY_THRESHOLD}",
+                    stats.skipped_below_threshold
+                );
+            }
+        }
+        Err(()) => {
+            log::error!("{ctx} Benchmark processing failed");
+        }
+    }
+
+    log::info!("{ctx} Finished processing benchmark");
+}
+
+/// Run `job` with the benchmark's `processing` flag set.
+///
+/// The flag is cleared by `update_benchmark_stats` however `job` ends: with
+/// a result, an error or a panic, which is resumed afterwards. When the flag
+/// cannot be set, `job` does not run and `Err(())` is returned.
+fn run_with_benchmark_processing_guard<R, F, T>(
+    benchmark_id: BenchmarkId,
+    ctx: &LogContext,
+    repo: &R,
+    job: F,
+) -> Result<T, ()>
+where
+    R: BenchmarkWriter,
+    F: FnOnce() -> Result<T, ()>,
+{
     if let Err(e) = repo.set_benchmark_processing(benchmark_id, true) {
-        log::error!("Failed to set benchmark processing: {e:?}");
-        return;
+        log::error!("{ctx} Failed to set benchmark processing: {e:?}");
+        return Err(());
     }
 
-    process_benchmark(benchmark, &repo);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(job));
 
     if let Err(e) = repo.update_benchmark_stats(benchmark_id) {
-        log::error!("Failed to update benchmark stats: {e:?}");
+        log::error!("{ctx} Failed to update benchmark stats: {e:?}");
     }
 
-    log::info!("Finished processing benchmark: {benchmark_id}");
+    match outcome {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
 }
+
 /// Core logic for processing a benchmark and updating associations.
-fn process_benchmark<R>(benchmark: Benchmark, repo: &R)
+///
+/// Returns the match counters, or `Err(())` after logging when the run was
+/// aborted.
+fn process_benchmark<R>(
+    benchmark: Benchmark,
+    repo: &R,
+    config: &BenchmarkMatchConfig,
+    embedder: &SharedEmbedder,
+    force_reembed: bool,
+) -> Result<BenchmarkMatchStats, ()>
 where
     R: BenchmarkReader + BenchmarkWriter + ProductReader + ProductWriter + CrawlerReader,
 {
+    let mut stats = BenchmarkMatchStats {
+        similarity_bands: SimilarityBands::new(&config.similarity_bands),
+        ..BenchmarkMatchStats::default()
+    };
     let benchmark_id = benchmark.id;
-    // Initialize embedder for multilingual E5 large
-    let mut embedder =
-        match TextEmbedding::try_new(InitOptions::new(EmbeddingModel::MultilingualE5Large)) {
-            Ok(embedder) => embedder,
-            Err(e) => {
-                log::error!("Failed to initialize embedder: {e:?}");
-                return;
-            }
-        };
+    let ctx = LogContext::benchmark(benchmark_id, benchmark.hub_id);
+    let prompt_template = embedder.prompt_template();
+    let description_max_words = embedder.description_max_words();
+    let mut embedder = match embedder.acquire() {
+        Ok(embedder) => embedder,
+        Err(e) => {
+            log::error!("{ctx} {e}");
+            return Err(());
+        }
+    };
 
-    let benchmark_prompt = product_embedding_prompt(
+    let benchmark_prompt = render_product_prompt(
+        prompt_template,
         benchmark.name.as_str(),
         benchmark.sku.as_str(),
         benchmark.category.as_str(),
         benchmark.units.as_str(),
         benchmark.price.get(),
         benchmark.amount.get(),
-        benchmark.description.as_str(),
+        truncate_description(benchmark.description.as_str(), description_max_words),
     );
     let benchmark_embedding = match load_or_generate_embedding(
         benchmark.embedding.as_deref(),
@@ -84,53 +275,79 @@ where
                 .map(|_| ())
                 .map_err(|error| format!("Failed to set benchmark embedding: {error:?}"))
         },
+        force_reembed,
     ) {
-        Ok((embedding, _generated)) => embedding,
+        Ok(outcome) => {
+            stats.benchmark_embedding_generated = outcome.generated;
+            if outcome.generated && is_zero_embedding(&outcome.embedding) {
+                stats.zero_embeddings += 1;
+                log::warn!(
+                    "{ctx} Benchmark {benchmark_id} got an all-zero embedding; check its name and description"
+                );
+            }
+            outcome.embedding
+        }
         Err(error) => {
-            log::error!(
-                "Failed to resolve benchmark embedding for benchmark {}: {error}",
-                benchmark.id
-            );
-            return;
+            log::error!("{ctx} Failed to resolve benchmark embedding: {error}");
+            return Err(());
         }
     };
 
     let crawlers = match repo.list_crawlers(benchmark.hub_id) {
         Ok(crawlers) => crawlers,
         Err(e) => {
-            log::error!("Failed to fetch crawlers: {e:?}");
-            return;
+            log::error!("{ctx} Failed to fetch crawlers: {e:?}");
+            return Err(());
         }
     };
 
     // Remove existing associations
     if let Err(e) = repo.remove_benchmark_associations(benchmark_id) {
-        log::error!("Failed to clear associations: {e:?}");
-        return;
+        log::error!("{ctx} Failed to clear associations: {e:?}");
+        return Err(());
     }
 
+    // Candidates of all crawlers, written once `max_associations` and
+    // `min_associations` can be applied across them.
+    let mut passed = Vec::new();
+    let mut below = Vec::new();
+
     for crawler in crawlers {
-        log::info!("Processing products for crawler: {}", crawler.name);
-        let products = match repo.list_products(crawler.id) {
+        let crawler_ctx = ctx.clone().with("crawler_id", crawler.id);
+        log::info!(
+            "{crawler_ctx} Processing products for crawler: {}",
+            crawler.name
+        );
+        let mut products = match repo.list_products(crawler.id) {
             Ok(products) => products,
             Err(e) => {
-                log::error!("Failed to fetch products: {e:?}");
-                return;
+                log::error!("{crawler_ctx} Failed to fetch products: {e:?}");
+                return Err(());
             }
         };
+        stats.products_loaded += products.len();
+        if config.skip_products_without_images {
+            let loaded = products.len();
+            products.retain(|product| !product.images.is_empty());
+            stats.skipped_without_images += loaded - products.len();
+        }
 
         // Collect embeddings for index
-        let mut product_embeddings: Vec<(i32, Vec<f32>)> = Vec::new();
+        let mut product_embeddings = Vec::with_capacity(products.len());
 
-        for product in products {
-            let product_prompt = product_embedding_prompt(
+        for product in &products {
+            let product_prompt = render_product_prompt(
+                prompt_template,
                 product.name.as_str(),
                 product.sku.as_str(),
                 product.category.as_deref().unwrap_or(""),
                 product.units.as_deref().unwrap_or(""),
                 product.price.get(),
                 product.amount.map(|value| value.get()).unwrap_or_default(),
-                product.description.as_deref().unwrap_or(""),
+                truncate_description(
+                    product.description.as_deref().unwrap_or(""),
+                    description_max_words,
+                ),
             );
             let embedding = match load_or_generate_embedding(
                 product.embedding.as_deref(),
@@ -141,73 +358,267 @@ where
                         .map(|_| ())
                         .map_err(|error| format!("Failed to set product embedding: {error:?}"))
                 },
+                force_reembed,
             ) {
-                Ok((embedding, _generated)) => embedding,
+                Ok(outcome) => {
+                    stats.product_embeddings_generated += usize::from(outcome.generated);
+                    if outcome.generated && is_zero_embedding(&outcome.embedding) {
+                        stats.zero_embeddings += 1;
+                        log::warn!(
+                            "{crawler_ctx} Product {} got an all-zero embedding; check its name and description",
+                            product.id
+                        );
+                    }
+                    outcome.embedding
+                }
                 Err(error) => {
                     log::error!(
-                        "Failed to resolve product embedding for product {}: {error}",
+                        "{crawler_ctx} Failed to resolve product embedding for product {}: {error}",
                         product.id
                     );
-                    return;
+                    return Err(());
                 }
             };
 
             product_embeddings.push((product.id.get(), embedding));
         }
 
-        let top_10_products = match search_top_k(&benchmark_embedding, &product_embeddings, 10) {
-            Ok(top_10_products) => top_10_products,
+        let top_products = match search_top_k(
+            &benchmark_embedding,
+            &product_embeddings,
+            config.top_k,
+            embedder.dimensions(),
+        ) {
+            Ok(top_products) => top_products,
             Err(e) => {
-                log::error!("Failed to search top 10 products: {e:?}");
-                return;
+                log::error!(
+                    "{crawler_ctx} Failed to search top {} products: {e:?}",
+                    config.top_k
+                );
+                return Err(());
             }
         };
 
-        for (key, distance) in top_10_products {
-            let distance = 1.0 - distance;
-            if distance < SIMILARITY_THRESHOLD {
-                continue;
-            }
+        for (key, distance) in top_products {
+            let similarity = distance_to_similarity(INDEX_METRIC, distance);
+            stats.similarity_bands.record(similarity);
             let product_id = match ProductId::new(key as i32) {
                 Ok(product_id) => product_id,
                 Err(e) => {
-                    log::warn!("Skipping invalid product id from similarity index: {e}");
-                    continue;
-                }
-            };
-            let similarity_distance = match SimilarityDistance::new(distance) {
-                Ok(similarity_distance) => similarity_distance,
-                Err(e) => {
-                    log::warn!("Skipping invalid similarity distance: {e}");
+                    log::warn!(
+                        "{crawler_ctx} Skipping invalid product id from similarity index: {e}"
+                    );
+                    stats.skipped_invalid += 1;
                     continue;
                 }
             };
-            if let Err(e) =
-                repo.set_benchmark_association(benchmark_id, product_id, similarity_distance)
-            {
-                log::error!("Failed to set association: {e:?}");
-                return;
+            if similarity < SIMILARITY_THRESHOLD {
+                stats.skipped_below_threshold += 1;
+                below.push((product_id, similarity));
+            } else {
+                passed.push((product_id, similarity));
             }
         }
     }
+
+    let (passed, below, capped) = select_associations(
+        passed,
+        below,
+        config.min_associations,
+        config.max_associations,
+    );
+    stats.capped = capped;
+    for (product_id, similarity) in &below {
+        log::warn!(
+            "{ctx} Associating product {product_id} below the similarity threshold ({similarity:.3}) to reach min_associations"
+        );
+    }
+
+    let selected = passed
+        .into_iter()
+        .map(|candidate| (candidate, false))
+        .chain(below.into_iter().map(|candidate| (candidate, true)));
+    for ((product_id, similarity), below_threshold) in selected {
+        let similarity_distance = match SimilarityDistance::new(similarity) {
+            Ok(similarity_distance) => similarity_distance,
+            Err(e) => {
+                log::warn!("{ctx} Skipping invalid similarity distance: {e}");
+                stats.skipped_invalid += 1;
+                continue;
+            }
+        };
+        if let Err(e) =
+            repo.set_benchmark_association(benchmark_id, product_id, similarity_distance)
+        {
+            log::error!("{ctx} Failed to set association: {e:?}");
+            return Err(());
+        }
+        stats.matched += 1;
+        if below_threshold {
+            stats.below_threshold_matched += 1;
+        }
+    }
+
+    Ok(stats)
 }
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
+
+    use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
+    use pushkind_dantes::domain::types::HubId;
+
     use super::*;
 
+    /// Records the benchmark `processing` updates and stats refreshes.
+    #[derive(Default)]
+    struct ProcessingRecordingRepo {
+        fail_claim: bool,
+        processing: Mutex<Vec<bool>>,
+        stats_updates: Mutex<usize>,
+    }
+
+    impl BenchmarkWriter for ProcessingRecordingRepo {
+        fn set_benchmark_embedding(
+            &self,
+            _benchmark_id: BenchmarkId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_benchmark_association(
+            &self,
+            _benchmark_id: BenchmarkId,
+            _product_id: ProductId,
+            _distance: SimilarityDistance,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn remove_benchmark_associations(
+            &self,
+            _benchmark_id: BenchmarkId,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn purge_orphaned_associations(&self) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_benchmark_processing(
+            &self,
+            _benchmark_id: BenchmarkId,
+            processing: bool,
+        ) -> RepositoryResult<usize> {
+            if self.fail_claim {
+                return Err(RepositoryError::NotFound);
+            }
+            self.processing.lock().unwrap().push(processing);
+            Ok(1)
+        }
+
+        fn update_benchmark_stats(&self, _benchmark_id: BenchmarkId) -> RepositoryResult<usize> {
+            *self.stats_updates.lock().unwrap() += 1;
+            Ok(1)
+        }
+    }
+
+    fn benchmark_id() -> BenchmarkId {
+        BenchmarkId::new(7).unwrap()
+    }
+
+    fn context() -> LogContext {
+        LogContext::benchmark(benchmark_id(), HubId::new(1).unwrap())
+    }
+
+    #[test]
+    fn embedding_failure_mid_run_still_clears_processing() {
+        let repo = ProcessingRecordingRepo::default();
+
+        let outcome =
+            run_with_benchmark_processing_guard(benchmark_id(), &context(), &repo, || {
+                // `process_benchmark` logs an embedding failure and aborts.
+                Err::<BenchmarkMatchStats, ()>(())
+            });
+
+        assert_eq!(outcome, Err(()));
+        assert_eq!(*repo.processing.lock().unwrap(), vec![true]);
+        assert_eq!(*repo.stats_updates.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn similarity_bands_count_candidates_per_band() {
+        let mut bands = SimilarityBands::new(&[0.8, 0.9, 0.7]);
+
+        for similarity in [0.95, 0.9, 0.85, 0.72, 0.4, 0.1] {
+            bands.record(similarity);
+        }
+
+        assert_eq!(bands.counts, vec![2, 1, 1, 2]);
+        assert_eq!(bands.to_string(), ">=0.9=2 0.8-0.9=1 0.7-0.8=1 <0.7=2");
+        assert_eq!(SimilarityBands::new(&[]).to_string(), "");
+    }
+
     #[test]
-    fn prompt_produces_expected_string() {
-        let result = product_embedding_prompt(
-            "Sample Name",
-            "SKU123",
-            "Category",
-            "units",
-            9.99,
-            2.0,
-            "Description",
+    fn associations_are_capped_and_filled_by_similarity() {
+        let id = |id| ProductId::new(id).unwrap();
+        let passed = vec![(id(1), 0.85), (id(2), 0.95), (id(3), 0.9)];
+        let below = vec![(id(4), 0.5), (id(5), 0.7)];
+
+        assert_eq!(
+            select_associations(passed.clone(), below.clone(), 0, Some(2)),
+            (vec![(id(2), 0.95), (id(3), 0.9)], vec![], 1)
+        );
+        assert_eq!(
+            select_associations(passed, below.clone(), 0, None),
+            (vec![(id(2), 0.95), (id(3), 0.9), (id(1), 0.85)], vec![], 0)
         );
+        assert_eq!(
+            select_associations(vec![], below.clone(), 1, None),
+            (vec![], vec![(id(5), 0.7)], 0)
+        );
+        assert_eq!(
+            select_associations(vec![(id(1), 0.85)], below, 5, Some(2)),
+            (vec![(id(1), 0.85)], vec![(id(5), 0.7)], 0)
+        );
+    }
+
+    #[test]
+    fn panic_mid_run_clears_processing_and_propagates() {
+        let repo = ProcessingRecordingRepo::default();
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_with_benchmark_processing_guard(
+                benchmark_id(),
+                &context(),
+                &repo,
+                || -> Result<(), ()> { panic!("embedder crashed") },
+            )
+        }));
+
+        assert!(panicked.is_err());
+        assert_eq!(*repo.stats_updates.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn failed_claim_does_not_run_the_job() {
+        let repo = ProcessingRecordingRepo {
+            fail_claim: true,
+            ..Default::default()
+        };
+        let mut ran = false;
+
+        let outcome =
+            run_with_benchmark_processing_guard(benchmark_id(), &context(), &repo, || {
+                ran = true;
+                Ok(())
+            });
 
-        let expected = "Name: Sample Name\nSKU: SKU123\nCategory: Category\nUnits: units\nPrice: 9.99\nAmount: 2\nDescription: Description";
-        assert_eq!(result, expected);
+        assert_eq!(outcome, Err(()));
+        assert!(!ran);
+        assert_eq!(*repo.stats_updates.lock().unwrap(), 0);
     }
 }
@@ -1,15 +1,24 @@
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
-use pushkind_dantes::domain::types::HubId;
+use std::borrow::Cow;
+use std::thread;
 
-use crate::SIMILARITY_THRESHOLD;
+use pushkind_dantes::domain::types::{
+    CategoryId, CrawlerId, CrawlerSelectorValue, HubId, ProductId,
+};
+
+use crate::models::config::{CategoryLimitAction, CategoryMatchConfig};
 use crate::processing::embedding::{
-    load_or_generate_embedding, product_embedding_prompt, search_top_k,
+    EmbeddingIndex, INDEX_METRIC, SharedEmbedder, distance_to_similarity, is_zero_embedding,
+    load_or_generate_embedding, render_product_prompt, truncate_description,
 };
+use crate::processing::log_context::LogContext;
 use crate::repository::{
     CategoryReader, CategoryWriter, CrawlerReader, ProcessingGuardWriter, ProductCategoryWriter,
     ProductReader, ProductWriter,
 };
 
+/// Number of products embedded before their category search runs.
+const MATCH_CHUNK_SIZE: usize = 1024;
+
 /// Category prompt for category-directory embeddings.
 ///
 /// The feature spec requires category name only.
@@ -17,20 +26,189 @@ fn category_prompt(name: &str) -> String {
     name.to_string()
 }
 
-#[derive(Default)]
-struct MatchStats {
+/// Counters collected during a product-to-category matching run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MatchStats {
+    pub categories_loaded: usize,
+    pub products_loaded: usize,
+    pub category_embeddings_generated: usize,
+    pub product_embeddings_generated: usize,
+    pub matched: usize,
+    pub unmatched: usize,
+    pub skipped_below_threshold: usize,
+    pub skipped_invalid_category_id: usize,
+    pub skipped_no_category_candidate: usize,
+    /// Generated product embeddings that are all zeros and so match nothing.
+    pub zero_embeddings: usize,
+}
+
+/// Category picked for a product from its nearest candidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CategoryChoice {
+    Assigned(CategoryId),
+    BelowThreshold,
+    /// Every candidate above the threshold had a key that is not a category id.
+    InvalidCategoryId(u64),
+    NoCandidate,
+}
+
+/// Pick the closest candidate whose similarity reaches `similarity_threshold`.
+///
+/// `candidates` are `(key, distance)` pairs ordered by ascending distance, as
+/// returned by [`EmbeddingIndex::search`]. Candidates with invalid keys are passed over
+/// in favor of the next one.
+fn choose_category(candidates: &[(u64, f32)], similarity_threshold: f32) -> CategoryChoice {
+    let mut invalid_key = None;
+    for &(key, distance) in candidates {
+        if distance_to_similarity(INDEX_METRIC, distance) < similarity_threshold {
+            break;
+        }
+        match i32::try_from(key)
+            .ok()
+            .and_then(|id| CategoryId::new(id).ok())
+        {
+            Some(category_id) => return CategoryChoice::Assigned(category_id),
+            None => invalid_key = invalid_key.or(Some(key)),
+        }
+    }
+
+    match (invalid_key, candidates.is_empty()) {
+        (Some(key), _) => CategoryChoice::InvalidCategoryId(key),
+        (None, true) => CategoryChoice::NoCandidate,
+        (None, false) => CategoryChoice::BelowThreshold,
+    }
+}
+
+/// Choose a category for each product embedding, searching `index` from up
+/// to `workers` threads.
+///
+/// The index is only read, so the threads share it. Choices keep the order
+/// of `product_embeddings`; the caller counts them in [`MatchStats`], so the
+/// counters are never updated concurrently.
+fn match_categories(
+    index: &EmbeddingIndex,
+    product_embeddings: &[(ProductId, Cow<'_, [f32]>)],
+    top_k: usize,
+    similarity_threshold: f32,
+    workers: usize,
+    ctx: &LogContext,
+) -> Result<Vec<(ProductId, CategoryChoice)>, String> {
+    let chunk_size = product_embeddings.len().div_ceil(workers.max(1)).max(1);
+
+    thread::scope(|scope| {
+        let handles = product_embeddings
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(product_id, embedding)| {
+                            search_category(
+                                index,
+                                *product_id,
+                                embedding,
+                                top_k,
+                                similarity_threshold,
+                                ctx,
+                            )
+                        })
+                        .collect::<Result<Vec<_>, String>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut choices = Vec::with_capacity(product_embeddings.len());
+        for handle in handles {
+            let chunk_choices = handle
+                .join()
+                .map_err(|_| "Category search worker panicked".to_string())??;
+            choices.extend(chunk_choices);
+        }
+        Ok(choices)
+    })
+}
+
+/// Search the `top_k` categories nearest to a product embedding and choose
+/// one of them.
+fn search_category(
+    index: &EmbeddingIndex,
+    product_id: ProductId,
+    embedding: &[f32],
+    top_k: usize,
+    similarity_threshold: f32,
+    ctx: &LogContext,
+) -> Result<(ProductId, CategoryChoice), String> {
+    let candidates = index.search(embedding, top_k).map_err(|error| {
+        format!("Failed to run top-{top_k} category search for product {product_id}: {error:?}")
+    })?;
+    if candidates.len() > 1 {
+        log::debug!(
+            "{ctx} Category candidates for product {product_id} (id, similarity): {:?}",
+            candidates
+                .iter()
+                .map(|(key, distance)| (*key, distance_to_similarity(INDEX_METRIC, *distance)))
+                .collect::<Vec<_>>()
+        );
+    }
+    Ok((
+        product_id,
+        choose_category(&candidates, similarity_threshold),
+    ))
+}
+
+/// Enforce the configured cap on the number of hub categories.
+///
+/// Exceeding the cap is logged; the run is aborted only when the configured
+/// action is [`CategoryLimitAction::Abort`].
+fn check_category_limit(
+    hub_id: HubId,
     categories_loaded: usize,
-    products_loaded: usize,
-    category_embeddings_generated: usize,
-    product_embeddings_generated: usize,
-    matched: usize,
-    unmatched: usize,
-    skipped_below_threshold: usize,
-    skipped_invalid_category_id: usize,
-    skipped_no_category_candidate: usize,
+    config: &CategoryMatchConfig,
+) -> Result<(), ()> {
+    let Some(max_categories) = config.max_categories else {
+        return Ok(());
+    };
+
+    if categories_loaded <= max_categories {
+        return Ok(());
+    }
+
+    match config.max_categories_action {
+        CategoryLimitAction::Warn => {
+            log::warn!(
+                "{} Hub has {categories_loaded} categories, exceeding max_categories={max_categories}; continuing",
+                LogContext::hub(hub_id)
+            );
+            Ok(())
+        }
+        CategoryLimitAction::Abort => {
+            log::error!(
+                "{} Hub has {categories_loaded} categories, exceeding max_categories={max_categories}; aborting",
+                LogContext::hub(hub_id)
+            );
+            Err(())
+        }
+    }
 }
 
-fn process_product_category_match<R>(hub_id: HubId, repo: &R) -> Result<MatchStats, ()>
+/// Match products against the hub category directory.
+///
+/// With `crawler_id` only that crawler's products are matched, after its
+/// automatic assignments are cleared; otherwise every hub crawler is matched.
+///
+/// A product is assigned its nearest category only when their similarity
+/// reaches `similarity_threshold`; callers pass
+/// [`CategoryMatchConfig::similarity_threshold`], which defaults to
+/// [`crate::SIMILARITY_THRESHOLD`].
+fn process_product_category_match<R>(
+    hub_id: HubId,
+    crawler_id: Option<CrawlerId>,
+    repo: &R,
+    config: &CategoryMatchConfig,
+    embedder: &SharedEmbedder,
+    force_reembed: bool,
+    similarity_threshold: f32,
+) -> Result<MatchStats, ()>
 where
     R: CrawlerReader
         + ProductReader
@@ -40,32 +218,36 @@ where
         + ProductCategoryWriter,
 {
     let mut stats = MatchStats::default();
+    let ctx = LogContext::hub(hub_id);
 
-    let mut embedder =
-        match TextEmbedding::try_new(InitOptions::new(EmbeddingModel::MultilingualE5Large)) {
-            Ok(embedder) => embedder,
-            Err(error) => {
-                log::error!("Failed to initialize embedder for hub {hub_id}: {error:?}");
+    let crawler_ids = match crawler_id {
+        Some(crawler_id) => {
+            if let Err(error) = repo.clear_product_categories_by_crawler(crawler_id) {
+                log::error!(
+                    "{} Failed to clear product categories: {error:?}",
+                    ctx.clone().with("crawler_id", crawler_id)
+                );
                 return Err(());
             }
-        };
-
-    let crawlers = match repo.list_crawlers(hub_id) {
-        Ok(crawlers) => crawlers,
-        Err(error) => {
-            log::error!("Failed to list crawlers for hub {hub_id}: {error:?}");
-            return Err(());
+            vec![crawler_id]
         }
+        None => match repo.list_crawlers(hub_id) {
+            Ok(crawlers) => crawlers.into_iter().map(|crawler| crawler.id).collect(),
+            Err(error) => {
+                log::error!("{ctx} Failed to list crawlers: {error:?}");
+                return Err(());
+            }
+        },
     };
 
     let mut products = Vec::new();
-    for crawler in crawlers {
-        let crawler_products = match repo.list_products(crawler.id) {
+    for crawler_id in crawler_ids {
+        let crawler_products = match repo.list_products(crawler_id) {
             Ok(products) => products,
             Err(error) => {
                 log::error!(
-                    "Failed to list products for crawler {} in hub {hub_id}: {error:?}",
-                    crawler.id
+                    "{} Failed to list products: {error:?}",
+                    ctx.clone().with("crawler_id", crawler_id)
                 );
                 return Err(());
             }
@@ -78,14 +260,26 @@ where
     let categories = match repo.list_categories(hub_id) {
         Ok(categories) => categories,
         Err(error) => {
-            log::error!("Failed to list categories for hub {hub_id}: {error:?}");
+            log::error!("{ctx} Failed to list categories: {error:?}");
             return Err(());
         }
     };
     stats.categories_loaded = categories.len();
 
-    let mut category_embeddings: Vec<(i32, Vec<f32>)> = Vec::with_capacity(categories.len());
-    for category in categories {
+    check_category_limit(hub_id, stats.categories_loaded, config)?;
+
+    let prompt_template = embedder.prompt_template();
+    let description_max_words = embedder.description_max_words();
+    let mut embedder = match embedder.acquire() {
+        Ok(embedder) => embedder,
+        Err(error) => {
+            log::error!("{ctx} {error}");
+            return Err(());
+        }
+    };
+
+    let mut category_embeddings = Vec::with_capacity(categories.len());
+    for category in &categories {
         let category_text = category_prompt(category.name.as_str());
         let embedding = match load_or_generate_embedding(
             category.embedding.as_deref(),
@@ -96,21 +290,20 @@ where
                     .map(|_| ())
                     .map_err(|error| {
                         format!(
-                            "Failed to persist category embedding for {} in hub {hub_id}: {error:?}",
+                            "{ctx} Failed to persist category embedding for {}: {error:?}",
                             category.id
                         )
                     })
             },
+            force_reembed,
         ) {
-            Ok((embedding, generated)) => {
-                if generated {
-                    stats.category_embeddings_generated += 1;
-                }
-                embedding
+            Ok(outcome) => {
+                stats.category_embeddings_generated += usize::from(outcome.generated);
+                outcome.embedding
             }
             Err(error) => {
                 log::error!(
-                    "Failed to resolve category embedding for {} in hub {hub_id}: {error}",
+                    "{ctx} Failed to resolve category embedding for {}: {error}",
                     category.id
                 );
                 return Err(());
@@ -122,123 +315,158 @@ where
 
     if stats.categories_loaded == 0 && stats.products_loaded > 0 {
         log::warn!(
-            "No categories found for hub {hub_id}; all {} products will be set to NULL category_id",
+            "{ctx} No categories found; all {} products will be set to NULL category_id",
             stats.products_loaded
         );
     }
 
-    for product in products {
-        let product_text = product_embedding_prompt(
-            product.name.as_str(),
-            product.sku.as_str(),
-            product.category.as_deref().unwrap_or(""),
-            product.units.as_deref().unwrap_or(""),
-            product.price.get(),
-            product.amount.map(|value| value.get()).unwrap_or_default(),
-            product.description.as_deref().unwrap_or(""),
-        );
-        let product_embedding = match load_or_generate_embedding(
-            product.embedding.as_deref(),
-            product_text,
-            &mut embedder,
-            |value| {
-                repo.set_product_embedding(product.id, value)
-                    .map(|_| ())
-                    .map_err(|error| {
-                        format!(
-                            "Failed to persist product embedding for {} in hub {hub_id}: {error:?}",
+    let category_index = match EmbeddingIndex::build(&category_embeddings, embedder.dimensions()) {
+        Ok(index) => index,
+        Err(error) => {
+            log::error!("{ctx} Failed to build the category index: {error:?}");
+            return Err(());
+        }
+    };
+    let top_k = config.top_k.max(1);
+
+    // Embedding holds the embedder lock, so products are embedded one chunk
+    // at a time and each chunk is then searched in parallel.
+    let mut assignments = Vec::with_capacity(products.len());
+    for chunk in products.chunks(MATCH_CHUNK_SIZE) {
+        let mut product_embeddings = Vec::with_capacity(chunk.len());
+        for product in chunk {
+            let product_text = render_product_prompt(
+                prompt_template,
+                product.name.as_str(),
+                product.sku.as_str(),
+                product.category.as_deref().unwrap_or(""),
+                product.units.as_deref().unwrap_or(""),
+                product.price.get(),
+                product.amount.map(|value| value.get()).unwrap_or_default(),
+                truncate_description(
+                    product.description.as_deref().unwrap_or(""),
+                    description_max_words,
+                ),
+            );
+            let product_embedding = match load_or_generate_embedding(
+                product.embedding.as_deref(),
+                product_text,
+                &mut embedder,
+                |value| {
+                    repo.set_product_embedding(product.id, value)
+                        .map(|_| ())
+                        .map_err(|error| {
+                            format!(
+                                "{ctx} Failed to persist product embedding for {}: {error:?}",
+                                product.id
+                            )
+                        })
+                },
+                force_reembed,
+            ) {
+                Ok(outcome) => {
+                    stats.product_embeddings_generated += usize::from(outcome.generated);
+                    if outcome.generated && is_zero_embedding(&outcome.embedding) {
+                        stats.zero_embeddings += 1;
+                        log::warn!(
+                            "{ctx} Product {} got an all-zero embedding; check its name and description",
                             product.id
-                        )
-                    })
-            },
-        ) {
-            Ok((embedding, generated)) => {
-                if generated {
-                    stats.product_embeddings_generated += 1;
+                        );
+                    }
+                    outcome.embedding
                 }
-                embedding
-            }
+                Err(error) => {
+                    log::error!(
+                        "{ctx} Failed to resolve product embedding for {}: {error}",
+                        product.id
+                    );
+                    return Err(());
+                }
+            };
+            product_embeddings.push((product.id, product_embedding));
+        }
+
+        let choices = match match_categories(
+            &category_index,
+            &product_embeddings,
+            top_k,
+            similarity_threshold,
+            config.match_workers,
+            &ctx,
+        ) {
+            Ok(choices) => choices,
             Err(error) => {
-                log::error!(
-                    "Failed to resolve product embedding for {} in hub {hub_id}: {error}",
-                    product.id
-                );
+                log::error!("{ctx} {error}");
                 return Err(());
             }
         };
 
-        let assigned_category = match search_top_k(&product_embedding, &category_embeddings, 1) {
-            Ok(results) => match results.into_iter().next() {
-                Some((key, distance)) => {
-                    let similarity = 1.0 - distance;
-                    if similarity < SIMILARITY_THRESHOLD {
-                        stats.skipped_below_threshold += 1;
-                        None
-                    } else {
-                        match i32::try_from(key)
-                            .ok()
-                            .and_then(|id| pushkind_dantes::domain::types::CategoryId::new(id).ok())
-                        {
-                            Some(category_id) => Some(category_id),
-                            None => {
-                                stats.skipped_invalid_category_id += 1;
-                                log::warn!(
-                                    "Skipping invalid category id {key} from similarity index for product {}",
-                                    product.id
-                                );
-                                None
-                            }
-                        }
-                    }
+        for (product_id, choice) in choices {
+            let assigned_category = match choice {
+                CategoryChoice::Assigned(category_id) => Some(category_id),
+                CategoryChoice::BelowThreshold => {
+                    stats.skipped_below_threshold += 1;
+                    None
                 }
-                None => {
+                CategoryChoice::InvalidCategoryId(key) => {
+                    stats.skipped_invalid_category_id += 1;
+                    log::warn!(
+                        "{ctx} Skipping invalid category id {key} from similarity index for product {product_id}"
+                    );
+                    None
+                }
+                CategoryChoice::NoCandidate => {
                     stats.skipped_no_category_candidate += 1;
                     None
                 }
-            },
-            Err(error) => {
-                log::error!(
-                    "Failed to run top-1 category search for product {}: {error:?}",
-                    product.id
-                );
-                return Err(());
-            }
-        };
+            };
 
-        if let Err(error) = repo.set_product_category_automatic(product.id, assigned_category) {
-            log::error!(
-                "Failed to set product category assignment for product {} in hub {hub_id}: {error:?}",
-                product.id
-            );
-            return Err(());
+            if assigned_category.is_some() {
+                stats.matched += 1;
+            } else {
+                stats.unmatched += 1;
+            }
+            assignments.push((product_id, assigned_category));
         }
+    }
 
-        if assigned_category.is_some() {
-            stats.matched += 1;
-        } else {
-            stats.unmatched += 1;
-        }
+    if let Err(error) = repo.set_product_categories_automatic(&assignments) {
+        log::error!(
+            "{ctx} Failed to set category assignments for {} products: {error:?}",
+            assignments.len()
+        );
+        return Err(());
     }
 
     Ok(stats)
 }
 
-fn run_with_hub_processing_guard<R, F, T>(hub_id: HubId, repo: &R, job: F) -> Result<Option<T>, ()>
+/// Run `job` while holding the hub processing guard.
+///
+/// Returns `Ok(None)` when another run already holds the guard; `job_name`
+/// labels that skip in the log.
+pub(crate) fn run_with_hub_processing_guard<R, F, T>(
+    hub_id: HubId,
+    job_name: &str,
+    repo: &R,
+    job: F,
+) -> Result<Option<T>, ()>
 where
     R: ProcessingGuardWriter,
     F: FnOnce() -> Result<T, ()>,
 {
+    let ctx = LogContext::hub(hub_id);
     let claimed = match repo.claim_hub_processing_lock(hub_id) {
         Ok(value) => value,
         Err(error) => {
-            log::error!("Failed to claim processing guard for hub {hub_id}: {error:?}");
+            log::error!("{ctx} Failed to claim processing guard: {error:?}");
             return Err(());
         }
     };
 
     if !claimed {
         log::warn!(
-            "Skipping ProductCategoryMatch for hub {hub_id}: processing already active (skipped_because_processing_active=1)"
+            "{ctx} Skipping {job_name}: processing already active (skipped_because_processing_active=1)"
         );
         return Ok(None);
     }
@@ -246,7 +474,7 @@ where
     let outcome = job();
 
     if let Err(error) = repo.release_hub_processing_lock(hub_id) {
-        log::error!("Failed to release processing guard for hub {hub_id}: {error:?}");
+        log::error!("{ctx} Failed to release processing guard: {error:?}");
     }
 
     match outcome {
@@ -256,7 +484,47 @@ where
 }
 
 /// Handle product-to-category matching messages.
-pub async fn process_product_category_match_message<R>(hub_id: HubId, repo: R)
+///
+/// With `force_reembed` every category and product embedding of the hub is
+/// regenerated instead of reusing the stored one.
+///
+/// Returns the run's [`MatchStats`], or `None` when the run was skipped by
+/// the processing guard or failed; both cases are logged.
+pub async fn process_product_category_match_message<R>(
+    hub_id: HubId,
+    repo: R,
+    config: CategoryMatchConfig,
+    embedder: SharedEmbedder,
+    force_reembed: bool,
+) -> Option<MatchStats>
+where
+    R: CrawlerReader
+        + ProductReader
+        + ProductWriter
+        + CategoryReader
+        + CategoryWriter
+        + ProductCategoryWriter
+        + ProcessingGuardWriter,
+{
+    log::info!(
+        "{} Received ProductCategoryMatch (force_reembed={force_reembed})",
+        LogContext::hub(hub_id)
+    );
+
+    run_product_category_match(hub_id, None, &repo, &config, &embedder, force_reembed)
+}
+
+/// Handle product-to-category matching for a single crawler.
+///
+/// The crawler's products are rematched against all categories of its hub
+/// while products of other crawlers are left untouched. Returns the same as
+/// [`process_product_category_match_message`].
+pub async fn process_crawler_product_category_match_message<R>(
+    selector: CrawlerSelectorValue,
+    repo: R,
+    config: CategoryMatchConfig,
+    embedder: SharedEmbedder,
+) -> Option<MatchStats>
 where
     R: CrawlerReader
         + ProductReader
@@ -266,20 +534,75 @@ where
         + ProductCategoryWriter
         + ProcessingGuardWriter,
 {
-    log::info!("Received ProductCategoryMatch for hub {hub_id}");
+    log::info!(
+        "{} Received ProductCategoryMatch",
+        LogContext::default().with("selector", &selector)
+    );
+
+    let crawler = match repo.get_crawler(&selector) {
+        Ok(crawler) => crawler,
+        Err(error) => {
+            log::error!(
+                "{} Error retrieving crawler: {error}",
+                LogContext::default().with("selector", &selector)
+            );
+            return None;
+        }
+    };
 
-    let outcome = match run_with_hub_processing_guard(hub_id, &repo, || {
-        process_product_category_match(hub_id, &repo)
+    run_product_category_match(
+        crawler.hub_id,
+        Some(crawler.id),
+        &repo,
+        &config,
+        &embedder,
+        false,
+    )
+}
+
+/// Run a matching job under the hub processing guard and log its outcome.
+fn run_product_category_match<R>(
+    hub_id: HubId,
+    crawler_id: Option<CrawlerId>,
+    repo: &R,
+    config: &CategoryMatchConfig,
+    embedder: &SharedEmbedder,
+    force_reembed: bool,
+) -> Option<MatchStats>
+where
+    R: CrawlerReader
+        + ProductReader
+        + ProductWriter
+        + CategoryReader
+        + CategoryWriter
+        + ProductCategoryWriter
+        + ProcessingGuardWriter,
+{
+    let ctx = match crawler_id {
+        Some(crawler_id) => LogContext::hub(hub_id).with("crawler_id", crawler_id),
+        None => LogContext::hub(hub_id),
+    };
+
+    let outcome = match run_with_hub_processing_guard(hub_id, "ProductCategoryMatch", repo, || {
+        process_product_category_match(
+            hub_id,
+            crawler_id,
+            repo,
+            config,
+            embedder,
+            force_reembed,
+            config.similarity_threshold,
+        )
     }) {
         Ok(Some(stats)) => Ok(stats),
-        Ok(None) => return,
+        Ok(None) => return None,
         Err(()) => Err(()),
     };
 
     match outcome {
         Ok(stats) => {
             log::info!(
-                "Finished ProductCategoryMatch for hub {hub_id}: categories_loaded={}, products_loaded={}, category_embeddings_generated={}, product_embeddings_generated={}, matched={}, unmatched={}, skipped_below_threshold={}, skipped_invalid_category_id={}, skipped_no_category_candidate={}",
+                "{ctx} Finished ProductCategoryMatch: categories_loaded={}, products_loaded={}, category_embeddings_generated={}, product_embeddings_generated={}, matched={}, unmatched={}, skipped_below_threshold={}, skipped_invalid_category_id={}, skipped_no_category_candidate={}, zero_embeddings={}",
                 stats.categories_loaded,
                 stats.products_loaded,
                 stats.category_embeddings_generated,
@@ -288,35 +611,59 @@ where
                 stats.unmatched,
                 stats.skipped_below_threshold,
                 stats.skipped_invalid_category_id,
-                stats.skipped_no_category_candidate
+                stats.skipped_no_category_candidate,
+                stats.zero_embeddings
             );
             if stats.skipped_below_threshold > 0
                 || stats.skipped_invalid_category_id > 0
                 || stats.skipped_no_category_candidate > 0
             {
                 log::warn!(
-                    "ProductCategoryMatch for hub {hub_id} had skipped assignments: below_threshold={}, invalid_category_id={}, no_candidate={}",
+                    "{ctx} ProductCategoryMatch had skipped assignments: below_threshold={} (threshold {}), invalid_category_id={}, no_candidate={}",
                     stats.skipped_below_threshold,
+                    config.similarity_threshold,
                     stats.skipped_invalid_category_id,
                     stats.skipped_no_category_candidate
                 );
             }
+            Some(stats)
         }
         Err(()) => {
-            log::error!("ProductCategoryMatch failed for hub {hub_id}");
+            log::error!("{ctx} ProductCategoryMatch failed");
+            None
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
     use std::sync::Mutex;
+    use std::time::Duration;
 
+    use chrono::{NaiveDateTime, Utc};
     use pushkind_common::repository::errors::RepositoryResult;
-    use pushkind_dantes::domain::types::HubId;
+    use pushkind_dantes::domain::category::Category;
+    use pushkind_dantes::domain::crawler::Crawler;
+    use pushkind_dantes::domain::product::{NewProduct, Product};
+    use pushkind_dantes::domain::types::{
+        CategoryId, CategoryName, CrawlerId, CrawlerSelectorValue, HubId, ProductId,
+    };
 
-    use super::{category_prompt, run_with_hub_processing_guard};
-    use crate::repository::ProcessingGuardWriter;
+    use super::{
+        CategoryChoice, category_prompt, check_category_limit, choose_category, match_categories,
+        process_product_category_match, run_with_hub_processing_guard,
+    };
+    use crate::SIMILARITY_THRESHOLD;
+    use crate::models::config::{CategoryLimitAction, CategoryMatchConfig, EmbeddingModelName};
+    use crate::processing::embedding::{EmbeddingIndex, SharedEmbedder};
+    use crate::processing::log_context::LogContext;
+    use crate::repository::{
+        CategoryReader, CategoryWriter, CrawlerReader, ProcessingGuardWriter, ProductCategoryCount,
+        ProductCategoryWriter, ProductReader, ProductSort, ProductSummary, ProductWriter,
+        StoredProductEmbedding,
+    };
 
     #[derive(Default)]
     struct GuardState {
@@ -420,6 +767,390 @@ mod tests {
         }
     }
 
+    /// Fake repository serving a fixed category directory and no products.
+    struct FakeMatchRepo {
+        categories: Vec<Category>,
+        category_assignments: Mutex<usize>,
+        listed_product_crawlers: Mutex<Vec<i32>>,
+        cleared_crawlers: Mutex<Vec<i32>>,
+    }
+
+    impl FakeMatchRepo {
+        fn with_categories(count: i32) -> Self {
+            let hub_id = HubId::new(1).expect("valid hub id");
+            let categories = (1..=count)
+                .map(|id| Category {
+                    id: CategoryId::new(id).expect("valid category id"),
+                    hub_id,
+                    name: CategoryName::new(format!("Category {id}")).expect("valid name"),
+                    embedding: Some(bytemuck::cast_slice(&[1.0_f32, 0.0]).to_vec()),
+                    created_at: Utc::now().naive_utc(),
+                    updated_at: Utc::now().naive_utc(),
+                })
+                .collect();
+            Self {
+                categories,
+                category_assignments: Mutex::new(0),
+                listed_product_crawlers: Mutex::new(Vec::new()),
+                cleared_crawlers: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn category_assignments(&self) -> usize {
+            *self
+                .category_assignments
+                .lock()
+                .expect("assignments mutex poisoned")
+        }
+    }
+
+    impl CrawlerReader for FakeMatchRepo {
+        fn get_crawler(&self, _selector: &CrawlerSelectorValue) -> RepositoryResult<Crawler> {
+            Err(pushkind_common::repository::errors::RepositoryError::NotFound)
+        }
+
+        fn list_crawlers(&self, _hub_id: HubId) -> RepositoryResult<Vec<Crawler>> {
+            Ok(vec![])
+        }
+
+        fn list_due_crawlers(
+            &self,
+            _now: NaiveDateTime,
+            _intervals: &HashMap<String, Duration>,
+        ) -> RepositoryResult<Vec<Crawler>> {
+            Ok(vec![])
+        }
+    }
+
+    impl ProductReader for FakeMatchRepo {
+        fn list_products(&self, crawler_id: CrawlerId) -> RepositoryResult<Vec<Product>> {
+            self.listed_product_crawlers
+                .lock()
+                .expect("crawlers mutex poisoned")
+                .push(crawler_id.get());
+            Ok(vec![])
+        }
+
+        fn get_product_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _url: &str,
+        ) -> RepositoryResult<Option<Product>> {
+            Ok(None)
+        }
+
+        fn count_products(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn count_hub_products(&self, _hub_id: HubId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn list_distinct_product_categories(
+            &self,
+            _hub_id: HubId,
+        ) -> RepositoryResult<Vec<ProductCategoryCount>> {
+            Ok(vec![])
+        }
+
+        fn list_products_without_embedding(
+            &self,
+            _crawler_id: CrawlerId,
+        ) -> RepositoryResult<Vec<Product>> {
+            Ok(vec![])
+        }
+
+        fn list_products_updated_since(
+            &self,
+            _crawler_id: CrawlerId,
+            _since: NaiveDateTime,
+        ) -> RepositoryResult<Vec<Product>> {
+            Ok(vec![])
+        }
+
+        fn list_products_sorted(
+            &self,
+            _crawler_id: CrawlerId,
+            _sort: ProductSort,
+            _desc: bool,
+            _limit: i64,
+            _offset: i64,
+        ) -> RepositoryResult<Vec<Product>> {
+            Ok(vec![])
+        }
+
+        fn list_product_summaries(
+            &self,
+            _crawler_id: CrawlerId,
+            _limit: i64,
+            _offset: i64,
+        ) -> RepositoryResult<Vec<ProductSummary>> {
+            Ok(vec![])
+        }
+
+        fn list_product_embeddings(
+            &self,
+            _crawler_id: CrawlerId,
+        ) -> RepositoryResult<Vec<StoredProductEmbedding>> {
+            Ok(vec![])
+        }
+    }
+
+    impl ProductWriter for FakeMatchRepo {
+        fn create_products(&self, products: &[NewProduct]) -> RepositoryResult<usize> {
+            Ok(products.len())
+        }
+
+        fn update_products(&self, products: &[NewProduct]) -> RepositoryResult<usize> {
+            Ok(products.len())
+        }
+
+        fn upsert_product(&self, _product: &NewProduct) -> RepositoryResult<ProductId> {
+            Ok(ProductId::new(1).unwrap())
+        }
+
+        fn set_product_embedding(
+            &self,
+            _product_id: ProductId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+
+        fn set_product_embeddings_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _embeddings: &[(String, Vec<u8>)],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_products(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_product_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _url: &str,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn replace_products(
+            &self,
+            _crawler_id: CrawlerId,
+            products: &[NewProduct],
+        ) -> RepositoryResult<usize> {
+            Ok(products.len())
+        }
+
+        fn delete_products_updated_before(
+            &self,
+            _crawler_id: CrawlerId,
+            _cutoff: NaiveDateTime,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+    }
+
+    impl CategoryReader for FakeMatchRepo {
+        fn list_categories(&self, _hub_id: HubId) -> RepositoryResult<Vec<Category>> {
+            Ok(self.categories.clone())
+        }
+    }
+
+    impl CategoryWriter for FakeMatchRepo {
+        fn set_category_embedding(
+            &self,
+            _category_id: CategoryId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+    }
+
+    impl ProductCategoryWriter for FakeMatchRepo {
+        fn set_product_category_automatic(
+            &self,
+            _product_id: ProductId,
+            _category_id: Option<CategoryId>,
+        ) -> RepositoryResult<usize> {
+            let mut assignments = self
+                .category_assignments
+                .lock()
+                .expect("assignments mutex poisoned");
+            *assignments += 1;
+            Ok(1)
+        }
+
+        fn set_product_categories_automatic(
+            &self,
+            assignments: &[(ProductId, Option<CategoryId>)],
+        ) -> RepositoryResult<usize> {
+            let mut count = self
+                .category_assignments
+                .lock()
+                .expect("assignments mutex poisoned");
+            *count += assignments.len();
+            Ok(assignments.len())
+        }
+
+        fn clear_product_categories_by_crawler(
+            &self,
+            crawler_id: CrawlerId,
+        ) -> RepositoryResult<usize> {
+            self.cleared_crawlers
+                .lock()
+                .expect("crawlers mutex poisoned")
+                .push(crawler_id.get());
+            Ok(0)
+        }
+
+        fn reset_product_category_source(&self, _product_id: ProductId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn category_limit_aborts_match_when_configured() {
+        let repo = FakeMatchRepo::with_categories(3);
+        let hub_id = HubId::new(1).expect("valid hub id");
+        let config = CategoryMatchConfig {
+            max_categories: Some(2),
+            max_categories_action: CategoryLimitAction::Abort,
+            ..Default::default()
+        };
+
+        let result = process_product_category_match(
+            hub_id,
+            None,
+            &repo,
+            &config,
+            &SharedEmbedder::new(EmbeddingModelName::default()),
+            false,
+            SIMILARITY_THRESHOLD,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(repo.category_assignments(), 0);
+    }
+
+    #[test]
+    fn crawler_scoped_match_only_touches_that_crawler() {
+        let repo = FakeMatchRepo::with_categories(3);
+        let hub_id = HubId::new(1).expect("valid hub id");
+        let crawler_id = CrawlerId::new(5).expect("valid crawler id");
+        // Abort on the category cap to stop before the embedder is loaded.
+        let config = CategoryMatchConfig {
+            max_categories: Some(2),
+            max_categories_action: CategoryLimitAction::Abort,
+            ..Default::default()
+        };
+
+        let result = process_product_category_match(
+            hub_id,
+            Some(crawler_id),
+            &repo,
+            &config,
+            &SharedEmbedder::new(EmbeddingModelName::default()),
+            false,
+            SIMILARITY_THRESHOLD,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(*repo.cleared_crawlers.lock().unwrap(), vec![5]);
+        assert_eq!(*repo.listed_product_crawlers.lock().unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn category_limit_warns_and_continues_by_default() {
+        let hub_id = HubId::new(1).expect("valid hub id");
+        let config = CategoryMatchConfig {
+            max_categories: Some(2),
+            ..Default::default()
+        };
+
+        assert!(check_category_limit(hub_id, 3, &config).is_ok());
+    }
+
+    #[test]
+    fn category_limit_is_disabled_without_cap() {
+        let hub_id = HubId::new(1).expect("valid hub id");
+        let config = CategoryMatchConfig {
+            max_categories_action: CategoryLimitAction::Abort,
+            ..Default::default()
+        };
+
+        assert!(check_category_limit(hub_id, 10_000, &config).is_ok());
+    }
+
+    #[test]
+    fn choose_category_picks_closest_candidate_above_threshold() {
+        let choice = choose_category(&[(u64::MAX, 0.05), (7, 0.15), (9, 0.18)], 0.8);
+
+        assert_eq!(
+            choice,
+            CategoryChoice::Assigned(CategoryId::new(7).expect("valid category id"))
+        );
+    }
+
+    #[test]
+    fn parallel_category_search_matches_a_single_worker_in_order() {
+        let categories = vec![
+            (1, vec![1.0_f32, 0.0, 0.0]),
+            (2, vec![0.0, 1.0, 0.0]),
+            (3, vec![0.0, 0.0, 1.0]),
+        ];
+        let index = EmbeddingIndex::build(&categories, 3).expect("index should build");
+        let product_embeddings = (1..=10)
+            .map(|id| {
+                let mut embedding = vec![0.1_f32; 3];
+                embedding[id as usize % 3] = 1.0;
+                (ProductId::new(id).unwrap(), Cow::Owned(embedding))
+            })
+            .collect::<Vec<_>>();
+        let ctx = LogContext::hub(HubId::new(1).unwrap());
+
+        let sequential = match_categories(&index, &product_embeddings, 1, 0.8, 1, &ctx).unwrap();
+        let parallel = match_categories(&index, &product_embeddings, 1, 0.8, 4, &ctx).unwrap();
+
+        assert_eq!(parallel, sequential);
+        assert_eq!(
+            parallel
+                .iter()
+                .map(|(product_id, _)| product_id.get())
+                .collect::<Vec<_>>(),
+            (1..=10).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            parallel[0].1,
+            CategoryChoice::Assigned(CategoryId::new(2).unwrap())
+        );
+    }
+
+    #[test]
+    fn choose_category_reports_why_nothing_was_assigned() {
+        assert_eq!(
+            choose_category(&[(7, 0.3), (9, 0.35)], 0.8),
+            CategoryChoice::BelowThreshold
+        );
+        assert_eq!(
+            choose_category(&[(u64::MAX, 0.1), (7, 0.3)], 0.8),
+            CategoryChoice::InvalidCategoryId(u64::MAX)
+        );
+        assert_eq!(choose_category(&[], 0.8), CategoryChoice::NoCandidate);
+    }
+
+    #[test]
+    fn category_threshold_defaults_to_shared_threshold() {
+        assert_eq!(
+            CategoryMatchConfig::default().similarity_threshold,
+            SIMILARITY_THRESHOLD
+        );
+    }
+
     #[test]
     fn category_prompt_uses_category_name_only() {
         assert_eq!(category_prompt("Green Tea"), "Green Tea");
@@ -430,7 +1161,7 @@ mod tests {
         let repo = FakeGuardRepo::with_state(Some(false), false);
         let hub_id = HubId::new(1).expect("valid hub id");
 
-        let result = run_with_hub_processing_guard(hub_id, &repo, || Ok(()));
+        let result = run_with_hub_processing_guard(hub_id, "test", &repo, || Ok(()));
 
         assert!(matches!(result, Ok(None)));
         assert_eq!(repo.events(), vec!["claim_hub_processing_lock".to_string()]);
@@ -442,7 +1173,7 @@ mod tests {
         let repo = FakeGuardRepo::with_state(Some(true), false);
         let hub_id = HubId::new(1).expect("valid hub id");
 
-        let result = run_with_hub_processing_guard(hub_id, &repo, || {
+        let result = run_with_hub_processing_guard(hub_id, "test", &repo, || {
             repo.mark("job_started");
             assert_eq!(repo.flags(), (true, true));
             Ok("ok")
@@ -465,10 +1196,11 @@ mod tests {
         let repo = FakeGuardRepo::with_state(Some(true), false);
         let hub_id = HubId::new(1).expect("valid hub id");
 
-        let result: Result<Option<()>, ()> = run_with_hub_processing_guard(hub_id, &repo, || {
-            repo.mark("job_started");
-            Err(())
-        });
+        let result: Result<Option<()>, ()> =
+            run_with_hub_processing_guard(hub_id, "test", &repo, || {
+                repo.mark("job_started");
+                Err(())
+            });
 
         assert!(matches!(result, Err(())));
         assert_eq!(repo.flags(), (false, false));
@@ -487,7 +1219,7 @@ mod tests {
         let repo = FakeGuardRepo::with_state(None, false);
         let hub_id = HubId::new(1).expect("valid hub id");
 
-        let result = run_with_hub_processing_guard(hub_id, &repo, || Ok(()));
+        let result = run_with_hub_processing_guard(hub_id, "test", &repo, || Ok(()));
 
         assert!(matches!(result, Err(())));
         assert_eq!(repo.flags(), (false, false));
@@ -499,7 +1231,7 @@ mod tests {
         let repo = FakeGuardRepo::with_state(Some(true), true);
         let hub_id = HubId::new(1).expect("valid hub id");
 
-        let result = run_with_hub_processing_guard(hub_id, &repo, || Ok("ok"));
+        let result = run_with_hub_processing_guard(hub_id, "test", &repo, || Ok("ok"));
 
         assert!(matches!(result, Ok(Some("ok"))));
         assert_eq!(repo.flags(), (true, true));
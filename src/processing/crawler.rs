@@ -1,23 +1,266 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{Timelike, Utc};
 use futures::future;
+use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
+use pushkind_dantes::domain::product::NewProduct;
+use pushkind_dantes::domain::types::CrawlerId;
 use pushkind_dantes::domain::zmq::CrawlerSelector;
+use tokio::sync::mpsc;
 
-use crate::crawlers::WebstoreCrawler;
 use crate::crawlers::gutenberg::WebstoreCrawlerGutenberg;
+use crate::crawlers::query::QueryFilter;
 use crate::crawlers::rusteaco::WebstoreCrawlerRusteaco;
+use crate::crawlers::sitemap::SitemapCrawler;
 use crate::crawlers::tea101::WebstoreCrawler101Tea;
 use crate::crawlers::teanadin::WebstoreCrawlerTeanadin;
 use crate::crawlers::wintergreen::WebstoreCrawlerWintergreen;
+use crate::crawlers::{
+    CrawlLimits, CrawlProgress, CrawlStats, CrawlerError, CrawlerResult, HttpCredentials,
+    ProductPage, SeenProducts, WebstoreCrawler,
+};
+use crate::models::config::{CrawlerConfig, FullCrawlPolicy};
+use crate::processing::embedding::product_embedding_prompt;
+use crate::processing::export::export_products;
+use crate::processing::log_context::LogContext;
 use crate::repository::CrawlerReader;
 use crate::repository::CrawlerWriter;
+use crate::repository::ProductReader;
 use crate::repository::ProductWriter;
+use crate::repository::StoredProductEmbedding;
+
+/// Parsed product pages buffered between a streaming crawl and the writer.
+const STREAM_CHANNEL_CAPACITY: usize = 64;
+
+/// Product pages requested concurrently by a streaming crawl; site crawlers
+/// additionally apply their own request limits.
+const STREAM_CONCURRENCY: usize = 16;
+
+/// Selectors of the site crawlers [`process_crawler_message`] can run.
+const SUPPORTED_CRAWLERS: &[&str] = &["rusteaco", "101tea", "gutenberg", "teanadin", "wintergreen"];
+
+/// Returns the crawler selectors this crate supports, e.g. to offer them in
+/// a UI; other selectors are rejected as unknown.
+pub fn supported_crawlers() -> &'static [&'static str] {
+    SUPPORTED_CRAWLERS
+}
+
+/// Crawl intervals of the crawlers whose config sets `crawl_interval_secs`,
+/// keyed by selector as [`CrawlerReader::list_due_crawlers`] expects them.
+pub fn crawl_intervals(
+    crawlers_config: &HashMap<String, CrawlerConfig>,
+) -> HashMap<String, Duration> {
+    crawlers_config
+        .iter()
+        .filter_map(|(selector, config)| {
+            let secs = config.crawl_interval_secs?;
+            Some((selector.clone(), Duration::from_secs(secs)))
+        })
+        .collect()
+}
+
+/// Creates the site crawler a run uses, so [`process_crawler_message_with`]
+/// can run other crawlers than the built-in ones, e.g. a stub in tests.
+pub trait CrawlerFactory: Send + Sync {
+    /// Selectors this factory creates crawlers for, listed when an unknown
+    /// one is requested.
+    fn supported(&self) -> &[&str];
+
+    /// Creates the crawler registered under `selector` for the crawler row
+    /// `crawler_id`, or `None` for an unknown selector.
+    fn build(
+        &self,
+        selector: &str,
+        crawler_id: i32,
+        config: &CrawlerConfig,
+    ) -> CrawlerResult<Option<Box<dyn WebstoreCrawler + Send + Sync>>>;
+}
+
+/// The built-in site crawlers, keyed by the selectors of
+/// [`supported_crawlers`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SiteCrawlers;
+
+impl CrawlerFactory for SiteCrawlers {
+    fn supported(&self) -> &[&str] {
+        SUPPORTED_CRAWLERS
+    }
+
+    fn build(
+        &self,
+        selector: &str,
+        crawler_id: i32,
+        config: &CrawlerConfig,
+    ) -> CrawlerResult<Option<Box<dyn WebstoreCrawler + Send + Sync>>> {
+        build_site_crawler(selector, crawler_id, config)
+    }
+}
+
+/// Request settings of a crawler derived from its [`CrawlerConfig`], shared
+/// by the site crawler and a wrapping sitemap crawler.
+struct CrawlSettings {
+    request_delay: Duration,
+    query_filter: QueryFilter,
+    limits: CrawlLimits,
+    credentials: HttpCredentials,
+}
+
+impl CrawlSettings {
+    fn from_config(config: &CrawlerConfig) -> Self {
+        Self {
+            request_delay: Duration::from_millis(config.request_delay_ms),
+            query_filter: QueryFilter::new(
+                config.strip_query_params.clone(),
+                config.keep_query_params.clone(),
+            ),
+            limits: CrawlLimits {
+                max_pages: config.max_pages,
+                max_products: config.max_products,
+                max_categories: config.max_categories,
+                category_timeout: config
+                    .category_discovery_timeout_secs
+                    .map(Duration::from_secs),
+            },
+            credentials: HttpCredentials {
+                basic_auth: config.basic_auth_username.clone().map(|username| {
+                    (
+                        username,
+                        config.basic_auth_password.clone().unwrap_or_default(),
+                    )
+                }),
+                cookie: config.cookie.clone(),
+                danger_accept_invalid_certs: config.danger_accept_invalid_certs,
+            },
+        }
+    }
+}
+
+/// Creates the site crawler registered under `selector`, or `None` for an
+/// unknown selector; [`SUPPORTED_CRAWLERS`] lists the known ones.
+///
+/// Fails when the configured selector overrides are unknown or invalid CSS,
+/// or the entry URL is invalid or on another host.
+fn build_site_crawler(
+    selector: &str,
+    crawler_id: i32,
+    config: &CrawlerConfig,
+) -> CrawlerResult<Option<Box<dyn WebstoreCrawler + Send + Sync>>> {
+    let CrawlSettings {
+        request_delay,
+        query_filter,
+        limits,
+        credentials,
+    } = CrawlSettings::from_config(config);
+    let web_crawler: Box<dyn WebstoreCrawler + Send + Sync> = match selector {
+        "rusteaco" => Box::new(
+            WebstoreCrawlerRusteaco::new(5, crawler_id)?
+                .with_selectors(&config.selectors)?
+                .with_entry_url(config.entry_url.as_deref())?
+                .with_debug_html_dir(config.debug_html_dir.as_ref().map(PathBuf::from))
+                .with_skip_unavailable(config.skip_unavailable)
+                .with_credentials(&credentials)?
+                .with_request_delay(request_delay)
+                .with_query_filter(query_filter.clone())
+                .with_limits(limits)
+                .with_category_urls(config.category_urls.clone()),
+        ),
+        "101tea" => Box::new(
+            WebstoreCrawler101Tea::new(5, crawler_id)?
+                .with_selectors(&config.selectors)?
+                .with_entry_url(config.entry_url.as_deref())?
+                .with_debug_html_dir(config.debug_html_dir.as_ref().map(PathBuf::from))
+                .with_skip_unavailable(config.skip_unavailable)
+                .with_credentials(&credentials)?
+                .with_request_delay(request_delay)
+                .with_query_filter(query_filter.clone())
+                .with_limits(limits)
+                .with_category_urls(config.category_urls.clone()),
+        ),
+        "gutenberg" => Box::new(
+            WebstoreCrawlerGutenberg::new(5, crawler_id)?
+                .with_selectors(&config.selectors)?
+                .with_entry_url(config.entry_url.as_deref())?
+                .with_debug_html_dir(config.debug_html_dir.as_ref().map(PathBuf::from))
+                .with_skip_unavailable(config.skip_unavailable)
+                .with_credentials(&credentials)?
+                .with_request_delay(request_delay)
+                .with_query_filter(query_filter.clone())
+                .with_limits(limits)
+                .with_category_urls(config.category_urls.clone()),
+        ),
+        "teanadin" => Box::new(
+            WebstoreCrawlerTeanadin::new(1, crawler_id)?
+                .with_selectors(&config.selectors)?
+                .with_entry_url(config.entry_url.as_deref())?
+                .with_debug_html_dir(config.debug_html_dir.as_ref().map(PathBuf::from))
+                .with_skip_unavailable(config.skip_unavailable)
+                .with_credentials(&credentials)?
+                .with_request_delay(request_delay)
+                .with_query_filter(query_filter.clone())
+                .with_limits(limits)
+                .with_category_urls(config.category_urls.clone()),
+        ),
+        "wintergreen" => Box::new(
+            WebstoreCrawlerWintergreen::new(1, crawler_id)?
+                .with_selectors(&config.selectors)?
+                .with_entry_url(config.entry_url.as_deref())?
+                .with_debug_html_dir(config.debug_html_dir.as_ref().map(PathBuf::from))
+                .with_skip_unavailable(config.skip_unavailable)
+                .with_credentials(&credentials)?
+                .with_request_delay(request_delay)
+                .with_query_filter(query_filter.clone())
+                .with_limits(limits)
+                .with_category_urls(config.category_urls.clone()),
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(web_crawler))
+}
 
 /// Processes a message for a specific crawler and either refreshes all of its
-/// products or updates a subset. When no product URLs are provided, existing
-/// items are cleared and the crawler fetches all products anew. If URLs are
-/// supplied, only those products are retrieved and updated in the repository.
-pub async fn process_crawler_message<R>(msg: CrawlerSelector, repo: R)
+/// products or updates a subset. When no product URLs are provided, the
+/// crawler fetches all products anew and they replace the existing items,
+/// unless the crawl came back empty or suspiciously small (see
+/// [`is_catalog_shrink_suspicious`]). With `category_urls` configured, only
+/// those categories are crawled and their products are upserted, keeping the
+/// rest of the catalog; [`FullCrawlPolicy::Merge`] upserts the same way and
+/// keeps products the crawl did not see. If URLs are supplied, only those products are
+/// retrieved and updated in the repository.
+///
+/// `crawlers_config` holds per-selector settings; selectors without an entry
+/// use the defaults.
+///
+/// Crawling is bounded by the selector's `crawl_timeout_secs`; a run that
+/// exceeds it writes no catalog replacement or deletion and releases the
+/// crawler (see [`crawl_within_deadline`]).
+///
+/// Returns the run's [`CrawlStats`]. A run that did not start, timed out or
+/// failed to write its products returns the [`CrawlerError`] instead; once
+/// the crawler was claimed, its `processing` flag is cleared on every path.
+pub async fn process_crawler_message<R>(
+    msg: CrawlerSelector,
+    repo: R,
+    crawlers_config: HashMap<String, CrawlerConfig>,
+) -> CrawlerResult<CrawlStats>
 where
-    R: CrawlerReader + CrawlerWriter + ProductWriter,
+    R: CrawlerReader + CrawlerWriter + ProductReader + ProductWriter,
+{
+    process_crawler_message_with(msg, repo, crawlers_config, &SiteCrawlers).await
+}
+
+/// Like [`process_crawler_message`], but creates the site crawler with
+/// `factory` instead of [`SiteCrawlers`], e.g. to run the orchestration
+/// against a stub crawler.
+pub async fn process_crawler_message_with<R>(
+    msg: CrawlerSelector,
+    repo: R,
+    crawlers_config: HashMap<String, CrawlerConfig>,
+    factory: &dyn CrawlerFactory,
+) -> CrawlerResult<CrawlStats>
+where
+    R: CrawlerReader + CrawlerWriter + ProductReader + ProductWriter,
 {
     log::info!("Received crawler: {msg:?}");
 
@@ -29,58 +272,1030 @@ where
     let crawler = match repo.get_crawler(&selector) {
         Ok(crawler) => crawler,
         Err(e) => {
-            log::error!("Error retrieving selector: {e}");
-            return;
+            log::error!(
+                "{} Error retrieving crawler: {e}",
+                LogContext::default().with("selector", &selector)
+            );
+            return Err(e.into());
         }
     };
 
-    if crawler.processing {
-        log::warn!("Crawler {selector} is already running");
-        return;
+    let ctx = LogContext::crawler(&selector, crawler.id, crawler.hub_id);
+
+    let crawler_config = crawlers_config
+        .get(selector.as_str())
+        .cloned()
+        .unwrap_or_default();
+    let CrawlSettings {
+        request_delay,
+        query_filter,
+        limits,
+        credentials,
+    } = CrawlSettings::from_config(&crawler_config);
+    if credentials.danger_accept_invalid_certs {
+        log::warn!(
+            "{ctx} TLS certificate verification is DISABLED (danger_accept_invalid_certs); never enable it for production stores"
+        );
     }
 
-    let web_crawler: Box<dyn WebstoreCrawler + Send + Sync> = match selector.as_str() {
-        "rusteaco" => Box::new(WebstoreCrawlerRusteaco::new(5, crawler.id.get()).unwrap()),
-        "101tea" => Box::new(WebstoreCrawler101Tea::new(5, crawler.id.get()).unwrap()),
-        "gutenberg" => Box::new(WebstoreCrawlerGutenberg::new(5, crawler.id.get()).unwrap()),
-        "teanadin" => Box::new(WebstoreCrawlerTeanadin::new(1, crawler.id.get()).unwrap()),
-        "wintergreen" => Box::new(WebstoreCrawlerWintergreen::new(1, crawler.id.get()).unwrap()),
-        _ => {
-            log::error!("Unknown crawler: {selector}");
-            return;
+    let web_crawler = match factory.build(selector.as_str(), crawler.id.get(), &crawler_config) {
+        Ok(Some(web_crawler)) => web_crawler,
+        Ok(None) => {
+            log::error!(
+                "{ctx} Unknown crawler; supported crawlers: {}",
+                factory.supported().join(", ")
+            );
+            return Err(CrawlerError::UnknownCrawler(selector.as_str().to_string()));
+        }
+        Err(e) => {
+            log::error!("{ctx} Failed to create crawler: {e}");
+            return Err(e);
         }
     };
 
-    if let Err(e) = repo.set_crawler_processing(crawler.id, true) {
-        log::error!("Failed to set crawler processing: {e:?}");
+    let web_crawler: Box<dyn WebstoreCrawler + Send + Sync> =
+        match crawler_config.sitemap_url.as_deref() {
+            Some(sitemap_url) => match SitemapCrawler::new(
+                1,
+                sitemap_url,
+                &crawler_config.sitemap_path_prefix,
+                web_crawler,
+            )
+            .and_then(|sitemap_crawler| sitemap_crawler.with_credentials(&credentials))
+            {
+                Ok(sitemap_crawler) => Box::new(
+                    sitemap_crawler
+                        .with_request_delay(request_delay)
+                        .with_query_filter(query_filter.clone())
+                        .with_limits(limits),
+                ),
+                Err(e) => {
+                    log::error!("{ctx} Failed to create sitemap crawler: {e}");
+                    return Err(e);
+                }
+            },
+            None => web_crawler,
+        };
+
+    // Claiming the crawler is a compare-and-set, so of two identical
+    // requests arriving together only one proceeds.
+    match repo.set_crawler_processing(crawler.id, true) {
+        Ok(0) => {
+            log::warn!("{ctx} Crawler is already running");
+            return Err(CrawlerError::AlreadyRunning);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("{ctx} Failed to set crawler processing: {e:?}");
+            return Err(e.into());
+        }
+    }
+
+    let deadline = Duration::from_secs(crawler_config.crawl_timeout_secs);
+
+    // A full run limited to some categories did not see the products of the
+    // others, so it upserts instead of replacing the catalog.
+    let has_categories = !crawler_config.category_urls.is_empty();
+    let scoped = has_categories && crawler_config.sitemap_url.is_none();
+    if has_categories && !scoped {
+        log::warn!("{ctx} Ignoring category_urls; products are discovered from the sitemap");
     }
+    // A merging run keeps the products it did not see on purpose.
+    let merged = crawler_config.full_crawl_policy == FullCrawlPolicy::Merge;
+
+    // Write failures do not stop the run, which still refreshes the crawler
+    // stats; the first one is returned at the end.
+    let mut write_error: Option<RepositoryError> = None;
 
-    if urls.is_empty() {
-        if let Err(e) = repo.delete_products(crawler.id) {
-            log::error!("Error deleting products: {e}");
-            return;
+    // Exports and dry runs need the whole product list, so they never stream.
+    let buffered = crawler_config.dry_run || crawler_config.export_path.is_some();
+    let stats = if urls.is_empty()
+        && !buffered
+        && let Some(batch_size) = crawler_config.stream_batch_size
+    {
+        let started_at = Utc::now().naive_utc();
+        // Rows written by this run keep `updated_at >= cutoff`; SQLite
+        // defaults store whole seconds, so the cutoff is truncated as well.
+        let cutoff = started_at.with_nanosecond(0).unwrap_or(started_at);
+        let streamed = crawl_within_deadline(
+            &repo,
+            crawler.id,
+            &ctx,
+            deadline,
+            stream_products_to_repo(
+                web_crawler.as_ref(),
+                &repo,
+                &crawler_config,
+                &ctx,
+                batch_size,
+            ),
+        )
+        .await?;
+        let mut stats = web_crawler.stats();
+        stats.products_parsed = streamed.parsed;
+        stats.products_rejected = streamed.rejected;
+        stats.products_written = streamed.written;
+        let written = streamed.written;
+        let previous = usize::try_from(crawler.num_products).unwrap_or_default();
+        if let Some(e) = streamed.write_error {
+            // Products of a failed batch keep their old `updated_at`, so the
+            // stale-row delete would remove them.
+            log::error!(
+                "{ctx} Some product batches were not written; keeping products not seen in this run"
+            );
+            write_error = Some(e);
+        } else if scoped {
+            log::info!("{ctx} Category crawl; keeping products of other categories");
+        } else if merged {
+            log::info!("{ctx} Merge policy; keeping products not seen in this run");
+        } else if is_catalog_shrink_suspicious(previous, written, crawler_config.min_catalog_ratio)
+        {
+            log::warn!(
+                "{ctx} Wrote {written} products, previously {previous}; keeping products not seen in this run"
+            );
+        } else {
+            match repo.delete_products_updated_before(crawler.id, cutoff) {
+                Ok(deleted) => {
+                    log::info!("{ctx} Deleted {deleted} products not seen in this run")
+                }
+                Err(e) => {
+                    log::error!("{ctx} Error deleting stale products: {e}");
+                    write_error = Some(e);
+                }
+            }
         }
-        let products = web_crawler.get_products().await;
-        if let Err(e) = repo.create_products(&products) {
-            log::error!("Error creating products: {e}");
+        stats
+    } else if urls.is_empty() {
+        let mut products = crawl_within_deadline(
+            &repo,
+            crawler.id,
+            &ctx,
+            deadline,
+            web_crawler.get_products(&CrawlProgress::new(
+                crawler_config.progress_log_pages,
+                ctx.to_string(),
+            )),
+        )
+        .await?;
+        let mut stats = web_crawler.stats();
+        stats.products_parsed = products.len();
+        stats.products_rejected = retain_valid_products(&mut products, &crawler_config, &ctx);
+        export_crawl(&crawler_config, &ctx, &products);
+        let previous = usize::try_from(crawler.num_products).unwrap_or_default();
+        if crawler_config.dry_run {
+            log::info!("{ctx} Dry run; not writing products");
+        } else if scoped || merged {
+            if products.is_empty() {
+                log::warn!("{ctx} Crawl found no products to upsert; nothing to update");
+            } else {
+                match repo.update_products(&products) {
+                    Ok(written) => stats.products_written = written,
+                    Err(e) => {
+                        log::error!("{ctx} Error updating products: {e}");
+                        write_error = Some(e);
+                    }
+                }
+            }
+        } else if is_catalog_shrink_suspicious(
+            previous,
+            products.len(),
+            crawler_config.min_catalog_ratio,
+        ) {
+            log::warn!(
+                "{ctx} Found {} products, previously {previous}; keeping the existing catalog",
+                products.len()
+            );
+        } else {
+            match replace_catalog(
+                &repo,
+                crawler.id,
+                &products,
+                crawler_config.preserve_embeddings,
+            ) {
+                Ok(written) => stats.products_written = written,
+                Err(e) => {
+                    log::error!("{ctx} Error replacing products: {e}");
+                    write_error = Some(e);
+                }
+            }
         }
+        stats
     } else {
-        let tasks = urls
+        let urls = urls
             .iter()
-            .map(|url| async { web_crawler.get_product(url).await });
-        let products = future::join_all(tasks)
-            .await
+            .map(|url| query_filter.apply(url))
+            .collect::<HashSet<_>>();
+        let (mut products, removed) = crawl_within_deadline(
+            &repo,
+            crawler.id,
+            &ctx,
+            deadline,
+            fetch_url_products(web_crawler.as_ref(), &ctx, &urls),
+        )
+        .await?;
+        let mut stats = web_crawler.stats();
+        stats.product_links_found = urls.len();
+        stats.products_parsed = products.len();
+        stats.products_rejected = retain_valid_products(&mut products, &crawler_config, &ctx);
+        export_crawl(&crawler_config, &ctx, &products);
+        if crawler_config.dry_run {
+            log::info!(
+                "{ctx} Dry run; not writing products or deleting {} removed ones",
+                removed.len()
+            );
+        } else {
+            if products.is_empty() {
+                log::warn!(
+                    "{ctx} None of the {} requested URLs yielded a product; nothing to update",
+                    urls.len()
+                );
+            } else {
+                match repo.update_products(&products) {
+                    Ok(written) => stats.products_written = written,
+                    Err(e) => {
+                        log::error!("{ctx} Error updating products: {e}");
+                        write_error = Some(e);
+                    }
+                }
+            }
+            stats.products_removed = delete_removed_products(&repo, crawler.id, &ctx, &removed);
+        }
+        stats
+    };
+
+    if let Err(e) = repo.update_crawler_stats(crawler.id) {
+        log::error!("{ctx} Error updating crawler stats: {e}");
+        // Refreshing the stats also clears the processing flag.
+        if let Err(reset) = repo.set_crawler_processing(crawler.id, false) {
+            log::error!("{ctx} Failed to reset crawler processing: {reset:?}");
+        }
+        write_error.get_or_insert(e);
+    }
+
+    log::info!(
+        "{ctx} Finished processing crawler: categories_discovered={}, pages_visited={}, product_links_found={}, products_parsed={}, products_rejected={}, products_unavailable={}, products_written={}, products_removed={}, fetch_failures={} (http={}, network={})",
+        stats.categories_discovered,
+        stats.pages_visited,
+        stats.product_links_found,
+        stats.products_parsed,
+        stats.products_rejected,
+        stats.products_unavailable,
+        stats.products_written,
+        stats.products_removed,
+        stats.fetch_failures,
+        stats.http_failures,
+        stats.network_failures
+    );
+    if stats.products_parsed == 0 {
+        log::warn!("{ctx} Crawler produced no products; the store layout may have changed");
+    }
+
+    match write_error {
+        Some(e) => Err(e.into()),
+        None => Ok(stats),
+    }
+}
+
+/// Fetches the products of each URL of a partial run.
+///
+/// A URL that yields no product, e.g. because its page failed to load or no
+/// longer matches the crawler's selectors, is logged and skipped; stored
+/// products of such URLs are left unchanged. URLs the store answers with
+/// `404 Not Found` are returned separately, as removed products.
+async fn fetch_url_products(
+    web_crawler: &(dyn WebstoreCrawler + Send + Sync),
+    ctx: &LogContext,
+    urls: &HashSet<String>,
+) -> (Vec<NewProduct>, Vec<String>) {
+    let pages = future::join_all(urls.iter().map(|url| async move {
+        let page = web_crawler.get_product_page(url).await;
+        match &page {
+            ProductPage::Products(products) if products.is_empty() => {
+                log::warn!("{ctx} Found no product at {url}; skipping it");
+            }
+            ProductPage::NotFound => log::info!("{ctx} Product page {url} is gone"),
+            ProductPage::Products(_) => {}
+        }
+        (url, page)
+    }))
+    .await;
+
+    let mut products = vec![];
+    let mut removed = vec![];
+    for (url, page) in pages {
+        match page {
+            ProductPage::Products(page_products) => products.extend(page_products),
+            ProductPage::NotFound => removed.push(url.clone()),
+        }
+    }
+    (products, removed)
+}
+
+/// Deletes the stored products at the `removed` URLs of a partial run and
+/// returns how many were deleted.
+fn delete_removed_products<R: ProductWriter>(
+    repo: &R,
+    crawler_id: CrawlerId,
+    ctx: &LogContext,
+    removed: &[String],
+) -> usize {
+    removed
+        .iter()
+        .map(|url| match repo.delete_product_by_url(crawler_id, url) {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                log::error!("{ctx} Error deleting removed product {url}: {e}");
+                0
+            }
+        })
+        .sum()
+}
+
+/// Awaits `crawl`, giving up after `deadline`.
+///
+/// On timeout the crawl is dropped, so nothing it had not yet written reaches
+/// the repository, and the crawler's `processing` flag is cleared so later
+/// requests can run. Returns [`CrawlerError::Timeout`] in that case.
+async fn crawl_within_deadline<R, F>(
+    repo: &R,
+    crawler_id: CrawlerId,
+    ctx: &LogContext,
+    deadline: Duration,
+    crawl: F,
+) -> CrawlerResult<F::Output>
+where
+    R: CrawlerWriter,
+    F: Future,
+{
+    match tokio::time::timeout(deadline, crawl).await {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            log::error!(
+                "{ctx} Crawl did not finish within {}s; abandoning the run without changing the catalog",
+                deadline.as_secs()
+            );
+            if let Err(e) = repo.set_crawler_processing(crawler_id, false) {
+                log::error!("{ctx} Failed to reset crawler processing: {e:?}");
+            }
+            Err(CrawlerError::Timeout(deadline.as_secs()))
+        }
+    }
+}
+
+/// Replaces a crawler's stored products with `products`.
+///
+/// The delete and insert run in one transaction, so on error the previous
+/// catalog is kept; the caller still refreshes the crawler stats, which
+/// clears its `processing` flag, on both outcomes.
+///
+/// With `preserve_embeddings`, embeddings of products whose URL and
+/// embedding prompt are unchanged are read before the delete and written back
+/// after the insert, so the next benchmark run does not regenerate them.
+/// Failing to read them only skips the preservation.
+///
+/// Returns the number of inserted products.
+pub fn replace_catalog<R>(
+    repo: &R,
+    crawler_id: CrawlerId,
+    products: &[NewProduct],
+    preserve_embeddings: bool,
+) -> RepositoryResult<usize>
+where
+    R: ProductReader + ProductWriter,
+{
+    let preserved = if preserve_embeddings {
+        match repo.list_product_embeddings(crawler_id) {
+            Ok(stored) => unchanged_embeddings(stored, products),
+            Err(e) => {
+                log::warn!("Failed to read embeddings to preserve: {e}");
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let written = repo.replace_products(crawler_id, products)?;
+
+    if !preserved.is_empty() {
+        match repo.set_product_embeddings_by_url(crawler_id, &preserved) {
+            Ok(restored) => log::info!("Preserved {restored} product embeddings"),
+            Err(e) => log::warn!("Failed to restore preserved embeddings: {e}"),
+        }
+    }
+
+    Ok(written)
+}
+
+/// Writes the crawled products to the configured export file, if any.
+fn export_crawl(config: &CrawlerConfig, ctx: &LogContext, products: &[NewProduct]) {
+    let Some(path) = config.export_path.as_deref() else {
+        return;
+    };
+    match export_products(Path::new(path), config.export_format, products) {
+        Ok(written) => log::info!("{ctx} Exported {written} products to {path}"),
+        Err(e) => log::error!("{ctx} Failed to export products to {path}: {e}"),
+    }
+}
+
+/// Returns `(url, embedding)` pairs of stored embeddings whose product is
+/// crawled again with the same embedding prompt.
+fn unchanged_embeddings(
+    stored: Vec<StoredProductEmbedding>,
+    products: &[NewProduct],
+) -> Vec<(String, Vec<u8>)> {
+    let prompts: HashMap<&str, String> = products
+        .iter()
+        .filter_map(|product| {
+            let url = product.url.as_ref()?.as_str();
+            let prompt = product_embedding_prompt(
+                product.name.as_str(),
+                product.sku.as_str(),
+                product
+                    .category
+                    .as_ref()
+                    .map(|value| value.as_str())
+                    .unwrap_or(""),
+                product
+                    .units
+                    .as_ref()
+                    .map(|value| value.as_str())
+                    .unwrap_or(""),
+                product.price.get(),
+                product.amount.map(|value| value.get()).unwrap_or_default(),
+                product
+                    .description
+                    .as_ref()
+                    .map(|value| value.as_str())
+                    .unwrap_or(""),
+            );
+            Some((url, prompt))
+        })
+        .collect();
+
+    stored
+        .into_iter()
+        .filter(|row| {
+            let prompt = product_embedding_prompt(
+                &row.name,
+                &row.sku,
+                row.category.as_deref().unwrap_or(""),
+                row.units.as_deref().unwrap_or(""),
+                row.price,
+                row.amount.unwrap_or_default(),
+                row.description.as_deref().unwrap_or(""),
+            );
+            prompts.get(row.url.as_str()) == Some(&prompt)
+        })
+        .map(|row| (row.url, row.embedding))
+        .collect()
+}
+
+/// Outcome of [`stream_products_to_repo`].
+#[derive(Debug, Default)]
+struct StreamedProducts {
+    parsed: usize,
+    rejected: usize,
+    written: usize,
+    /// First failed batch write; later batches are still attempted.
+    write_error: Option<RepositoryError>,
+}
+
+/// Crawls all products through [`WebstoreCrawler::stream_products`] and
+/// upserts them in batches of `batch_size` as they arrive.
+///
+/// Pages are passed through a bounded channel, so at most
+/// [`STREAM_CHANNEL_CAPACITY`] parsed pages plus one batch are held in
+/// memory. Products whose URL or SKU was already streamed (the keys of
+/// [`dedup_products`](crate::crawlers::dedup_products)) and products
+/// failing [`is_valid_product`] are dropped before they reach a batch.
+/// Returns the number of products parsed, rejected and
+/// written, and the first batch write that failed.
+async fn stream_products_to_repo<R>(
+    web_crawler: &(dyn WebstoreCrawler + Send + Sync),
+    repo: &R,
+    config: &CrawlerConfig,
+    ctx: &LogContext,
+    batch_size: usize,
+) -> StreamedProducts
+where
+    R: ProductWriter,
+{
+    let batch_size = batch_size.max(1);
+    let (sink, mut pages) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+    let progress = CrawlProgress::new(config.progress_log_pages, ctx.to_string());
+    let producer = web_crawler.stream_products(sink, STREAM_CONCURRENCY, &progress);
+    let consumer = async {
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut streamed = StreamedProducts::default();
+        let mut seen = SeenProducts::default();
+        while let Some(mut products) = pages.recv().await {
+            products.retain(|product| seen.insert(product));
+            streamed.rejected += retain_valid_products(&mut products, config, ctx);
+            batch.extend(products);
+            if batch.len() >= batch_size {
+                write_batch(repo, ctx, &mut batch, &mut streamed);
+            }
+        }
+        write_batch(repo, ctx, &mut batch, &mut streamed);
+        streamed
+    };
+
+    let (parsed, streamed) = tokio::join!(producer, consumer);
+    StreamedProducts { parsed, ..streamed }
+}
+
+/// Upserts and clears `batch`, adding the rows written to `streamed` or
+/// keeping the error when it is the first failed batch.
+fn write_batch<R: ProductWriter>(
+    repo: &R,
+    ctx: &LogContext,
+    batch: &mut Vec<NewProduct>,
+    streamed: &mut StreamedProducts,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    match repo.update_products(batch) {
+        Ok(written) => streamed.written += written,
+        Err(e) => {
+            log::error!("{ctx} Error writing batch of {} products: {e}", batch.len());
+            streamed.write_error.get_or_insert(e);
+        }
+    }
+    batch.clear();
+}
+
+/// Returns `true` when `product` has the fields required to be stored.
+///
+/// Broken pages yield products with an empty name or SKU, which pollute the
+/// catalog and produce meaningless embeddings; both must be at least
+/// `min_name_length` and `min_sku_length` characters long after trimming.
+pub fn is_valid_product(product: &NewProduct, config: &CrawlerConfig) -> bool {
+    let length = |value: &str| value.trim().chars().count();
+    length(product.name.as_str()) >= config.min_name_length
+        && length(product.sku.as_str()) >= config.min_sku_length
+}
+
+/// Drops products failing [`is_valid_product`], logging each one, and
+/// returns how many were dropped.
+fn retain_valid_products(
+    products: &mut Vec<NewProduct>,
+    config: &CrawlerConfig,
+    ctx: &LogContext,
+) -> usize {
+    let before = products.len();
+    products.retain(|product| {
+        let valid = is_valid_product(product, config);
+        if !valid {
+            log::warn!(
+                "{ctx} Rejecting product sku={:?} name={:?} url={}: missing required fields",
+                product.sku.as_str(),
+                product.name.as_str(),
+                product.url.as_ref().map(|url| url.as_str()).unwrap_or("-")
+            );
+        }
+        valid
+    });
+    before - products.len()
+}
+
+/// Returns `true` when a full crawl should not replace the stored catalog.
+///
+/// An empty crawl is always rejected. Otherwise the crawl is rejected when it
+/// found fewer than `min_ratio` of the `previous` product count.
+fn is_catalog_shrink_suspicious(previous: usize, crawled: usize, min_ratio: f64) -> bool {
+    crawled == 0 || (crawled as f64) < previous as f64 * min_ratio
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use chrono::NaiveDateTime;
+    use pushkind_dantes::domain::types::{CrawlerSelectorValue, HubId, ProductId};
+
+    use super::*;
+    use crate::crawlers::build_new_product;
+
+    /// Crawler serving `count` synthetic single-product pages; URLs under
+    /// `/removed/` answer `404 Not Found` and other URLs yield no product.
+    struct SyntheticCrawler {
+        count: usize,
+    }
+
+    #[async_trait]
+    impl WebstoreCrawler for SyntheticCrawler {
+        async fn get_product_urls(&self) -> Vec<String> {
+            (0..self.count)
+                .map(|index| format!("https://store.example/products/{index}"))
+                .collect()
+        }
+
+        async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+            let path = url.split('?').next().unwrap_or_default();
+            let index = path.rsplit('/').next().unwrap_or_default();
+            if !index.parse().is_ok_and(|index: usize| index < self.count) {
+                return vec![];
+            }
+            build_new_product(
+                1,
+                format!("SKU-{index}"),
+                format!("Product {index}"),
+                None,
+                None,
+                100.0,
+                None,
+                None,
+                url.to_string(),
+                vec![],
+            )
             .into_iter()
-            .flatten()
+            .collect()
+        }
+
+        async fn get_product_page(&self, url: &str) -> ProductPage {
+            if url.contains("/removed/") {
+                return ProductPage::NotFound;
+            }
+            ProductPage::Products(self.get_product(url).await)
+        }
+    }
+
+    /// Lists every product of the wrapped crawler twice, under its canonical
+    /// URL and a filtered view of it, like stores linking the same SKU from
+    /// several listings.
+    struct FilteredViewCrawler(SyntheticCrawler);
+
+    #[async_trait]
+    impl WebstoreCrawler for FilteredViewCrawler {
+        async fn get_product_urls(&self) -> Vec<String> {
+            let urls = self.0.get_product_urls().await;
+            let filtered = urls.iter().map(|url| format!("{url}?color=green"));
+            urls.iter().cloned().chain(filtered).collect()
+        }
+
+        async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+            self.0.get_product(url).await
+        }
+    }
+
+    /// Records the size of every batch passed to `update_products` and
+    /// every URL passed to `delete_product_by_url`; with `fail_writes`,
+    /// `update_products` fails after recording the batch.
+    #[derive(Default)]
+    struct BatchRecordingRepo {
+        batches: Mutex<Vec<usize>>,
+        deleted_urls: Mutex<Vec<String>>,
+        fail_writes: bool,
+    }
+
+    impl ProductWriter for BatchRecordingRepo {
+        fn create_products(&self, _products: &[NewProduct]) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn update_products(&self, products: &[NewProduct]) -> RepositoryResult<usize> {
+            self.batches.lock().unwrap().push(products.len());
+            if self.fail_writes {
+                return Err(RepositoryError::NotFound);
+            }
+            Ok(products.len())
+        }
+
+        fn upsert_product(&self, _product: &NewProduct) -> RepositoryResult<ProductId> {
+            Ok(ProductId::new(1).unwrap())
+        }
+
+        fn set_product_embedding(
+            &self,
+            _product_id: ProductId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_product_embeddings_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _embeddings: &[(String, Vec<u8>)],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_products(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_product_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            url: &str,
+        ) -> RepositoryResult<usize> {
+            self.deleted_urls.lock().unwrap().push(url.to_string());
+            Ok(1)
+        }
+
+        fn replace_products(
+            &self,
+            _crawler_id: CrawlerId,
+            products: &[NewProduct],
+        ) -> RepositoryResult<usize> {
+            Ok(products.len())
+        }
+
+        fn delete_products_updated_before(
+            &self,
+            _crawler_id: CrawlerId,
+            _cutoff: NaiveDateTime,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+    }
+
+    fn test_context(selector: &str) -> LogContext {
+        LogContext::crawler(
+            &CrawlerSelectorValue::new(selector.to_string()).unwrap(),
+            CrawlerId::new(1).unwrap(),
+            HubId::new(1).unwrap(),
+        )
+    }
+
+    /// Records every `processing` value passed to `set_crawler_processing`.
+    #[derive(Default)]
+    struct ProcessingRecordingRepo {
+        processing: Mutex<Vec<bool>>,
+    }
+
+    impl CrawlerWriter for ProcessingRecordingRepo {
+        fn update_crawler_stats(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_crawler_processing(
+            &self,
+            _crawler_id: CrawlerId,
+            processing: bool,
+        ) -> RepositoryResult<usize> {
+            self.processing.lock().unwrap().push(processing);
+            Ok(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn crawl_past_deadline_is_abandoned_and_releases_crawler() {
+        let repo = ProcessingRecordingRepo::default();
+        let ctx = test_context("slow");
+
+        let output = crawl_within_deadline(
+            &repo,
+            CrawlerId::new(1).unwrap(),
+            &ctx,
+            Duration::from_millis(10),
+            future::pending::<()>(),
+        )
+        .await;
+
+        assert!(matches!(output, Err(CrawlerError::Timeout(_))));
+        assert_eq!(*repo.processing.lock().unwrap(), vec![false]);
+    }
+
+    #[tokio::test]
+    async fn crawl_within_deadline_keeps_crawler_claimed() {
+        let repo = ProcessingRecordingRepo::default();
+        let ctx = test_context("fast");
+
+        let output = crawl_within_deadline(
+            &repo,
+            CrawlerId::new(1).unwrap(),
+            &ctx,
+            Duration::from_secs(60),
+            async { 42 },
+        )
+        .await;
+
+        assert_eq!(output.ok(), Some(42));
+        assert!(repo.processing.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn partial_run_skips_urls_without_products() {
+        let crawler = SyntheticCrawler { count: 3 };
+        let ctx = test_context("synthetic");
+        let urls = [
+            "https://store.example/products/0",
+            "https://store.example/products/not-a-product",
+            "https://store.example/products/2",
+            "https://store.example/products/7",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<HashSet<_>>();
+
+        let (mut products, removed) = fetch_url_products(&crawler, &ctx, &urls).await;
+        products.sort_by(|a, b| a.sku.as_str().cmp(b.sku.as_str()));
+
+        let skus = products
+            .iter()
+            .map(|product| product.sku.as_str())
             .collect::<Vec<_>>();
-        if let Err(e) = repo.update_products(&products) {
-            log::error!("Error updating products: {e}");
+        assert_eq!(skus, vec!["SKU-0", "SKU-2"]);
+        assert!(removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn partial_run_deletes_products_whose_page_is_gone() {
+        let crawler = SyntheticCrawler { count: 3 };
+        let repo = BatchRecordingRepo::default();
+        let ctx = test_context("synthetic");
+        let urls = [
+            "https://store.example/products/1",
+            "https://store.example/removed/2",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<HashSet<_>>();
+
+        let (products, removed) = fetch_url_products(&crawler, &ctx, &urls).await;
+        let deleted = delete_removed_products(&repo, CrawlerId::new(1).unwrap(), &ctx, &removed);
+
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].sku.as_str(), "SKU-1");
+        assert_eq!(deleted, 1);
+        assert_eq!(
+            *repo.deleted_urls.lock().unwrap(),
+            vec!["https://store.example/removed/2".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_crawl_reports_progress_of_every_page() {
+        let crawler = SyntheticCrawler { count: 5 };
+        let progress = CrawlProgress::new(2, test_context("synthetic").to_string());
+
+        let products = crawler.get_products(&progress).await;
+
+        assert_eq!(products.len(), 5);
+        assert_eq!(progress.snapshot(), (5, 5));
+    }
+
+    #[tokio::test]
+    async fn streaming_writes_many_products_in_bounded_batches() {
+        let crawler = SyntheticCrawler { count: 2_345 };
+        let repo = BatchRecordingRepo::default();
+        let config = CrawlerConfig::default();
+        let ctx = test_context("synthetic");
+
+        let streamed = stream_products_to_repo(&crawler, &repo, &config, &ctx, 500).await;
+
+        assert_eq!(streamed.parsed, 2_345);
+        assert_eq!(streamed.rejected, 0);
+        assert_eq!(streamed.written, 2_345);
+        assert!(streamed.write_error.is_none());
+        let batches = repo.batches.lock().unwrap();
+        assert_eq!(*batches, vec![500, 500, 500, 500, 345]);
+    }
+
+    #[tokio::test]
+    async fn streaming_reports_failed_batches() {
+        let crawler = SyntheticCrawler { count: 1_200 };
+        let repo = BatchRecordingRepo {
+            fail_writes: true,
+            ..BatchRecordingRepo::default()
+        };
+        let config = CrawlerConfig::default();
+        let ctx = test_context("synthetic");
+
+        let streamed = stream_products_to_repo(&crawler, &repo, &config, &ctx, 500).await;
+
+        assert_eq!((streamed.parsed, streamed.written), (1_200, 0));
+        assert!(streamed.write_error.is_some());
+        assert_eq!(*repo.batches.lock().unwrap(), vec![500, 500, 200]);
+    }
+
+    #[tokio::test]
+    async fn streaming_drops_repeated_skus_across_pages() {
+        let crawler = FilteredViewCrawler(SyntheticCrawler { count: 30 });
+        let repo = BatchRecordingRepo::default();
+        let config = CrawlerConfig::default();
+        let ctx = test_context("synthetic");
+
+        let streamed = stream_products_to_repo(&crawler, &repo, &config, &ctx, 25).await;
+
+        assert_eq!((streamed.parsed, streamed.written), (60, 30));
+        assert_eq!(*repo.batches.lock().unwrap(), vec![25, 5]);
+    }
+
+    #[tokio::test]
+    async fn streaming_an_empty_catalog_writes_nothing() {
+        let crawler = SyntheticCrawler { count: 0 };
+        let repo = BatchRecordingRepo::default();
+        let config = CrawlerConfig::default();
+        let ctx = test_context("synthetic");
+
+        let streamed = stream_products_to_repo(&crawler, &repo, &config, &ctx, 500).await;
+
+        assert_eq!(
+            (streamed.parsed, streamed.rejected, streamed.written),
+            (0, 0, 0)
+        );
+        assert!(repo.batches.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streaming_drops_products_failing_required_fields() {
+        // "Product 0".."Product 9" are one character short of the minimum.
+        let crawler = SyntheticCrawler { count: 25 };
+        let repo = BatchRecordingRepo::default();
+        let config = CrawlerConfig {
+            min_name_length: 10,
+            ..CrawlerConfig::default()
+        };
+        let ctx = test_context("synthetic");
+
+        let streamed = stream_products_to_repo(&crawler, &repo, &config, &ctx, 500).await;
+
+        assert_eq!(
+            (streamed.parsed, streamed.rejected, streamed.written),
+            (25, 10, 15)
+        );
+        assert_eq!(*repo.batches.lock().unwrap(), vec![15]);
+    }
+
+    #[test]
+    fn products_need_name_and_sku_of_minimum_length() {
+        let product = |sku: &str, name: &str| {
+            build_new_product(
+                1,
+                sku.to_string(),
+                name.to_string(),
+                None,
+                None,
+                100.0,
+                None,
+                None,
+                "https://store.example/products/0".to_string(),
+                vec![],
+            )
+            .unwrap()
+        };
+        let config = CrawlerConfig {
+            min_name_length: 3,
+            min_sku_length: 2,
+            ..CrawlerConfig::default()
+        };
+
+        assert!(is_valid_product(&product("A1", "Чай"), &config));
+        assert!(!is_valid_product(&product("A1", " Ча "), &config));
+        assert!(!is_valid_product(&product("A", "Пуэр"), &config));
+    }
+
+    #[test]
+    fn supported_crawlers_match_the_dispatch_arms() {
+        let build = |selector| {
+            SiteCrawlers
+                .build(selector, 1, &CrawlerConfig::default())
+                .unwrap()
+        };
+
+        for selector in supported_crawlers() {
+            assert!(build(selector).is_some(), "{selector} is not dispatched");
         }
+        assert!(build("unknown").is_none());
     }
 
-    if let Err(e) = repo.update_crawler_stats(crawler.id) {
-        log::error!("Error updating crawler stats: {e}");
+    #[test]
+    fn crawl_intervals_skip_crawlers_without_an_interval() {
+        let scheduled = CrawlerConfig {
+            crawl_interval_secs: Some(3600),
+            ..CrawlerConfig::default()
+        };
+        let config = HashMap::from([
+            ("rusteaco".to_string(), scheduled),
+            ("101tea".to_string(), CrawlerConfig::default()),
+        ]);
+
+        let intervals = crawl_intervals(&config);
+
+        assert_eq!(
+            intervals,
+            HashMap::from([("rusteaco".to_string(), Duration::from_secs(3600))])
+        );
     }
 
-    log::info!("Finished processing crawler: {selector}");
+    #[test]
+    fn empty_crawl_is_always_rejected() {
+        assert!(is_catalog_shrink_suspicious(0, 0, 0.0));
+        assert!(is_catalog_shrink_suspicious(120, 0, 0.0));
+    }
+
+    #[test]
+    fn crawl_below_ratio_of_previous_count_is_rejected() {
+        assert!(is_catalog_shrink_suspicious(100, 49, 0.5));
+        assert!(!is_catalog_shrink_suspicious(100, 50, 0.5));
+        assert!(!is_catalog_shrink_suspicious(100, 1, 0.0));
+        assert!(!is_catalog_shrink_suspicious(0, 10, 0.5));
+    }
 }
@@ -0,0 +1,67 @@
+//! Storage of incoming messages that could not be parsed.
+
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+
+/// Distinguishes dead letters written within the same timestamp.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Writes a malformed `payload` and the `error` it produced into `dir`.
+///
+/// Each message is stored in its own `<timestamp>-<sequence>.dead` file with
+/// an `error: ...` line, a blank line and the raw payload bytes, so the
+/// payload can be inspected or replayed as-is. `dir` is created if missing.
+///
+/// Returns the path of the written file.
+pub fn write_dead_letter(dir: &Path, payload: &[u8], error: impl Display) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+    let path = dir.join(format!("{timestamp}-{sequence}.dead"));
+
+    let mut file = fs::File::create_new(&path)?;
+    // Keep the header on one line even for multi-line error messages.
+    let error = error.to_string().replace('\n', " ");
+    writeln!(file, "error: {error}")?;
+    writeln!(file)?;
+    file.write_all(payload)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::message::parse_crawler_message;
+
+    #[test]
+    fn malformed_payload_is_written_with_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = br#"{"Crawler":{"Selector":"#;
+        let error = parse_crawler_message(payload, "").unwrap_err();
+
+        let path = write_dead_letter(dir.path(), payload, &error).unwrap();
+
+        let written = fs::read(&path).unwrap();
+        let expected_header = format!("error: {error}\n\n");
+        assert!(written.starts_with(expected_header.as_bytes()));
+        assert_eq!(&written[expected_header.len()..], payload);
+    }
+
+    #[test]
+    fn each_message_gets_its_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let first = write_dead_letter(&dir.path().join("nested"), b"a", "bad").unwrap();
+        let second = write_dead_letter(&dir.path().join("nested"), b"b", "bad").unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_dir(dir.path().join("nested")).unwrap().count(), 2);
+    }
+}
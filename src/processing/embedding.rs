@@ -1,13 +1,61 @@
+use std::borrow::Cow;
 use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
 
-use bytemuck::cast_slice;
-use fastembed::TextEmbedding;
+use bytemuck::{pod_collect_to_vec, try_cast_slice};
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
 use usearch::{Index, IndexOptions, MetricKind, ScalarKind};
 
+use crate::models::config::{EmbeddingModelName, PromptTemplate};
+use crate::processing::units::normalize_units;
+
+/// Returns the fastembed model and output dimension for a configured model.
+fn model_spec(name: EmbeddingModelName) -> (EmbeddingModel, usize) {
+    match name {
+        EmbeddingModelName::MultilingualE5Small => (EmbeddingModel::MultilingualE5Small, 384),
+        EmbeddingModelName::MultilingualE5Base => (EmbeddingModel::MultilingualE5Base, 768),
+        EmbeddingModelName::MultilingualE5Large => (EmbeddingModel::MultilingualE5Large, 1024),
+    }
+}
+
+/// A loaded text embedding model and the dimension of its vectors.
+pub(crate) struct Embedder {
+    model: TextEmbedding,
+    dimensions: usize,
+}
+
+impl Embedder {
+    /// Load the configured model, downloading it on first use.
+    pub(crate) fn new(name: EmbeddingModelName) -> Result<Self, String> {
+        let (model, dimensions) = model_spec(name);
+        let model = TextEmbedding::try_new(InitOptions::new(model))
+            .map_err(|error| format!("Failed to initialize embedder {name:?}: {error:?}"))?;
+        Ok(Self { model, dimensions })
+    }
+
+    /// Number of dimensions of the model's vectors.
+    pub(crate) fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Embed `text` into a unit-length vector.
+    pub(crate) fn embed_text(&mut self, text: String) -> Result<Vec<f32>, String> {
+        Ok(self
+            .model
+            .embed(vec![text], None)
+            .map_err(|error| format!("Failed to generate embedding: {error:?}"))?
+            .into_iter()
+            .next()
+            .map(|value| normalize_embedding(&value))
+            .unwrap_or_default())
+    }
+}
+
 /// Build a textual prompt describing a benchmark or product for embedding.
 ///
 /// The prompt includes the following fields in order: name, SKU, category,
-/// units, price, amount and description.
+/// units, price, amount and description. This is [`PromptTemplate::V1`]; see
+/// [`render_product_prompt`] for the configurable templates.
 pub(crate) fn product_embedding_prompt(
     name: &str,
     sku: &str,
@@ -17,14 +65,91 @@ pub(crate) fn product_embedding_prompt(
     amount: f64,
     description: &str,
 ) -> String {
-    format!(
-        "Name: {name}\nSKU: {sku}\nCategory: {category}\nUnits: {units}\nPrice: {price}\nAmount: {amount}\nDescription: {description}",
+    render_product_prompt(
+        PromptTemplate::V1,
+        name,
+        sku,
+        category,
+        units,
+        price,
+        amount,
+        description,
     )
 }
 
+/// Build the embedding prompt of a benchmark or product with `template`.
+///
+/// Every template lists the same fields in the same order as
+/// [`product_embedding_prompt`]; they differ in labels only. Amount and
+/// units are first converted with [`normalize_units`], so e.g. `500 г` and
+/// `0.5 кг` are embedded alike.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_product_prompt(
+    template: PromptTemplate,
+    name: &str,
+    sku: &str,
+    category: &str,
+    units: &str,
+    price: f64,
+    amount: f64,
+    description: &str,
+) -> String {
+    let (amount, units) = normalize_units(amount, units);
+    match template {
+        PromptTemplate::V1 => format!(
+            "Name: {name}\nSKU: {sku}\nCategory: {category}\nUnits: {units}\nPrice: {price}\nAmount: {amount}\nDescription: {description}",
+        ),
+        PromptTemplate::V2 => format!(
+            "Название: {name}\nАртикул: {sku}\nКатегория: {category}\nЕдиницы: {units}\nЦена: {price}\nКоличество: {amount}\nОписание: {description}",
+        ),
+        PromptTemplate::V3 => [
+            name.to_string(),
+            sku.to_string(),
+            category.to_string(),
+            units.to_string(),
+            price.to_string(),
+            amount.to_string(),
+            description.to_string(),
+        ]
+        .into_iter()
+        .filter(|value| !value.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n"),
+    }
+}
+
+/// Cut `description` after its first `max_words` whitespace-delimited words,
+/// so verbose marketing copy does not dominate or overflow the prompt.
+///
+/// Whitespace inside the kept part is preserved; `None` keeps everything.
+pub(crate) fn truncate_description(description: &str, max_words: Option<usize>) -> &str {
+    let Some(max_words) = max_words else {
+        return description;
+    };
+
+    let mut words = 0;
+    let mut in_word = false;
+    for (index, ch) in description.char_indices() {
+        let starts_word = !in_word && !ch.is_whitespace();
+        in_word = !ch.is_whitespace();
+        if starts_word {
+            if words == max_words {
+                log::debug!(
+                    "Truncating description of {} words to {max_words}",
+                    description.split_whitespace().count()
+                );
+                return description[..index].trim_end();
+            }
+            words += 1;
+        }
+    }
+    description
+}
+
 /// Normalize a vector to unit length.
 ///
-/// Returns the original vector when the norm is zero.
+/// Returns the original vector when the norm is zero; callers detect such
+/// vectors with [`is_zero_embedding`].
 pub(crate) fn normalize_embedding(vec: &[f32]) -> Vec<f32> {
     let norm = vec.iter().map(|x| x * x).sum::<f32>().sqrt();
     if norm == 0.0 {
@@ -34,86 +159,410 @@ pub(crate) fn normalize_embedding(vec: &[f32]) -> Vec<f32> {
     }
 }
 
+/// Returns `true` when every component of `embedding` is zero.
+///
+/// Such a vector has no direction, so it matches nothing meaningfully in
+/// cosine space; it usually means the prompt text was degenerate.
+pub(crate) fn is_zero_embedding(embedding: &[f32]) -> bool {
+    embedding.iter().all(|value| *value == 0.0)
+}
+
+/// Embedding model loaded once and shared by all matching runs.
+///
+/// The model is loaded on first use and guarded by a mutex, so concurrent
+/// runs take turns embedding instead of each loading their own copy.
+#[derive(Clone)]
+pub struct SharedEmbedder {
+    model: EmbeddingModelName,
+    prompt_template: PromptTemplate,
+    description_max_words: Option<usize>,
+    cell: Arc<EmbedderCell>,
+}
+
+#[derive(Default)]
+struct EmbedderCell {
+    /// Serializes model loading so it happens at most once.
+    init: Mutex<()>,
+    embedder: OnceLock<Mutex<Embedder>>,
+}
+
+impl SharedEmbedder {
+    /// Creates a handle for `model` without loading it yet.
+    pub fn new(model: EmbeddingModelName) -> Self {
+        Self {
+            model,
+            prompt_template: PromptTemplate::default(),
+            description_max_words: None,
+            cell: Arc::default(),
+        }
+    }
+
+    /// Sets the template product and benchmark prompts are rendered with.
+    pub fn with_prompt_template(mut self, prompt_template: PromptTemplate) -> Self {
+        self.prompt_template = prompt_template;
+        self
+    }
+
+    /// Returns the template product and benchmark prompts are rendered with.
+    pub(crate) fn prompt_template(&self) -> PromptTemplate {
+        self.prompt_template
+    }
+
+    /// Sets the number of description words kept in prompts; see
+    /// [`truncate_description`].
+    pub fn with_description_max_words(mut self, max_words: Option<usize>) -> Self {
+        self.description_max_words = max_words;
+        self
+    }
+
+    /// Returns the number of description words kept in prompts.
+    pub(crate) fn description_max_words(&self) -> Option<usize> {
+        self.description_max_words
+    }
+
+    /// Returns exclusive access to the embedder, loading the model first if
+    /// no run has used it yet.
+    pub(crate) fn acquire(&self) -> Result<MutexGuard<'_, Embedder>, String> {
+        let embedder = match self.cell.embedder.get() {
+            Some(embedder) => embedder,
+            None => {
+                let _init = self
+                    .cell
+                    .init
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                match self.cell.embedder.get() {
+                    Some(embedder) => embedder,
+                    None => {
+                        let loaded = Embedder::new(self.model)?;
+                        self.cell.embedder.get_or_init(|| Mutex::new(loaded))
+                    }
+                }
+            }
+        };
+        // A panic mid-embedding leaves the model itself usable.
+        Ok(embedder.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Embedding resolved by [`load_or_generate_embedding`].
+pub(crate) struct EmbeddingOutcome<'a> {
+    /// The embedding, borrowed from the stored blob when it is reused.
+    pub(crate) embedding: Cow<'a, [f32]>,
+    /// Whether the embedding was generated and persisted by this call.
+    pub(crate) generated: bool,
+    /// Number of dimensions of `embedding`.
+    pub(crate) dimensions: usize,
+}
+
+/// Returns the stored embedding when it can be reused.
+///
+/// Blobs of another length than `dimensions` floats were produced by another
+/// model and are not reused. Suitably aligned blobs are borrowed; others are
+/// copied.
+pub(crate) fn reusable_embedding(
+    blob: Option<&[u8]>,
+    dimensions: usize,
+    force_reembed: bool,
+) -> Option<Cow<'_, [f32]>> {
+    let blob = blob.filter(|blob| !force_reembed && blob.len() == dimensions * size_of::<f32>())?;
+    Some(match try_cast_slice(blob) {
+        Ok(embedding) => Cow::Borrowed(embedding),
+        Err(_) => Cow::Owned(pod_collect_to_vec(blob)),
+    })
+}
+
 /// Load an embedding from blob when present, otherwise generate and persist it.
 ///
-/// Returns the embedding and a flag indicating whether a new embedding was
-/// generated.
-pub(crate) fn load_or_generate_embedding<F>(
-    existing_blob: Option<&[u8]>,
+/// With `force_reembed`, or when the stored blob was produced by a model of
+/// another dimension, the blob is ignored and a fresh embedding is generated
+/// and persisted in its place.
+pub(crate) fn load_or_generate_embedding<'a, F>(
+    existing_blob: Option<&'a [u8]>,
     prompt: String,
-    embedder: &mut TextEmbedding,
+    embedder: &mut Embedder,
     persist: F,
-) -> Result<(Vec<f32>, bool), String>
+    force_reembed: bool,
+) -> Result<EmbeddingOutcome<'a>, String>
 where
     F: FnOnce(&[f32]) -> Result<(), String>,
 {
-    if let Some(blob) = existing_blob {
-        return Ok((cast_slice(blob).to_vec(), false));
+    let dimensions = embedder.dimensions;
+    if let Some(embedding) = reusable_embedding(existing_blob, dimensions, force_reembed) {
+        return Ok(EmbeddingOutcome {
+            embedding,
+            generated: false,
+            dimensions,
+        });
     }
 
-    let generated = embedder
-        .embed(vec![prompt], None)
-        .map_err(|error| format!("Failed to generate embedding: {error:?}"))?
-        .into_iter()
-        .next()
-        .map(|value| normalize_embedding(&value))
-        .unwrap_or_default();
+    let generated = embedder.embed_text(prompt)?;
 
     persist(&generated)?;
 
-    Ok((generated, true))
+    Ok(EmbeddingOutcome {
+        dimensions: generated.len(),
+        embedding: Cow::Owned(generated),
+        generated: true,
+    })
+}
+
+/// Distance metric of every similarity index built by [`EmbeddingIndex`].
+pub(crate) const INDEX_METRIC: MetricKind = MetricKind::Cos;
+
+/// Convert a `usearch` distance under `metric` to a similarity, where `1.0`
+/// means identical directions.
+///
+/// Cosine and inner-product distances are `1 - similarity`. Squared
+/// euclidean distance between unit vectors is `2 - 2 * similarity`. Other
+/// metrics have no cosine reading and report negative infinity, so they
+/// never pass a similarity threshold.
+pub(crate) fn distance_to_similarity(metric: MetricKind, distance: f32) -> f32 {
+    match metric {
+        MetricKind::Cos | MetricKind::IP => 1.0 - distance,
+        MetricKind::L2sq => 1.0 - distance / 2.0,
+        _ => f32::NEG_INFINITY,
+    }
+}
+
+/// Cosine ANN index over keyed embeddings.
+///
+/// The index is read-only once built, so one index can be searched from
+/// several threads at once.
+pub(crate) struct EmbeddingIndex {
+    /// `None` when built from no items; every search then finds nothing.
+    index: Option<Index>,
+    dimensions: usize,
+}
+
+/// Fails when `embedding` does not have the expected number of dimensions.
+fn check_dimensions(
+    what: &str,
+    embedding: &[f32],
+    dimensions: usize,
+) -> Result<(), Box<dyn Error>> {
+    if embedding.len() == dimensions {
+        Ok(())
+    } else {
+        Err(format!(
+            "{what} has {} dimensions, expected {dimensions}",
+            embedding.len()
+        )
+        .into())
+    }
+}
+
+impl EmbeddingIndex {
+    /// Build an index of `items`, keyed by their ids, for vectors of
+    /// `dimensions` dimensions, i.e. those of the active model.
+    ///
+    /// An item of another length fails the build instead of producing an
+    /// index that silently mismatches its queries.
+    pub(crate) fn build<T>(items: &[(i32, T)], dimensions: usize) -> Result<Self, Box<dyn Error>>
+    where
+        T: AsRef<[f32]>,
+    {
+        for (id, embedding) in items {
+            check_dimensions(
+                &format!("Embedding of {id}"),
+                embedding.as_ref(),
+                dimensions,
+            )?;
+        }
+        if items.is_empty() {
+            return Ok(Self {
+                index: None,
+                dimensions,
+            });
+        }
+
+        let index = Index::new(&IndexOptions {
+            dimensions,
+            metric: INDEX_METRIC,
+            quantization: ScalarKind::F32,
+            ..Default::default()
+        })?;
+
+        index.reserve(items.len())?;
+
+        for (id, embedding) in items {
+            index.add(*id as u64, embedding.as_ref())?;
+        }
+
+        Ok(Self {
+            index: Some(index),
+            dimensions,
+        })
+    }
+
+    /// Search the top-k closest vectors to the query embedding, as
+    /// `(key, distance)` pairs ordered by ascending distance.
+    ///
+    /// A query of another length than the index's dimensions fails.
+    pub(crate) fn search(
+        &self,
+        query_embedding: &[f32],
+        k: usize,
+    ) -> Result<Vec<(u64, f32)>, Box<dyn Error>> {
+        check_dimensions("Query embedding", query_embedding, self.dimensions)?;
+        let Some(index) = self.index.as_ref().filter(|_| k > 0) else {
+            return Ok(Vec::new());
+        };
+
+        let neighbors = index.search(query_embedding, k)?;
+
+        let results: Vec<(u64, f32)> = neighbors
+            .keys
+            .iter()
+            .zip(neighbors.distances.iter())
+            .map(|(&key, &distance)| (key, distance))
+            .collect();
+
+        Ok(results)
+    }
 }
 
 /// Search the top-k closest vectors to the query embedding.
+///
+/// Builds a one-off [`EmbeddingIndex`] of `dimensions` dimensions; callers
+/// searching the same items for many queries should build the index once
+/// instead. A query or item of another length fails.
 pub(crate) fn search_top_k<'a, T>(
     query_embedding: &[f32],
     items: &'a [(i32, T)],
     k: usize,
+    dimensions: usize,
 ) -> Result<Vec<(u64, f32)>, Box<dyn Error>>
 where
     T: AsRef<[f32]> + 'a,
 {
+    check_dimensions("Query embedding", query_embedding, dimensions)?;
     if items.is_empty() || k == 0 {
         return Ok(Vec::new());
     }
 
-    let dim = query_embedding.len();
+    EmbeddingIndex::build(items, dimensions)?.search(query_embedding, k)
+}
 
-    let index = Index::new(&IndexOptions {
-        dimensions: dim,
-        metric: MetricKind::Cos,
-        quantization: ScalarKind::F32,
-        ..Default::default()
-    })?;
+#[cfg(test)]
+mod tests {
+    use bytemuck::cast_slice;
 
-    index.reserve(items.len())?;
+    use usearch::MetricKind;
 
-    for (id, embedding) in items {
-        index.add(*id as u64, embedding.as_ref())?;
+    use super::{
+        EmbeddingIndex, INDEX_METRIC, distance_to_similarity, is_zero_embedding, model_spec,
+        normalize_embedding, product_embedding_prompt, render_product_prompt, reusable_embedding,
+        search_top_k, truncate_description,
+    };
+    use crate::models::config::{EmbeddingModelName, PromptTemplate};
+
+    #[test]
+    fn prompt_produces_expected_string() {
+        let result = product_embedding_prompt(
+            "Sample Name",
+            "SKU123",
+            "Category",
+            "units",
+            9.99,
+            2.0,
+            "Description",
+        );
+
+        let expected = "Name: Sample Name\nSKU: SKU123\nCategory: Category\nUnits: units\nPrice: 9.99\nAmount: 2\nDescription: Description";
+        assert_eq!(result, expected);
     }
 
-    let neighbors = index.search(query_embedding, k)?;
+    #[test]
+    fn templates_label_the_same_fields_differently() {
+        let render = |template| {
+            render_product_prompt(template, "Пуэр", "SKU1", "Чай", "г", 450.0, 100.0, "")
+        };
 
-    let results: Vec<(u64, f32)> = neighbors
-        .keys
-        .iter()
-        .zip(neighbors.distances.iter())
-        .map(|(&key, &distance)| (key, distance))
-        .collect();
+        assert_eq!(
+            render(PromptTemplate::V1),
+            product_embedding_prompt("Пуэр", "SKU1", "Чай", "г", 450.0, 100.0, "")
+        );
+        assert_eq!(
+            render(PromptTemplate::V2),
+            "Название: Пуэр\nАртикул: SKU1\nКатегория: Чай\nЕдиницы: кг\nЦена: 450\nКоличество: 0.1\nОписание: "
+        );
+        assert_eq!(render(PromptTemplate::V3), "Пуэр\nSKU1\nЧай\nкг\n450\n0.1");
+    }
 
-    Ok(results)
-}
+    #[test]
+    fn descriptions_are_cut_after_max_words() {
+        let description = "  Выдержанный  шу пуэр\nиз Юньнани. ";
 
-#[cfg(test)]
-mod tests {
-    use super::search_top_k;
+        assert_eq!(
+            truncate_description(description, Some(3)),
+            "  Выдержанный  шу пуэр"
+        );
+        assert_eq!(truncate_description(description, Some(5)), description);
+        assert_eq!(truncate_description(description, None), description);
+        assert_eq!(truncate_description(description, Some(0)), "");
+    }
+
+    #[test]
+    fn cosine_distance_converts_to_cosine_similarity() {
+        let items = vec![
+            (1, vec![1.0_f32, 0.0]),
+            (2, vec![0.0, 1.0]),
+            (3, vec![-1.0, 0.0]),
+        ];
+        let neighbors = search_top_k(&[1.0, 0.0], &items, 3, 2).expect("search should succeed");
+
+        let similarities = neighbors
+            .iter()
+            .map(|(key, distance)| (*key, distance_to_similarity(INDEX_METRIC, *distance)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            similarities.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        for ((_, similarity), expected) in similarities.iter().zip([1.0, 0.0, -1.0]) {
+            assert!((similarity - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn other_metrics_convert_by_their_own_scale() {
+        // Unit vectors 60 degrees apart: cosine 0.5, squared distance 1.0.
+        assert!((distance_to_similarity(MetricKind::L2sq, 1.0) - 0.5).abs() < f32::EPSILON);
+        assert!((distance_to_similarity(MetricKind::IP, 0.5) - 0.5).abs() < f32::EPSILON);
+        assert_eq!(
+            distance_to_similarity(MetricKind::Hamming, 0.0),
+            f32::NEG_INFINITY
+        );
+    }
+
+    #[test]
+    fn zero_vectors_stay_zero_and_are_detected() {
+        let normalized = normalize_embedding(&[0.0, 0.0, 0.0]);
+
+        assert!(is_zero_embedding(&normalized));
+        assert!(!is_zero_embedding(&normalize_embedding(&[0.0, 3.0, 4.0])));
+        assert!(!is_zero_embedding(&[0.0, -0.1]));
+    }
+
+    #[test]
+    fn default_model_is_multilingual_e5_large() {
+        let (model, dimensions) = model_spec(EmbeddingModelName::default());
+
+        assert!(matches!(
+            model,
+            fastembed::EmbeddingModel::MultilingualE5Large
+        ));
+        assert_eq!(dimensions, 1024);
+    }
 
     #[test]
     fn search_top_k_returns_empty_for_empty_items() {
         let query = vec![1.0_f32, 0.0, 0.0];
         let items: Vec<(i32, Vec<f32>)> = Vec::new();
 
-        let result = search_top_k(&query, &items, 1).expect("search should succeed");
+        let result = search_top_k(&query, &items, 1, 3).expect("search should succeed");
 
         assert!(result.is_empty());
     }
@@ -127,9 +576,35 @@ mod tests {
             (30, vec![0.5_f32, 0.5, 0.0]),
         ];
 
-        let result = search_top_k(&query, &items, 1).expect("search should succeed");
+        let result = search_top_k(&query, &items, 1, 3).expect("search should succeed");
 
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].0, 20);
     }
+
+    #[test]
+    fn mismatched_dimensions_fail_instead_of_building_an_index() {
+        let items = vec![(10, vec![1.0_f32, 0.0, 0.0]), (20, vec![0.0_f32, 1.0])];
+
+        assert!(search_top_k(&[1.0, 0.0], &items[..1], 1, 3).is_err());
+        assert!(search_top_k(&[1.0, 0.0, 0.0], &items, 1, 3).is_err());
+        assert!(EmbeddingIndex::build(&items, 3).is_err());
+
+        let index = EmbeddingIndex::build(&items[..1], 3).expect("index should build");
+        assert!(index.search(&[], 1).is_err());
+        assert_eq!(index.search(&[1.0, 0.0, 0.0], 1).unwrap()[0].0, 10);
+    }
+
+    #[test]
+    fn stored_embedding_is_reused_only_at_the_model_dimension() {
+        let stored = [0.6_f32, 0.8, 0.0];
+        let blob: &[u8] = cast_slice(&stored);
+
+        let reused = reusable_embedding(Some(blob), 3, false).expect("blob should be reused");
+
+        assert_eq!(reused.as_ref(), &stored);
+        assert!(reusable_embedding(Some(blob), 4, false).is_none());
+        assert!(reusable_embedding(Some(blob), 3, true).is_none());
+        assert!(reusable_embedding(None, 3, false).is_none());
+    }
 }
@@ -0,0 +1,167 @@
+//! Export of crawled products to files for offline inspection.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use pushkind_dantes::domain::product::NewProduct;
+use serde::Serialize;
+
+use crate::models::config::ExportFormat;
+
+/// Column order of CSV exports; JSON lines use the same field names.
+const CSV_HEADER: &str = "sku,name,price,category,units,amount,description,url,images";
+
+/// A product flattened to plain values.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    sku: &'a str,
+    name: &'a str,
+    price: f64,
+    category: Option<&'a str>,
+    units: Option<&'a str>,
+    amount: Option<f64>,
+    description: Option<&'a str>,
+    url: Option<&'a str>,
+    images: Vec<&'a str>,
+}
+
+impl<'a> From<&'a NewProduct> for ExportRow<'a> {
+    fn from(product: &'a NewProduct) -> Self {
+        Self {
+            sku: product.sku.as_str(),
+            name: product.name.as_str(),
+            price: product.price.get(),
+            category: product.category.as_ref().map(|value| value.as_str()),
+            units: product.units.as_ref().map(|value| value.as_str()),
+            amount: product.amount.map(|value| value.get()),
+            description: product.description.as_ref().map(|value| value.as_str()),
+            url: product.url.as_ref().map(|value| value.as_str()),
+            images: product.images.iter().map(|image| image.as_str()).collect(),
+        }
+    }
+}
+
+/// Writes `products` to `path` in `format`, replacing any existing file.
+///
+/// JSON lines hold one object per product with `images` as an array. CSV
+/// files start with a header row; missing values are empty and `images` are
+/// joined with single spaces, which never occur inside a URL. Parent
+/// directories are created if missing.
+///
+/// Returns the number of products written.
+pub fn export_products(
+    path: &Path,
+    format: ExportFormat,
+    products: &[NewProduct],
+) -> io::Result<usize> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    if format == ExportFormat::Csv {
+        writeln!(writer, "{CSV_HEADER}")?;
+    }
+    for product in products {
+        let row = ExportRow::from(product);
+        match format {
+            ExportFormat::Jsonl => {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+            ExportFormat::Csv => writeln!(writer, "{}", csv_line(&row))?,
+        }
+    }
+    writer.flush()?;
+
+    Ok(products.len())
+}
+
+fn csv_line(row: &ExportRow) -> String {
+    let optional = |value: Option<&str>| value.map(csv_field).unwrap_or_default();
+    [
+        csv_field(row.sku),
+        csv_field(row.name),
+        row.price.to_string(),
+        optional(row.category),
+        optional(row.units),
+        row.amount
+            .map(|value| value.to_string())
+            .unwrap_or_default(),
+        optional(row.description),
+        optional(row.url),
+        csv_field(&row.images.join(" ")),
+    ]
+    .join(",")
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pushkind_dantes::domain::types::{
+        CrawlerId, ImageUrl, ProductDescription, ProductName, ProductPrice, ProductSku, ProductUrl,
+    };
+
+    use super::*;
+
+    fn product() -> NewProduct {
+        NewProduct {
+            crawler_id: CrawlerId::new(1).unwrap(),
+            sku: ProductSku::new("P-1".to_string()).unwrap(),
+            name: ProductName::new("Пуэр \"Шу\", 2019".to_string()).unwrap(),
+            price: ProductPrice::new(450.0).unwrap(),
+            category: None,
+            units: None,
+            amount: None,
+            description: Some(ProductDescription::new("Плотный\nземлистый".to_string()).unwrap()),
+            url: Some(ProductUrl::new("https://store.example/puer".to_string()).unwrap()),
+            images: vec![
+                ImageUrl::new("https://store.example/1.jpg".to_string()).unwrap(),
+                ImageUrl::new("https://store.example/2.jpg".to_string()).unwrap(),
+            ],
+        }
+    }
+
+    #[test]
+    fn jsonl_keeps_images_as_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.jsonl");
+
+        let written = export_products(&path, ExportFormat::Jsonl, &[product(), product()]).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let first: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(text.lines().count(), 2);
+        assert_eq!(first["name"], "Пуэр \"Шу\", 2019");
+        assert_eq!(first["category"], serde_json::Value::Null);
+        assert_eq!(
+            first["images"],
+            serde_json::json!(["https://store.example/1.jpg", "https://store.example/2.jpg"])
+        );
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_joins_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("export.csv");
+
+        export_products(&path, ExportFormat::Csv, &[product()]).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "{CSV_HEADER}\nP-1,\"Пуэр \"\"Шу\"\", 2019\",450,,,,\"Плотный\nземлистый\",https://store.example/puer,https://store.example/1.jpg https://store.example/2.jpg\n"
+            )
+        );
+    }
+}
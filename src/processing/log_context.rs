@@ -0,0 +1,85 @@
+//! `key=value` context shared by the log lines of one processing run.
+
+use std::fmt;
+
+use pushkind_dantes::domain::types::{BenchmarkId, CrawlerId, CrawlerSelectorValue, HubId};
+
+/// Identifies what a log line is about, rendered as a bracketed list of
+/// `key=value` fields, e.g. `[selector=rusteaco crawler_id=3 hub_id=1]`.
+///
+/// Handlers build one context per run and start every log line with it, so
+/// aggregated logs can be filtered by crawler, benchmark or hub.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LogContext {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl LogContext {
+    /// Context of a crawler run.
+    pub(crate) fn crawler(
+        selector: &CrawlerSelectorValue,
+        crawler_id: CrawlerId,
+        hub_id: HubId,
+    ) -> Self {
+        Self::default()
+            .with("selector", selector)
+            .with("crawler_id", crawler_id)
+            .with("hub_id", hub_id)
+    }
+
+    /// Context of a benchmark matching run.
+    pub(crate) fn benchmark(benchmark_id: BenchmarkId, hub_id: HubId) -> Self {
+        Self::default()
+            .with("benchmark_id", benchmark_id)
+            .with("hub_id", hub_id)
+    }
+
+    /// Context of a hub-wide run.
+    pub(crate) fn hub(hub_id: HubId) -> Self {
+        Self::default().with("hub_id", hub_id)
+    }
+
+    /// Appends a field, e.g. the crawler a hub-wide run is working on.
+    pub(crate) fn with(mut self, key: &'static str, value: impl fmt::Display) -> Self {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for LogContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (index, (key, value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{key}={value}")?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crawler_context_renders_key_value_fields() {
+        let context = LogContext::crawler(
+            &CrawlerSelectorValue::new("rusteaco".to_string()).unwrap(),
+            CrawlerId::new(3).unwrap(),
+            HubId::new(1).unwrap(),
+        );
+
+        assert_eq!(
+            context.to_string(),
+            "[selector=rusteaco crawler_id=3 hub_id=1]"
+        );
+        assert_eq!(
+            LogContext::hub(HubId::new(2).unwrap())
+                .with("crawler_id", 5)
+                .to_string(),
+            "[hub_id=2 crawler_id=5]"
+        );
+    }
+}
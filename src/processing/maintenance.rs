@@ -0,0 +1,388 @@
+use pushkind_dantes::domain::types::HubId;
+
+use crate::processing::log_context::LogContext;
+use crate::processing::message::MaintenanceMessage;
+use crate::repository::{
+    BenchmarkReader, BenchmarkWriter, CrawlerReader, CrawlerWriter, ProductWriter,
+};
+
+/// Handle service-local maintenance messages.
+///
+/// Clearing a `processing` flag is meant for manual recovery of a single
+/// stuck crawler or benchmark; it does not stop a run that is still active.
+/// Clearing a crawler's products and rebuilding hub stats claim records like
+/// a run does, so they skip crawlers and benchmarks that are processing.
+/// Purging orphaned associations only removes rows of missing products; a
+/// `RebuildHubStats` afterwards refreshes the benchmark counts.
+pub async fn process_maintenance_message<R>(msg: MaintenanceMessage, repo: R)
+where
+    R: CrawlerReader + CrawlerWriter + BenchmarkReader + BenchmarkWriter + ProductWriter,
+{
+    log::info!("Received maintenance message: {msg:?}");
+
+    match msg {
+        MaintenanceMessage::ClearCrawlerProcessing(selector) => {
+            let crawler = match repo.get_crawler(&selector) {
+                Ok(crawler) => crawler,
+                Err(e) => {
+                    log::error!("Error retrieving selector {selector}: {e}");
+                    return;
+                }
+            };
+            match repo.set_crawler_processing(crawler.id, false) {
+                Ok(0) => log::info!("Crawler {selector} is not processing; nothing to clear"),
+                Ok(_) => log::info!("Cleared processing flag for crawler {selector}"),
+                Err(e) => log::error!("Failed to clear processing for crawler {selector}: {e:?}"),
+            }
+        }
+        MaintenanceMessage::ClearBenchmarkProcessing(benchmark_id) => {
+            match repo.set_benchmark_processing(benchmark_id, false) {
+                Ok(0) => log::info!(
+                    "Benchmark {benchmark_id} is not processing or does not exist; nothing to clear"
+                ),
+                Ok(_) => log::info!("Cleared processing flag for benchmark {benchmark_id}"),
+                Err(e) => {
+                    log::error!("Failed to clear processing for benchmark {benchmark_id}: {e:?}")
+                }
+            }
+        }
+        MaintenanceMessage::ClearCrawlerProducts(selector) => {
+            let crawler = match repo.get_crawler(&selector) {
+                Ok(crawler) => crawler,
+                Err(e) => {
+                    log::error!("Error retrieving selector {selector}: {e}");
+                    return;
+                }
+            };
+            match repo.set_crawler_processing(crawler.id, true) {
+                Ok(0) => {
+                    log::warn!("Crawler {selector} is processing; not clearing its products");
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("Failed to claim crawler {selector}: {e:?}");
+                    return;
+                }
+            }
+
+            match repo.delete_products(crawler.id) {
+                Ok(deleted) => log::info!("Deleted {deleted} products of crawler {selector}"),
+                Err(e) => log::error!("Failed to delete products of crawler {selector}: {e:?}"),
+            }
+            if let Err(e) = repo.update_crawler_stats(crawler.id) {
+                log::error!("Error updating crawler stats: {e}");
+            }
+            if let Err(e) = repo.set_crawler_processing(crawler.id, false) {
+                log::error!("Failed to release crawler {selector}: {e:?}");
+            }
+        }
+        MaintenanceMessage::RebuildHubStats(hub_id) => rebuild_hub_stats(hub_id, &repo),
+        MaintenanceMessage::PurgeOrphanedAssociations => match repo.purge_orphaned_associations() {
+            Ok(purged) => {
+                log::info!("Purged {purged} benchmark associations of missing products")
+            }
+            Err(e) => log::error!("Failed to purge orphaned benchmark associations: {e:?}"),
+        },
+    }
+}
+
+/// Recomputes `num_products` of every crawler and benchmark in the hub.
+///
+/// Each record is claimed through its `processing` flag before
+/// `update_*_stats` rewrites the count and releases it, so records with a
+/// run in progress are skipped and keep their flag.
+fn rebuild_hub_stats<R>(hub_id: HubId, repo: &R)
+where
+    R: CrawlerReader + CrawlerWriter + BenchmarkReader + BenchmarkWriter,
+{
+    let ctx = LogContext::hub(hub_id);
+    let mut crawlers_rebuilt = 0;
+    let mut benchmarks_rebuilt = 0;
+    let mut skipped_processing = 0;
+
+    match repo.list_crawlers(hub_id) {
+        Ok(crawlers) => {
+            for crawler in crawlers {
+                let crawler_ctx = ctx.clone().with("crawler_id", crawler.id);
+                match repo.set_crawler_processing(crawler.id, true) {
+                    Ok(0) => {
+                        log::warn!("{crawler_ctx} Crawler is processing; not rebuilding its stats");
+                        skipped_processing += 1;
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("{crawler_ctx} Failed to claim crawler: {e:?}");
+                        continue;
+                    }
+                }
+                match repo.update_crawler_stats(crawler.id) {
+                    Ok(_) => crawlers_rebuilt += 1,
+                    Err(e) => {
+                        log::error!("{crawler_ctx} Error updating crawler stats: {e}");
+                        if let Err(e) = repo.set_crawler_processing(crawler.id, false) {
+                            log::error!("{crawler_ctx} Failed to release crawler: {e:?}");
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => log::error!("{ctx} Failed to list crawlers: {e:?}"),
+    }
+
+    match repo.list_benchmarks(hub_id) {
+        Ok(benchmarks) => {
+            for benchmark in benchmarks {
+                let benchmark_ctx = ctx.clone().with("benchmark_id", benchmark.id);
+                match repo.set_benchmark_processing(benchmark.id, true) {
+                    Ok(0) => {
+                        log::warn!(
+                            "{benchmark_ctx} Benchmark is processing; not rebuilding its stats"
+                        );
+                        skipped_processing += 1;
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("{benchmark_ctx} Failed to claim benchmark: {e:?}");
+                        continue;
+                    }
+                }
+                match repo.update_benchmark_stats(benchmark.id) {
+                    Ok(_) => benchmarks_rebuilt += 1,
+                    Err(e) => {
+                        log::error!("{benchmark_ctx} Error updating benchmark stats: {e}");
+                        if let Err(e) = repo.set_benchmark_processing(benchmark.id, false) {
+                            log::error!("{benchmark_ctx} Failed to release benchmark: {e:?}");
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => log::error!("{ctx} Failed to list benchmarks: {e:?}"),
+    }
+
+    log::info!(
+        "{ctx} Rebuilt hub stats: crawlers_rebuilt={crawlers_rebuilt}, benchmarks_rebuilt={benchmarks_rebuilt}, skipped_processing={skipped_processing}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chrono::NaiveDateTime;
+    use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
+    use pushkind_dantes::domain::benchmark::Benchmark;
+    use pushkind_dantes::domain::crawler::Crawler;
+    use pushkind_dantes::domain::product::{NewProduct, Product};
+    use pushkind_dantes::domain::types::{
+        BenchmarkId, CrawlerId, CrawlerSelectorValue, ProductId, SimilarityDistance,
+    };
+
+    use super::*;
+
+    type Writes = Arc<Mutex<Vec<(i32, bool)>>>;
+
+    /// Records processing flag writes; knows no crawlers.
+    #[derive(Default)]
+    struct FakeProcessingRepo {
+        crawler_writes: Writes,
+        benchmark_writes: Writes,
+    }
+
+    fn recorded(writes: &Writes) -> Vec<(i32, bool)> {
+        writes.lock().expect("writes mutex poisoned").clone()
+    }
+
+    impl CrawlerReader for FakeProcessingRepo {
+        fn get_crawler(&self, _selector: &CrawlerSelectorValue) -> RepositoryResult<Crawler> {
+            Err(RepositoryError::NotFound)
+        }
+
+        fn list_crawlers(&self, _hub_id: HubId) -> RepositoryResult<Vec<Crawler>> {
+            Ok(vec![])
+        }
+
+        fn list_due_crawlers(
+            &self,
+            _now: NaiveDateTime,
+            _intervals: &HashMap<String, Duration>,
+        ) -> RepositoryResult<Vec<Crawler>> {
+            Ok(vec![])
+        }
+    }
+
+    impl CrawlerWriter for FakeProcessingRepo {
+        fn update_crawler_stats(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+
+        fn set_crawler_processing(
+            &self,
+            crawler_id: CrawlerId,
+            processing: bool,
+        ) -> RepositoryResult<usize> {
+            self.crawler_writes
+                .lock()
+                .expect("writes mutex poisoned")
+                .push((crawler_id.get(), processing));
+            Ok(1)
+        }
+    }
+
+    impl BenchmarkReader for FakeProcessingRepo {
+        fn get_benchmark(&self, _benchmark_id: BenchmarkId) -> RepositoryResult<Benchmark> {
+            Err(RepositoryError::NotFound)
+        }
+
+        fn list_benchmarks(&self, _hub_id: HubId) -> RepositoryResult<Vec<Benchmark>> {
+            Ok(vec![])
+        }
+
+        fn list_associations(
+            &self,
+            _benchmark_id: BenchmarkId,
+        ) -> RepositoryResult<Vec<(Product, f32)>> {
+            Ok(vec![])
+        }
+    }
+
+    impl BenchmarkWriter for FakeProcessingRepo {
+        fn set_benchmark_embedding(
+            &self,
+            _benchmark_id: BenchmarkId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+
+        fn set_benchmark_association(
+            &self,
+            _benchmark_id: BenchmarkId,
+            _product_id: ProductId,
+            _distance: SimilarityDistance,
+        ) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+
+        fn remove_benchmark_associations(
+            &self,
+            _benchmark_id: BenchmarkId,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn purge_orphaned_associations(&self) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_benchmark_processing(
+            &self,
+            benchmark_id: BenchmarkId,
+            processing: bool,
+        ) -> RepositoryResult<usize> {
+            self.benchmark_writes
+                .lock()
+                .expect("writes mutex poisoned")
+                .push((benchmark_id.get(), processing));
+            Ok(1)
+        }
+
+        fn update_benchmark_stats(&self, _benchmark_id: BenchmarkId) -> RepositoryResult<usize> {
+            Ok(1)
+        }
+    }
+
+    impl ProductWriter for FakeProcessingRepo {
+        fn create_products(&self, _products: &[NewProduct]) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn update_products(&self, _products: &[NewProduct]) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn upsert_product(&self, _product: &NewProduct) -> RepositoryResult<ProductId> {
+            Ok(ProductId::new(1).unwrap())
+        }
+
+        fn set_product_embedding(
+            &self,
+            _product_id: ProductId,
+            _embedding: &[f32],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn set_product_embeddings_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _embeddings: &[(String, Vec<u8>)],
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_products(&self, _crawler_id: CrawlerId) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn delete_product_by_url(
+            &self,
+            _crawler_id: CrawlerId,
+            _url: &str,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+
+        fn replace_products(
+            &self,
+            _crawler_id: CrawlerId,
+            products: &[NewProduct],
+        ) -> RepositoryResult<usize> {
+            Ok(products.len())
+        }
+
+        fn delete_products_updated_before(
+            &self,
+            _crawler_id: CrawlerId,
+            _cutoff: NaiveDateTime,
+        ) -> RepositoryResult<usize> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn clear_benchmark_processing_resets_only_target_benchmark() {
+        let repo = FakeProcessingRepo::default();
+        let crawler_writes = repo.crawler_writes.clone();
+        let benchmark_writes = repo.benchmark_writes.clone();
+        let benchmark_id = BenchmarkId::new(7).expect("valid benchmark id");
+
+        process_maintenance_message(
+            MaintenanceMessage::ClearBenchmarkProcessing(benchmark_id),
+            repo,
+        )
+        .await;
+
+        assert_eq!(recorded(&benchmark_writes), vec![(7, false)]);
+        assert!(recorded(&crawler_writes).is_empty());
+    }
+
+    #[tokio::test]
+    async fn clear_crawler_processing_skips_unknown_selector() {
+        let repo = FakeProcessingRepo::default();
+        let crawler_writes = repo.crawler_writes.clone();
+        let benchmark_writes = repo.benchmark_writes.clone();
+        let selector = CrawlerSelectorValue::new("missing".to_string()).expect("valid selector");
+
+        process_maintenance_message(MaintenanceMessage::ClearCrawlerProcessing(selector), repo)
+            .await;
+
+        assert!(recorded(&crawler_writes).is_empty());
+        assert!(recorded(&benchmark_writes).is_empty());
+    }
+}
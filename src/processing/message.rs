@@ -0,0 +1,196 @@
+//! Decoding of raw ZeroMQ payloads into crawler messages.
+
+use pushkind_dantes::domain::types::{BenchmarkId, CrawlerSelectorValue, HubId};
+use pushkind_dantes::domain::zmq::ZMQCrawlerMessage;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MessageError {
+    #[error("message does not start with topic {0:?}")]
+    TopicMismatch(String),
+    #[error("invalid message JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Operational messages handled only by this service.
+///
+/// These complement the shared [`ZMQCrawlerMessage`] contract owned by
+/// `pushkind-dantes` and use the same externally tagged JSON shape, e.g.
+/// `{"ClearCrawlerProcessing":"rusteaco"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub enum MaintenanceMessage {
+    /// Reset `processing` for the crawler with the given selector.
+    ClearCrawlerProcessing(CrawlerSelectorValue),
+    /// Reset `processing` for the given benchmark.
+    ClearBenchmarkProcessing(BenchmarkId),
+    /// Delete every product of the crawler with the given selector, keeping
+    /// the crawler itself, e.g. when a store is decommissioned.
+    ClearCrawlerProducts(CrawlerSelectorValue),
+    /// Recompute `num_products` of every crawler and benchmark in the hub
+    /// from the stored rows, without crawling or embedding, e.g. after
+    /// manual database edits.
+    RebuildHubStats(HubId),
+    /// Delete benchmark associations of products that no longer exist, e.g.
+    /// after products were removed outside this service. Sent as the bare
+    /// string `"PurgeOrphanedAssociations"`.
+    PurgeOrphanedAssociations,
+}
+
+/// Matching runs not covered by the shared contract, e.g.
+/// `{"ReembedBenchmark":1}`.
+#[derive(Debug, Clone, Deserialize)]
+pub enum MatchMessage {
+    /// Run benchmark matching with fresh benchmark and product embeddings,
+    /// e.g. after the prompt format changes.
+    ReembedBenchmark(BenchmarkId),
+    /// Run hub category matching with fresh category and product embeddings.
+    ReembedProductCategoryMatch(HubId),
+    /// Rematch only the products of the crawler with the given selector
+    /// against its hub categories, e.g. after that store was re-crawled.
+    CrawlerProductCategoryMatch(CrawlerSelectorValue),
+    /// Generate and store embeddings for every product of the hub that has
+    /// none, without matching, so later runs skip that work.
+    EmbedHubProducts(HubId),
+}
+
+/// A decoded incoming message.
+#[derive(Debug)]
+pub enum IncomingMessage {
+    /// Message from the shared `pushkind-dantes` contract.
+    Crawler(ZMQCrawlerMessage),
+    /// Service-local maintenance message.
+    Maintenance(MaintenanceMessage),
+    /// Service-local matching run.
+    Match(MatchMessage),
+}
+
+/// Strips `topic` from the front of `payload` and parses the remaining JSON.
+///
+/// `SUB` sockets receive the topic either as a prefix of a single frame or as
+/// a separate leading frame; both arrive here concatenated. Whitespace
+/// between the topic and the JSON body is ignored. An empty topic parses the
+/// payload as-is, which is the `PULL` behavior.
+///
+/// The body is decoded as a [`ZMQCrawlerMessage`] first, then as a
+/// [`MaintenanceMessage`] and a [`MatchMessage`]; the shared contract's
+/// error is reported when none matches.
+pub fn parse_crawler_message(payload: &[u8], topic: &str) -> Result<IncomingMessage, MessageError> {
+    let body = payload
+        .strip_prefix(topic.as_bytes())
+        .ok_or_else(|| MessageError::TopicMismatch(topic.to_string()))?
+        .trim_ascii_start();
+
+    let error = match serde_json::from_slice::<ZMQCrawlerMessage>(body) {
+        Ok(message) => return Ok(IncomingMessage::Crawler(message)),
+        Err(error) => error,
+    };
+    if let Ok(message) = serde_json::from_slice::<MaintenanceMessage>(body) {
+        return Ok(IncomingMessage::Maintenance(message));
+    }
+    serde_json::from_slice::<MatchMessage>(body)
+        .map(IncomingMessage::Match)
+        .map_err(|_| MessageError::Json(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_payload_without_topic() {
+        let message = parse_crawler_message(br#"{"Benchmark":1}"#, "").unwrap();
+
+        assert!(matches!(
+            message,
+            IncomingMessage::Crawler(ZMQCrawlerMessage::Benchmark(_))
+        ));
+    }
+
+    #[test]
+    fn strips_topic_prefix_before_parsing() {
+        let message = parse_crawler_message(br#"crawlers {"Benchmark":1}"#, "crawlers").unwrap();
+
+        assert!(matches!(
+            message,
+            IncomingMessage::Crawler(ZMQCrawlerMessage::Benchmark(_))
+        ));
+    }
+
+    #[test]
+    fn parses_maintenance_messages() {
+        let crawler =
+            parse_crawler_message(br#"{"ClearCrawlerProcessing":"rusteaco"}"#, "").unwrap();
+        let benchmark = parse_crawler_message(br#"{"ClearBenchmarkProcessing":7}"#, "").unwrap();
+        let products =
+            parse_crawler_message(br#"{"ClearCrawlerProducts":"teanadin"}"#, "").unwrap();
+        let stats = parse_crawler_message(br#"{"RebuildHubStats":2}"#, "").unwrap();
+        let purge = parse_crawler_message(br#""PurgeOrphanedAssociations""#, "").unwrap();
+
+        assert!(matches!(
+            crawler,
+            IncomingMessage::Maintenance(MaintenanceMessage::ClearCrawlerProcessing(selector))
+                if selector.as_str() == "rusteaco"
+        ));
+        assert!(matches!(
+            benchmark,
+            IncomingMessage::Maintenance(MaintenanceMessage::ClearBenchmarkProcessing(id))
+                if id.get() == 7
+        ));
+        assert!(matches!(
+            products,
+            IncomingMessage::Maintenance(MaintenanceMessage::ClearCrawlerProducts(selector))
+                if selector.as_str() == "teanadin"
+        ));
+        assert!(matches!(
+            stats,
+            IncomingMessage::Maintenance(MaintenanceMessage::RebuildHubStats(id)) if id.get() == 2
+        ));
+        assert!(matches!(
+            purge,
+            IncomingMessage::Maintenance(MaintenanceMessage::PurgeOrphanedAssociations)
+        ));
+    }
+
+    #[test]
+    fn parses_match_messages() {
+        let benchmark = parse_crawler_message(br#"{"ReembedBenchmark":3}"#, "").unwrap();
+        let hub = parse_crawler_message(br#"{"ReembedProductCategoryMatch":2}"#, "").unwrap();
+        let crawler =
+            parse_crawler_message(br#"{"CrawlerProductCategoryMatch":"rusteaco"}"#, "").unwrap();
+        let embed = parse_crawler_message(br#"{"EmbedHubProducts":4}"#, "").unwrap();
+
+        assert!(matches!(
+            benchmark,
+            IncomingMessage::Match(MatchMessage::ReembedBenchmark(id)) if id.get() == 3
+        ));
+        assert!(matches!(
+            hub,
+            IncomingMessage::Match(MatchMessage::ReembedProductCategoryMatch(id))
+                if id.get() == 2
+        ));
+        assert!(matches!(
+            crawler,
+            IncomingMessage::Match(MatchMessage::CrawlerProductCategoryMatch(selector))
+                if selector.as_str() == "rusteaco"
+        ));
+        assert!(matches!(
+            embed,
+            IncomingMessage::Match(MatchMessage::EmbedHubProducts(id)) if id.get() == 4
+        ));
+    }
+
+    #[test]
+    fn rejects_payload_with_other_topic() {
+        let result = parse_crawler_message(br#"benchmarks{"Benchmark":1}"#, "crawlers");
+
+        assert!(matches!(result, Err(MessageError::TopicMismatch(_))));
+    }
+
+    #[test]
+    fn reports_invalid_json_after_topic() {
+        let result = parse_crawler_message(b"crawlers not-json", "crawlers");
+
+        assert!(matches!(result, Err(MessageError::Json(_))));
+    }
+}
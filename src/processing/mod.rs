@@ -1,4 +1,12 @@
 pub mod benchmark;
 pub mod category;
 pub mod crawler;
-pub(crate) mod embedding;
+pub mod dead_letter;
+pub mod embedding;
+pub mod export;
+pub mod log_context;
+pub mod maintenance;
+pub mod message;
+pub mod precompute;
+pub mod search;
+pub mod units;
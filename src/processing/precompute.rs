@@ -0,0 +1,152 @@
+//! Precomputation of product embeddings ahead of matching runs.
+
+use pushkind_dantes::domain::types::HubId;
+
+use crate::processing::category::run_with_hub_processing_guard;
+use crate::processing::embedding::{
+    SharedEmbedder, load_or_generate_embedding, render_product_prompt, truncate_description,
+};
+use crate::processing::log_context::LogContext;
+use crate::repository::{CrawlerReader, ProcessingGuardWriter, ProductReader, ProductWriter};
+
+/// Number of products embedded per hold of the shared embedder; other
+/// matching runs may take their turn between batches.
+const EMBED_BATCH_SIZE: usize = 64;
+
+/// Counters collected during an embedding precomputation run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EmbedStats {
+    pub crawlers_loaded: usize,
+    pub products_loaded: usize,
+    pub embeddings_generated: usize,
+}
+
+/// Generate and persist embeddings for every hub product that lacks one.
+fn embed_hub_products<R>(
+    hub_id: HubId,
+    repo: &R,
+    embedder: &SharedEmbedder,
+) -> Result<EmbedStats, ()>
+where
+    R: CrawlerReader + ProductReader + ProductWriter,
+{
+    let mut stats = EmbedStats::default();
+    let ctx = LogContext::hub(hub_id);
+    let prompt_template = embedder.prompt_template();
+    let description_max_words = embedder.description_max_words();
+
+    let crawlers = match repo.list_crawlers(hub_id) {
+        Ok(crawlers) => crawlers,
+        Err(error) => {
+            log::error!("{ctx} Failed to list crawlers: {error:?}");
+            return Err(());
+        }
+    };
+    stats.crawlers_loaded = crawlers.len();
+
+    for crawler in crawlers {
+        let products = match repo.list_products_without_embedding(crawler.id) {
+            Ok(products) => products,
+            Err(error) => {
+                log::error!(
+                    "{} Failed to list products without embedding: {error:?}",
+                    ctx.clone().with("crawler_id", crawler.id)
+                );
+                return Err(());
+            }
+        };
+        stats.products_loaded += products.len();
+
+        for batch in products.chunks(EMBED_BATCH_SIZE) {
+            let mut embedder = match embedder.acquire() {
+                Ok(embedder) => embedder,
+                Err(error) => {
+                    log::error!("{ctx} {error}");
+                    return Err(());
+                }
+            };
+
+            for product in batch {
+                let product_text = render_product_prompt(
+                    prompt_template,
+                    product.name.as_str(),
+                    product.sku.as_str(),
+                    product.category.as_deref().unwrap_or(""),
+                    product.units.as_deref().unwrap_or(""),
+                    product.price.get(),
+                    product.amount.map(|value| value.get()).unwrap_or_default(),
+                    truncate_description(
+                        product.description.as_deref().unwrap_or(""),
+                        description_max_words,
+                    ),
+                );
+                match load_or_generate_embedding(
+                    None,
+                    product_text,
+                    &mut embedder,
+                    |value| {
+                        repo.set_product_embedding(product.id, value)
+                            .map(|_| ())
+                            .map_err(|error| {
+                                format!(
+                                    "{ctx} Failed to persist product embedding for {}: {error:?}",
+                                    product.id
+                                )
+                            })
+                    },
+                    false,
+                ) {
+                    Ok(outcome) => stats.embeddings_generated += usize::from(outcome.generated),
+                    Err(error) => {
+                        log::error!(
+                            "{ctx} Failed to generate product embedding for {}: {error}",
+                            product.id
+                        );
+                        return Err(());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Handle a request to precompute the embeddings of a hub's products.
+///
+/// Products that already have an embedding are not loaded, and associations
+/// and category assignments are left untouched. The run holds the hub
+/// processing guard, so it never overlaps a crawl or matching run in the hub.
+///
+/// Returns the run's [`EmbedStats`], or `None` when the run was skipped by
+/// the processing guard or failed; both cases are logged.
+pub async fn process_embed_hub_products_message<R>(
+    hub_id: HubId,
+    repo: R,
+    embedder: SharedEmbedder,
+) -> Option<EmbedStats>
+where
+    R: CrawlerReader + ProductReader + ProductWriter + ProcessingGuardWriter,
+{
+    let ctx = LogContext::hub(hub_id);
+    log::info!("{ctx} Received EmbedHubProducts");
+
+    match run_with_hub_processing_guard(hub_id, "EmbedHubProducts", &repo, || {
+        embed_hub_products(hub_id, &repo, &embedder)
+    }) {
+        Ok(Some(stats)) => {
+            log::info!(
+                "{ctx} Finished EmbedHubProducts: crawlers_loaded={}, products_loaded={}, embeddings_generated={}",
+                stats.crawlers_loaded,
+                stats.products_loaded,
+                stats.embeddings_generated
+            );
+            Some(stats)
+        }
+        Ok(None) => None,
+        Err(()) => {
+            log::error!("{ctx} EmbedHubProducts failed");
+            None
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Semantic search of a hub's products by free text.
+
+use std::collections::HashMap;
+
+use pushkind_dantes::domain::product::Product;
+use pushkind_dantes::domain::types::HubId;
+
+use crate::processing::embedding::{
+    INDEX_METRIC, SharedEmbedder, distance_to_similarity, reusable_embedding, search_top_k,
+};
+use crate::processing::log_context::LogContext;
+use crate::repository::{CrawlerReader, ProductReader};
+
+/// A product found by [`search_products`].
+#[derive(Debug, Clone)]
+pub struct ProductMatch {
+    pub product: Product,
+    /// Cosine similarity between the query and the product embedding.
+    pub similarity: f32,
+}
+
+/// Returns the `k` hub products most similar to the free-text `query`,
+/// closest first.
+///
+/// The query is embedded with the shared embedder as is, without a prompt
+/// template. Only stored product embeddings are searched; see
+/// [`search_products_by_embedding`].
+pub fn search_products<R>(
+    repo: &R,
+    embedder: &SharedEmbedder,
+    hub_id: HubId,
+    query: &str,
+    k: usize,
+) -> Result<Vec<ProductMatch>, String>
+where
+    R: CrawlerReader + ProductReader,
+{
+    let query_embedding = {
+        let mut embedder = embedder.acquire()?;
+        let query_embedding = embedder.embed_text(query.to_string())?;
+        if query_embedding.len() != embedder.dimensions() {
+            return Err(format!(
+                "Query embedding has {} dimensions, expected {}",
+                query_embedding.len(),
+                embedder.dimensions()
+            ));
+        }
+        query_embedding
+    };
+    search_products_by_embedding(repo, hub_id, &query_embedding, k)
+}
+
+/// Returns the `k` hub products whose stored embedding is closest to
+/// `query_embedding`, closest first.
+///
+/// Products without an embedding, or with one of another dimension (e.g.
+/// from a previous model), are skipped rather than embedded on the fly, so
+/// a search never writes; run `EmbedHubProducts` to cover them.
+pub fn search_products_by_embedding<R>(
+    repo: &R,
+    hub_id: HubId,
+    query_embedding: &[f32],
+    k: usize,
+) -> Result<Vec<ProductMatch>, String>
+where
+    R: CrawlerReader + ProductReader,
+{
+    let ctx = LogContext::hub(hub_id);
+    let crawlers = repo
+        .list_crawlers(hub_id)
+        .map_err(|error| format!("Failed to list crawlers: {error:?}"))?;
+
+    let mut products = HashMap::new();
+    let mut embeddings = Vec::new();
+    let mut skipped = 0;
+    for crawler in crawlers {
+        let crawler_products = repo
+            .list_products(crawler.id)
+            .map_err(|error| format!("Failed to list products: {error:?}"))?;
+        for product in crawler_products {
+            match reusable_embedding(product.embedding.as_deref(), query_embedding.len(), false) {
+                Some(embedding) => {
+                    embeddings.push((product.id.get(), embedding.into_owned()));
+                    products.insert(product.id.get(), product);
+                }
+                None => skipped += 1,
+            }
+        }
+    }
+    if skipped > 0 {
+        log::debug!("{ctx} Skipped {skipped} products without a usable embedding");
+    }
+
+    // Stored embeddings were filtered to the query's length above.
+    let neighbors = search_top_k(query_embedding, &embeddings, k, query_embedding.len())
+        .map_err(|error| format!("Failed to search top {k} products: {error:?}"))?;
+
+    Ok(neighbors
+        .into_iter()
+        .filter_map(|(key, distance)| {
+            let product = products.remove(&i32::try_from(key).ok()?)?;
+            Some(ProductMatch {
+                product,
+                similarity: distance_to_similarity(INDEX_METRIC, distance),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,78 @@
+//! Normalization of scraped amounts and units before they are embedded.
+
+use std::fmt;
+
+/// Unit that amounts of comparable products are converted to.
+///
+/// Weights become kilograms and volumes litres, so `500 г` and `0,5 кг`
+/// produce the same prompt; pieces stay separate because they cannot be
+/// converted to a weight.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CanonicalUnit {
+    Kilogram,
+    Litre,
+    Piece,
+    /// A unit without a known conversion, kept as scraped (trimmed).
+    Other(String),
+}
+
+impl fmt::Display for CanonicalUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CanonicalUnit::Kilogram => f.write_str("кг"),
+            CanonicalUnit::Litre => f.write_str("л"),
+            CanonicalUnit::Piece => f.write_str("шт"),
+            CanonicalUnit::Other(units) => f.write_str(units),
+        }
+    }
+}
+
+/// Converts `amount` in scraped `units` to a [`CanonicalUnit`].
+///
+/// Units are matched case-insensitively and without a trailing dot, in
+/// Russian and English spellings (`г`, `гр`, `кг`, `мл`, `л`, `шт`, `g`,
+/// `kg`, ...). Unknown units, including an empty one, keep their amount.
+pub fn normalize_units(amount: f64, units: &str) -> (f64, CanonicalUnit) {
+    let key = units.trim().trim_end_matches('.').to_lowercase();
+    match key.as_str() {
+        "г" | "гр" | "грамм" | "граммов" | "g" | "gr" => {
+            (amount / 1000.0, CanonicalUnit::Kilogram)
+        }
+        "кг" | "kg" => (amount, CanonicalUnit::Kilogram),
+        "мл" | "ml" => (amount / 1000.0, CanonicalUnit::Litre),
+        "л" | "l" => (amount, CanonicalUnit::Litre),
+        "шт" | "штук" | "pcs" | "pc" => (amount, CanonicalUnit::Piece),
+        _ => (amount, CanonicalUnit::Other(units.trim().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grams_become_kilograms() {
+        assert_eq!(normalize_units(500.0, "г"), (0.5, CanonicalUnit::Kilogram));
+        assert_eq!(
+            normalize_units(250.0, "гр."),
+            (0.25, CanonicalUnit::Kilogram)
+        );
+        assert_eq!(normalize_units(1.5, "КГ"), (1.5, CanonicalUnit::Kilogram));
+    }
+
+    #[test]
+    fn millilitres_become_litres() {
+        assert_eq!(normalize_units(330.0, "мл"), (0.33, CanonicalUnit::Litre));
+        assert_eq!(normalize_units(1.0, "л"), (1.0, CanonicalUnit::Litre));
+    }
+
+    #[test]
+    fn pieces_and_unknown_units_keep_their_amount() {
+        assert_eq!(normalize_units(3.0, "шт"), (3.0, CanonicalUnit::Piece));
+        assert_eq!(
+            normalize_units(2.0, " уп "),
+            (2.0, CanonicalUnit::Other("уп".to_string()))
+        );
+        assert_eq!(normalize_units(500.0, "г").1.to_string(), "кг");
+    }
+}
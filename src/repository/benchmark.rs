@@ -1,13 +1,19 @@
+use std::collections::HashMap;
+
 use bytemuck::cast_slice;
+use diesel::dsl::count_distinct;
 use diesel::prelude::*;
 use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
 use pushkind_dantes::domain::benchmark::Benchmark;
-use pushkind_dantes::domain::types::{BenchmarkId, ProductId, SimilarityDistance};
+use pushkind_dantes::domain::product::Product;
+use pushkind_dantes::domain::types::{BenchmarkId, HubId, ProductId, SimilarityDistance};
 use pushkind_dantes::models::benchmark::Benchmark as DbBenchmark;
+use pushkind_dantes::models::product::Product as DbProduct;
 
 use crate::repository::BenchmarkReader;
 use crate::repository::BenchmarkWriter;
 use crate::repository::DieselRepository;
+use crate::repository::product::products_with_images;
 
 impl BenchmarkReader for DieselRepository {
     fn get_benchmark(&self, benchmark_id: BenchmarkId) -> RepositoryResult<Benchmark> {
@@ -23,6 +29,62 @@ impl BenchmarkReader for DieselRepository {
         Benchmark::try_from(benchmark)
             .map_err(|err| RepositoryError::ValidationError(err.to_string()))
     }
+
+    fn list_benchmarks(&self, hub_id: HubId) -> RepositoryResult<Vec<Benchmark>> {
+        use pushkind_dantes::schema::benchmarks;
+
+        let mut conn = self.conn()?;
+
+        let result = benchmarks::table
+            .filter(benchmarks::hub_id.eq(hub_id.get()))
+            .order_by(benchmarks::id.asc())
+            .load::<DbBenchmark>(&mut conn)?;
+
+        result
+            .into_iter()
+            .map(Benchmark::try_from)
+            .collect::<Result<Vec<Benchmark>, _>>()
+            .map_err(|err| RepositoryError::ValidationError(err.to_string()))
+    }
+
+    fn list_associations(
+        &self,
+        benchmark_id: BenchmarkId,
+    ) -> RepositoryResult<Vec<(Product, f32)>> {
+        use pushkind_dantes::schema::{product_benchmark, products};
+
+        let mut conn = self.conn()?;
+
+        // The `distance` column holds a similarity, so higher is closer
+        let associations: Vec<(i32, f32)> = product_benchmark::table
+            .filter(product_benchmark::benchmark_id.eq(benchmark_id.get()))
+            .order((
+                product_benchmark::distance.desc(),
+                product_benchmark::product_id.asc(),
+            ))
+            .select((product_benchmark::product_id, product_benchmark::distance))
+            .load(&mut conn)?;
+
+        let product_ids: Vec<i32> = associations.iter().map(|(id, _)| *id).collect();
+        let db_products = products::table
+            .filter(products::id.eq_any(&product_ids))
+            .load::<DbProduct>(&mut conn)?;
+        let mut products_by_id: HashMap<i32, Product> =
+            products_with_images(&mut conn, db_products)?
+                .into_iter()
+                .map(|product| (product.id.get(), product))
+                .collect();
+
+        // Taking each product out keeps only its first, most similar row
+        Ok(associations
+            .into_iter()
+            .filter_map(|(product_id, distance)| {
+                products_by_id
+                    .remove(&product_id)
+                    .map(|product| (product, distance))
+            })
+            .collect())
+    }
 }
 
 impl BenchmarkWriter for DieselRepository {
@@ -60,6 +122,20 @@ impl BenchmarkWriter for DieselRepository {
         Ok(affected)
     }
 
+    fn purge_orphaned_associations(&self) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::{product_benchmark, products};
+
+        let mut conn = self.conn()?;
+
+        // Anti-join: keep only rows whose product is still stored
+        let affected = diesel::delete(product_benchmark::table.filter(diesel::dsl::not(
+            product_benchmark::product_id.eq_any(products::table.select(products::id)),
+        )))
+        .execute(&mut conn)?;
+
+        Ok(affected)
+    }
+
     fn set_benchmark_association(
         &self,
         benchmark_id: BenchmarkId,
@@ -91,10 +167,15 @@ impl BenchmarkWriter for DieselRepository {
 
         let mut conn = self.conn()?;
 
-        let affected =
-            diesel::update(benchmarks::table.filter(benchmarks::id.eq(benchmark_id.get())))
-                .set(benchmarks::processing.eq(processing))
-                .execute(&mut conn)?;
+        // Compare-and-set, as for crawlers: only a row still in the opposite
+        // state is updated, so a running benchmark cannot be claimed twice.
+        let affected = diesel::update(
+            benchmarks::table
+                .filter(benchmarks::id.eq(benchmark_id.get()))
+                .filter(benchmarks::processing.eq(!processing)),
+        )
+        .set(benchmarks::processing.eq(processing))
+        .execute(&mut conn)?;
 
         Ok(affected)
     }
@@ -105,10 +186,11 @@ impl BenchmarkWriter for DieselRepository {
 
         let mut conn = self.conn()?;
 
-        // Count products for the benchmark to update statistics
+        // Count distinct products so duplicate association rows are not
+        // reported as extra matches
         let product_count: i64 = product_benchmark::table
             .filter(product_benchmark::benchmark_id.eq(benchmark_id.get()))
-            .count()
+            .select(count_distinct(product_benchmark::product_id))
             .get_result(&mut conn)?;
 
         // Update timestamp, processing state and product count
@@ -1,17 +1,26 @@
+use std::collections::HashMap;
+
 use bytemuck::cast_slice;
 use diesel::prelude::*;
 use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
 use pushkind_dantes::domain::category::Category;
+use pushkind_dantes::domain::product::Product;
 use pushkind_dantes::domain::types::{
     CategoryAssignmentSource, CategoryId, CrawlerId, HubId, ProductId,
 };
 use pushkind_dantes::models::category::Category as DbCategory;
+use pushkind_dantes::models::product::Product as DbProduct;
 
+use crate::repository::product::products_with_images;
 use crate::repository::{
     CategoryReader, CategoryWriter, DieselRepository, ProcessingGuardReader, ProcessingGuardWriter,
-    ProductCategoryWriter,
+    ProductCategoryReader, ProductCategoryWriter,
 };
 
+/// Number of product ids updated per statement by
+/// `set_product_categories_automatic`.
+const CATEGORY_ASSIGNMENT_CHUNK_SIZE: usize = 500;
+
 impl CategoryReader for DieselRepository {
     fn list_categories(&self, hub_id: HubId) -> RepositoryResult<Vec<Category>> {
         use pushkind_dantes::schema::categories;
@@ -78,6 +87,46 @@ impl ProductCategoryWriter for DieselRepository {
         Ok(affected)
     }
 
+    fn set_product_categories_automatic(
+        &self,
+        assignments: &[(ProductId, Option<CategoryId>)],
+    ) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
+
+        // One statement per target category instead of one per product.
+        let mut by_category: HashMap<Option<i32>, Vec<i32>> = HashMap::new();
+        for (product_id, category_id) in assignments {
+            by_category
+                .entry(category_id.map(|value| value.get()))
+                .or_default()
+                .push(product_id.get());
+        }
+
+        let mut conn = self.conn()?;
+
+        conn.immediate_transaction::<usize, RepositoryError, _>(|conn| {
+            let mut affected = 0;
+            for (category_id, product_ids) in &by_category {
+                for chunk in product_ids.chunks(CATEGORY_ASSIGNMENT_CHUNK_SIZE) {
+                    affected += diesel::update(
+                        products::table.filter(products::id.eq_any(chunk)).filter(
+                            products::category_assignment_source
+                                .ne(CategoryAssignmentSource::Manual.as_str()),
+                        ),
+                    )
+                    .set((
+                        products::category_id.eq(*category_id),
+                        products::category_assignment_source
+                            .eq(CategoryAssignmentSource::Automatic.as_str()),
+                        products::updated_at.eq(diesel::dsl::now),
+                    ))
+                    .execute(conn)?;
+                }
+            }
+            Ok(affected)
+        })
+    }
+
     fn clear_product_categories_by_crawler(
         &self,
         crawler_id: CrawlerId,
@@ -103,6 +152,49 @@ impl ProductCategoryWriter for DieselRepository {
 
         Ok(affected)
     }
+
+    fn reset_product_category_source(&self, product_id: ProductId) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let affected = diesel::update(
+            products::table
+                .filter(products::id.eq(product_id.get()))
+                .filter(
+                    products::category_assignment_source
+                        .eq(CategoryAssignmentSource::Manual.as_str()),
+                ),
+        )
+        .set((
+            products::category_id.eq::<Option<i32>>(None),
+            products::category_assignment_source.eq(CategoryAssignmentSource::Automatic.as_str()),
+            products::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut conn)?;
+
+        Ok(affected)
+    }
+}
+
+impl ProductCategoryReader for DieselRepository {
+    fn list_manual_category_products(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<Product>> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let products = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .filter(
+                products::category_assignment_source.eq(CategoryAssignmentSource::Manual.as_str()),
+            )
+            .load::<DbProduct>(&mut conn)?;
+
+        products_with_images(&mut conn, products)
+    }
 }
 
 impl ProcessingGuardReader for DieselRepository {
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeDelta};
 use diesel::prelude::*;
 use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
 use pushkind_dantes::domain::crawler::Crawler;
@@ -35,6 +39,40 @@ impl CrawlerReader for DieselRepository {
             .collect::<Result<Vec<Crawler>, _>>()
             .map_err(|err| RepositoryError::ValidationError(err.to_string()))
     }
+
+    fn list_due_crawlers(
+        &self,
+        now: NaiveDateTime,
+        intervals: &HashMap<String, Duration>,
+    ) -> RepositoryResult<Vec<Crawler>> {
+        use pushkind_dantes::schema::crawlers;
+
+        let mut conn = self.conn()?;
+
+        let mut result = Vec::new();
+        for (selector, interval) in intervals {
+            // An interval reaching before the earliest timestamp is never due.
+            let Some(cutoff) = TimeDelta::from_std(*interval)
+                .ok()
+                .and_then(|interval| now.checked_sub_signed(interval))
+            else {
+                continue;
+            };
+            let due = crawlers::table
+                .filter(crawlers::selector.eq(selector.as_str()))
+                .filter(crawlers::processing.eq(false))
+                .filter(crawlers::updated_at.le(cutoff))
+                .load::<DbCrawler>(&mut conn)?;
+            result.extend(due);
+        }
+        result.sort_by_key(|crawler| crawler.id);
+
+        result
+            .into_iter()
+            .map(Crawler::try_from)
+            .collect::<Result<Vec<Crawler>, _>>()
+            .map_err(|err| RepositoryError::ValidationError(err.to_string()))
+    }
 }
 
 impl CrawlerWriter for DieselRepository {
@@ -71,9 +109,15 @@ impl CrawlerWriter for DieselRepository {
 
         let mut conn = self.conn()?;
 
-        let affected = diesel::update(crawlers::table.filter(crawlers::id.eq(crawler_id.get())))
-            .set(crawlers::processing.eq(processing))
-            .execute(&mut conn)?;
+        // Compare-and-set: only a row still in the opposite state is updated,
+        // so of two concurrent callers setting `true` exactly one sees `1`.
+        let affected = diesel::update(
+            crawlers::table
+                .filter(crawlers::id.eq(crawler_id.get()))
+                .filter(crawlers::processing.eq(!processing)),
+        )
+        .set(crawlers::processing.eq(processing))
+        .execute(&mut conn)?;
 
         Ok(affected)
     }
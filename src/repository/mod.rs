@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use diesel::Connection;
 use pushkind_common::db::{DbConnection, DbPool};
 use pushkind_common::repository::errors::RepositoryResult;
 use pushkind_dantes::domain::benchmark::Benchmark;
@@ -28,34 +33,221 @@ impl DieselRepository {
     pub fn conn(&self) -> RepositoryResult<DbConnection> {
         Ok(self.pool.get()?)
     }
+
+    /// Run `operation` on one connection inside a single Diesel transaction.
+    ///
+    /// The transaction commits when `operation` returns `Ok` and rolls back
+    /// on `Err`, so multi-step writes are applied completely or not at all.
+    /// SQLite holds its write lock until the transaction ends; keep
+    /// `operation` short and free of I/O other than the database.
+    pub fn with_transaction<T, F>(&self, operation: F) -> RepositoryResult<T>
+    where
+        F: FnOnce(&mut DbConnection) -> RepositoryResult<T>,
+    {
+        let mut conn = self.conn()?;
+        conn.transaction(operation)
+    }
+}
+
+/// Column used to order paginated product listings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProductSort {
+    Price,
+    Name,
+    UpdatedAt,
+}
+
+/// Lightweight product row for list views.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductSummary {
+    pub id: ProductId,
+    pub name: String,
+    pub price: f64,
+    /// Category text scraped from the store.
+    pub category: Option<String>,
+    /// URL of the product's first stored image.
+    pub primary_image: Option<String>,
+}
+
+/// Stored product embedding with the fields its prompt was built from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredProductEmbedding {
+    pub url: String,
+    pub name: String,
+    pub sku: String,
+    pub category: Option<String>,
+    pub units: Option<String>,
+    pub price: f64,
+    pub amount: Option<f64>,
+    pub description: Option<String>,
+    pub embedding: Vec<u8>,
+}
+
+/// A category string scraped from products and how many products carry it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProductCategoryCount {
+    pub category: String,
+    pub products: usize,
 }
 
 /// Defines read-only operations for accessing products.
 pub trait ProductReader {
     fn list_products(&self, crawler_id: CrawlerId) -> RepositoryResult<Vec<Product>>;
+
+    /// Get the crawler's product stored at exactly `url`, with its images,
+    /// or `None` when there is none.
+    fn get_product_by_url(
+        &self,
+        crawler_id: CrawlerId,
+        url: &str,
+    ) -> RepositoryResult<Option<Product>>;
+
+    /// Count a crawler's products without loading them.
+    fn count_products(&self, crawler_id: CrawlerId) -> RepositoryResult<usize>;
+
+    /// Count the products of every crawler in the hub without loading them.
+    fn count_hub_products(&self, hub_id: HubId) -> RepositoryResult<usize>;
+
+    /// List the distinct non-empty `category` strings scraped into the hub's
+    /// products with their product counts, most common first.
+    ///
+    /// Used to seed the hub's category directory before category matching.
+    fn list_distinct_product_categories(
+        &self,
+        hub_id: HubId,
+    ) -> RepositoryResult<Vec<ProductCategoryCount>>;
+
+    /// List a crawler's products whose embedding has not been generated yet.
+    fn list_products_without_embedding(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<Product>>;
+
+    /// List a crawler's products changed at or after `since`, oldest change
+    /// first, for incremental downstream syncs.
+    fn list_products_updated_since(
+        &self,
+        crawler_id: CrawlerId,
+        since: NaiveDateTime,
+    ) -> RepositoryResult<Vec<Product>>;
+
+    /// List one page of a crawler's products ordered by `sort`.
+    ///
+    /// Rows with equal sort keys are ordered by id so pagination is stable.
+    fn list_products_sorted(
+        &self,
+        crawler_id: CrawlerId,
+        sort: ProductSort,
+        desc: bool,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<Product>>;
+
+    /// List one page of a crawler's products as [`ProductSummary`] rows,
+    /// ordered by id.
+    ///
+    /// Only the summary columns and one image per product are loaded.
+    fn list_product_summaries(
+        &self,
+        crawler_id: CrawlerId,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<ProductSummary>>;
+
+    /// List a crawler's products that have an embedding, with the fields the
+    /// embedding prompt is built from.
+    fn list_product_embeddings(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<StoredProductEmbedding>>;
 }
 
 /// Defines write operations for storing and mutating products.
 pub trait ProductWriter {
     fn create_products(&self, products: &[NewProduct]) -> RepositoryResult<usize>;
     fn update_products(&self, products: &[NewProduct]) -> RepositoryResult<usize>;
+
+    /// Insert a product, or update the one with the same crawler and URL,
+    /// replacing its images.
+    ///
+    /// Returns the id of the written row. Crawls keep using the batch
+    /// methods.
+    fn upsert_product(&self, product: &NewProduct) -> RepositoryResult<ProductId>;
     fn set_product_embedding(
         &self,
         product_id: ProductId,
         embedding: &[f32],
     ) -> RepositoryResult<usize>;
+
+    /// Store raw embedding blobs on a crawler's products, matched by URL.
+    ///
+    /// URLs without a product are skipped. Returns the number of rows updated.
+    fn set_product_embeddings_by_url(
+        &self,
+        crawler_id: CrawlerId,
+        embeddings: &[(String, Vec<u8>)],
+    ) -> RepositoryResult<usize>;
     fn delete_products(&self, crawler_id: CrawlerId) -> RepositoryResult<usize>;
+
+    /// Delete the crawler's products at `url` together with their images and
+    /// benchmark associations.
+    ///
+    /// Variants stored under `url` with a `#fragment`, e.g. `#SKU-100`, are
+    /// deleted as well. Used for targeted cleanup, e.g. when a known product
+    /// page is gone. Returns the number of deleted products, `0` when none
+    /// matched.
+    fn delete_product_by_url(&self, crawler_id: CrawlerId, url: &str) -> RepositoryResult<usize>;
+
+    /// Replace a crawler's products with `products` in one transaction.
+    ///
+    /// The old rows, their images and benchmark associations are deleted and
+    /// the new rows inserted atomically, so a failure leaves the previous
+    /// catalog in place. Unlike [`Self::create_products`] the write lock is
+    /// held for the whole catalog. Returns the number of inserted rows.
+    fn replace_products(
+        &self,
+        crawler_id: CrawlerId,
+        products: &[NewProduct],
+    ) -> RepositoryResult<usize>;
+
+    /// Delete a crawler's products last written before `cutoff`, together
+    /// with their images and benchmark associations.
+    ///
+    /// Used after a streaming crawl to drop products the run did not see.
+    fn delete_products_updated_before(
+        &self,
+        crawler_id: CrawlerId,
+        cutoff: NaiveDateTime,
+    ) -> RepositoryResult<usize>;
 }
 
 /// Retrieves a single crawler from the repository.
 pub trait CrawlerReader {
     fn get_crawler(&self, selector: &CrawlerSelectorValue) -> RepositoryResult<Crawler>;
     fn list_crawlers(&self, hub_id: HubId) -> RepositoryResult<Vec<Crawler>>;
+
+    /// List the crawlers due for a scheduled crawl at `now`, ordered by id.
+    ///
+    /// `intervals` maps crawler selectors to their crawl interval. A crawler
+    /// is due when it is not processing and its `updated_at`, refreshed when
+    /// a run finishes, is at least its interval before `now`; crawlers
+    /// without an interval are never due.
+    fn list_due_crawlers(
+        &self,
+        now: NaiveDateTime,
+        intervals: &HashMap<String, Duration>,
+    ) -> RepositoryResult<Vec<Crawler>>;
 }
 
 /// Persists changes to crawler records.
 pub trait CrawlerWriter {
     fn update_crawler_stats(&self, crawler_id: CrawlerId) -> RepositoryResult<usize>;
+
+    /// Atomically switch the crawler's `processing` flag to `processing`.
+    ///
+    /// Returns `1` when the flag changed and `0` when it already had the
+    /// requested value (or the crawler does not exist), so a caller setting
+    /// `true` knows whether it claimed the crawler.
     fn set_crawler_processing(
         &self,
         crawler_id: CrawlerId,
@@ -66,6 +258,15 @@ pub trait CrawlerWriter {
 /// Provides read access to benchmark metadata.
 pub trait BenchmarkReader {
     fn get_benchmark(&self, benchmark_id: BenchmarkId) -> RepositoryResult<Benchmark>;
+    fn list_benchmarks(&self, hub_id: HubId) -> RepositoryResult<Vec<Benchmark>>;
+
+    /// Return the products associated with a benchmark, with their images,
+    /// and the stored similarity of each, most similar first.
+    ///
+    /// A product associated more than once is returned once, with its best
+    /// similarity; associations of deleted products are skipped.
+    fn list_associations(&self, benchmark_id: BenchmarkId)
+    -> RepositoryResult<Vec<(Product, f32)>>;
 }
 
 /// Provides methods to mutate benchmark records and their associations.
@@ -82,6 +283,18 @@ pub trait BenchmarkWriter {
         distance: SimilarityDistance,
     ) -> RepositoryResult<usize>;
     fn remove_benchmark_associations(&self, benchmark_id: BenchmarkId) -> RepositoryResult<usize>;
+
+    /// Delete `product_benchmark` rows whose product no longer exists, e.g.
+    /// after products were deleted outside [`ProductWriter::delete_products`].
+    ///
+    /// Returns the number of rows removed.
+    fn purge_orphaned_associations(&self) -> RepositoryResult<usize>;
+
+    /// Atomically switch the benchmark's `processing` flag to `processing`.
+    ///
+    /// Returns `1` when the flag changed and `0` when it already had the
+    /// requested value (or the benchmark does not exist), so a caller setting
+    /// `true` knows whether it claimed the benchmark.
     fn set_benchmark_processing(
         &self,
         benchmark_id: BenchmarkId,
@@ -113,9 +326,35 @@ pub trait ProductCategoryWriter {
         category_id: Option<CategoryId>,
     ) -> RepositoryResult<usize>;
 
+    /// Set automatic category assignments for many products in one
+    /// transaction.
+    ///
+    /// Products whose category was set manually are skipped, as with
+    /// [`Self::set_product_category_automatic`]. Returns the number of
+    /// updated products.
+    fn set_product_categories_automatic(
+        &self,
+        assignments: &[(ProductId, Option<CategoryId>)],
+    ) -> RepositoryResult<usize>;
+
     /// Clear category assignments for all products under a crawler.
     fn clear_product_categories_by_crawler(&self, crawler_id: CrawlerId)
     -> RepositoryResult<usize>;
+
+    /// Return a manually categorized product to automatic matching.
+    ///
+    /// Clears `category_id` and sets the source to automatic so the next
+    /// match run assigns it. Products that are not manual are left untouched.
+    fn reset_product_category_source(&self, product_id: ProductId) -> RepositoryResult<usize>;
+}
+
+/// Provides read access to product-to-category assignment state.
+pub trait ProductCategoryReader {
+    /// List a crawler's products whose category was set manually.
+    fn list_manual_category_products(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<Product>>;
 }
 
 /// Provides read methods for hub-scoped processing guard checks.
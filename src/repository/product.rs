@@ -1,19 +1,27 @@
 use std::collections::HashMap;
 
 use bytemuck::cast_slice;
-use chrono::Utc;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::QueryResult;
 use pushkind_common::db::DbConnection;
 use pushkind_common::repository::errors::{RepositoryError, RepositoryResult};
 use pushkind_dantes::domain::product::{NewProduct, Product};
-use pushkind_dantes::domain::types::{CrawlerId, ImageUrl, ProductId};
+use pushkind_dantes::domain::types::{CrawlerId, HubId, ImageUrl, ProductId};
 use pushkind_dantes::models::product::{NewProduct as DbNewProduct, Product as DbProduct};
 use pushkind_dantes::models::product_image::{NewProductImage, ProductImage};
 
 use crate::repository::DieselRepository;
+use crate::repository::ProductCategoryCount;
 use crate::repository::ProductReader;
+use crate::repository::ProductSort;
+use crate::repository::ProductSummary;
 use crate::repository::ProductWriter;
+use crate::repository::StoredProductEmbedding;
+
+/// Number of rows inserted per transaction by `create_products`, and per
+/// statement when products are replaced or deleted by id.
+const CREATE_PRODUCTS_CHUNK_SIZE: usize = 500;
 
 fn replace_product_images(
     conn: &mut DbConnection,
@@ -44,78 +52,430 @@ fn replace_product_images(
     Ok(())
 }
 
+/// Inserts `products` with their images on `conn`, returning the number of
+/// inserted rows.
+fn insert_products(conn: &mut DbConnection, products: &[NewProduct]) -> RepositoryResult<usize> {
+    use pushkind_dantes::schema::products;
+
+    if products.is_empty() {
+        return Ok(0);
+    }
+
+    let db_products = products
+        .iter()
+        .map(|product| DbNewProduct::from(product.clone()))
+        .collect::<Vec<_>>();
+    let inserted_rows = diesel::insert_into(products::table)
+        .values(&db_products)
+        .execute(conn)?;
+
+    // Resolve the generated ids by the `(crawler_id, url)` key to
+    // attach images to the freshly inserted rows.
+    let urls = products
+        .iter()
+        .filter_map(|product| product.url.as_ref().map(|url| url.as_str()))
+        .collect::<Vec<_>>();
+    let ids_by_key: HashMap<(i32, String), i32> = products::table
+        .filter(products::url.eq_any(&urls))
+        .select((products::id, products::crawler_id, products::url))
+        .load::<(i32, i32, String)>(conn)?
+        .into_iter()
+        .map(|(id, crawler_id, url)| ((crawler_id, url), id))
+        .collect();
+
+    for product in products.iter().filter(|product| !product.images.is_empty()) {
+        let Some(url) = product.url.as_ref() else {
+            continue;
+        };
+        let key = (product.crawler_id.get(), url.as_str().to_string());
+        if let Some(product_id) = ids_by_key.get(&key) {
+            replace_product_images(conn, *product_id, &product.images)?;
+        }
+    }
+
+    Ok(inserted_rows)
+}
+
+/// Inserts `product`, or updates the row with the same `(crawler_id, url)`,
+/// replacing its images; returns the row id.
+fn upsert_product_row(conn: &mut DbConnection, product: &NewProduct) -> RepositoryResult<i32> {
+    use pushkind_dantes::schema::products;
+
+    let db_product: DbNewProduct = product.clone().into();
+    let product_id = diesel::insert_into(products::table)
+        .values(&db_product)
+        .on_conflict((products::crawler_id, products::url))
+        .do_update()
+        .set((&db_product, products::updated_at.eq(Utc::now().naive_utc())))
+        .returning(products::id)
+        .get_result::<i32>(conn)?;
+    replace_product_images(conn, product_id, &product.images)?;
+
+    Ok(product_id)
+}
+
+/// Deletes a crawler's products with their images and benchmark
+/// associations on `conn`.
+fn delete_crawler_products(conn: &mut DbConnection, crawler_id: CrawlerId) -> QueryResult<usize> {
+    use pushkind_dantes::schema::products;
+
+    // Fetch product ids to cascade delete related benchmark associations
+    let ids: Vec<i32> = products::table
+        .filter(products::crawler_id.eq(crawler_id.get()))
+        .select(products::id)
+        .load(conn)?;
+
+    delete_products_by_id(conn, &ids)
+}
+
+/// Deletes the products with `ids` together with their images and benchmark
+/// associations.
+fn delete_products_by_id(conn: &mut DbConnection, ids: &[i32]) -> QueryResult<usize> {
+    use pushkind_dantes::schema::{product_benchmark, product_images, products};
+
+    let mut deleted = 0;
+    for ids in ids.chunks(CREATE_PRODUCTS_CHUNK_SIZE) {
+        diesel::delete(product_images::table.filter(product_images::product_id.eq_any(ids)))
+            .execute(conn)?;
+        diesel::delete(product_benchmark::table.filter(product_benchmark::product_id.eq_any(ids)))
+            .execute(conn)?;
+        deleted +=
+            diesel::delete(products::table.filter(products::id.eq_any(ids))).execute(conn)?;
+    }
+    Ok(deleted)
+}
+
+/// Load images for the given products and convert them into domain values.
+pub(super) fn products_with_images(
+    conn: &mut DbConnection,
+    products: Vec<DbProduct>,
+) -> RepositoryResult<Vec<Product>> {
+    use pushkind_dantes::schema::product_images;
+
+    let product_ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let mut images_by_product = HashMap::new();
+    if !product_ids.is_empty() {
+        let images = product_images::table
+            .filter(product_images::product_id.eq_any(&product_ids))
+            .load::<ProductImage>(conn)?;
+        for image in images {
+            images_by_product
+                .entry(image.product_id)
+                .or_insert_with(Vec::new)
+                .push(image.url);
+        }
+    }
+
+    products
+        .into_iter()
+        .map(|db_product| {
+            let image_urls = images_by_product.remove(&db_product.id).unwrap_or_default();
+            let mut product: Product = Product::try_from(db_product)
+                .map_err(|err| RepositoryError::ValidationError(err.to_string()))?;
+            product.images = image_urls
+                .into_iter()
+                .map(|url| {
+                    ImageUrl::new(url)
+                        .map_err(|err| RepositoryError::ValidationError(err.to_string()))
+                })
+                .collect::<RepositoryResult<Vec<_>>>()?;
+            Ok(product)
+        })
+        .collect::<RepositoryResult<Vec<_>>>()
+}
+
 impl ProductReader for DieselRepository {
     fn list_products(&self, crawler_id: CrawlerId) -> RepositoryResult<Vec<Product>> {
-        use pushkind_dantes::schema::{product_images, products};
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let products: Vec<DbProduct> = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .load::<DbProduct>(&mut conn)?;
+
+        products_with_images(&mut conn, products)
+    }
+
+    fn get_product_by_url(
+        &self,
+        crawler_id: CrawlerId,
+        url: &str,
+    ) -> RepositoryResult<Option<Product>> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let product = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .filter(products::url.eq(url))
+            .first::<DbProduct>(&mut conn)
+            .optional()?;
+
+        Ok(products_with_images(&mut conn, product.into_iter().collect())?.pop())
+    }
+
+    fn count_products(&self, crawler_id: CrawlerId) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let count = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .count()
+            .get_result::<i64>(&mut conn)?;
+
+        Ok(count as usize)
+    }
+
+    fn count_hub_products(&self, hub_id: HubId) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::{crawlers, products};
+
+        let mut conn = self.conn()?;
+
+        let hub_crawlers = crawlers::table
+            .filter(crawlers::hub_id.eq(hub_id.get()))
+            .select(crawlers::id);
+        let count = products::table
+            .filter(products::crawler_id.eq_any(hub_crawlers))
+            .count()
+            .get_result::<i64>(&mut conn)?;
+
+        Ok(count as usize)
+    }
+
+    fn list_distinct_product_categories(
+        &self,
+        hub_id: HubId,
+    ) -> RepositoryResult<Vec<ProductCategoryCount>> {
+        use diesel::dsl::count_star;
+        use pushkind_dantes::schema::{crawlers, products};
+
+        let mut conn = self.conn()?;
+
+        let hub_crawlers = crawlers::table
+            .filter(crawlers::hub_id.eq(hub_id.get()))
+            .select(crawlers::id);
+        let rows: Vec<(Option<String>, i64)> = products::table
+            .filter(products::crawler_id.eq_any(hub_crawlers))
+            .filter(products::category.is_not_null())
+            .filter(products::category.ne(""))
+            .group_by(products::category)
+            .select((products::category, count_star()))
+            .load(&mut conn)?;
+
+        let mut categories: Vec<ProductCategoryCount> = rows
+            .into_iter()
+            .filter_map(|(category, count)| {
+                Some(ProductCategoryCount {
+                    category: category?,
+                    products: count as usize,
+                })
+            })
+            .collect();
+        categories.sort_by(|a, b| {
+            b.products
+                .cmp(&a.products)
+                .then_with(|| a.category.cmp(&b.category))
+        });
+
+        Ok(categories)
+    }
+
+    fn list_products_without_embedding(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<Product>> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let products: Vec<DbProduct> = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .filter(products::embedding.is_null())
+            .load::<DbProduct>(&mut conn)?;
+
+        products_with_images(&mut conn, products)
+    }
+
+    fn list_products_updated_since(
+        &self,
+        crawler_id: CrawlerId,
+        since: NaiveDateTime,
+    ) -> RepositoryResult<Vec<Product>> {
+        use pushkind_dantes::schema::products;
 
         let mut conn = self.conn()?;
 
         let products: Vec<DbProduct> = products::table
             .filter(products::crawler_id.eq(crawler_id.get()))
+            .filter(products::updated_at.ge(since))
+            .order((products::updated_at.asc(), products::id.asc()))
+            .load::<DbProduct>(&mut conn)?;
+
+        products_with_images(&mut conn, products)
+    }
+
+    fn list_products_sorted(
+        &self,
+        crawler_id: CrawlerId,
+        sort: ProductSort,
+        desc: bool,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<Product>> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        let query = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .into_boxed();
+
+        let query = match (sort, desc) {
+            (ProductSort::Price, false) => query.order_by(products::price.asc()),
+            (ProductSort::Price, true) => query.order_by(products::price.desc()),
+            (ProductSort::Name, false) => query.order_by(products::name.asc()),
+            (ProductSort::Name, true) => query.order_by(products::name.desc()),
+            (ProductSort::UpdatedAt, false) => query.order_by(products::updated_at.asc()),
+            (ProductSort::UpdatedAt, true) => query.order_by(products::updated_at.desc()),
+        };
+
+        // Tie-break on id so pages stay stable across requests.
+        let products: Vec<DbProduct> = query
+            .then_order_by(products::id.asc())
+            .limit(limit)
+            .offset(offset)
             .load::<DbProduct>(&mut conn)?;
 
-        let product_ids: Vec<i32> = products.iter().map(|p| p.id).collect();
-        let mut images_by_product = HashMap::new();
+        products_with_images(&mut conn, products)
+    }
+
+    fn list_product_summaries(
+        &self,
+        crawler_id: CrawlerId,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<ProductSummary>> {
+        use pushkind_dantes::schema::{product_images, products};
+
+        let mut conn = self.conn()?;
+
+        let rows: Vec<(i32, String, f64, Option<String>)> = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .order_by(products::id.asc())
+            .limit(limit)
+            .offset(offset)
+            .select((
+                products::id,
+                products::name,
+                products::price,
+                products::category,
+            ))
+            .load(&mut conn)?;
+
+        // The image with the lowest id is the primary one.
+        let product_ids: Vec<i32> = rows.iter().map(|(id, ..)| *id).collect();
+        let mut primary_images: HashMap<i32, String> = HashMap::new();
         if !product_ids.is_empty() {
-            let images = product_images::table
+            let images: Vec<(i32, String)> = product_images::table
                 .filter(product_images::product_id.eq_any(&product_ids))
-                .load::<ProductImage>(&mut conn)?;
-            for image in images {
-                images_by_product
-                    .entry(image.product_id)
-                    .or_insert_with(Vec::new)
-                    .push(image.url);
+                .order_by((product_images::product_id, product_images::id))
+                .select((product_images::product_id, product_images::url))
+                .load(&mut conn)?;
+            for (product_id, url) in images {
+                primary_images.entry(product_id).or_insert(url);
             }
         }
 
-        products
+        rows.into_iter()
+            .map(|(id, name, price, category)| {
+                Ok(ProductSummary {
+                    id: ProductId::new(id)
+                        .map_err(|err| RepositoryError::ValidationError(err.to_string()))?,
+                    name,
+                    price,
+                    category,
+                    primary_image: primary_images.remove(&id),
+                })
+            })
+            .collect()
+    }
+
+    fn list_product_embeddings(
+        &self,
+        crawler_id: CrawlerId,
+    ) -> RepositoryResult<Vec<StoredProductEmbedding>> {
+        use pushkind_dantes::schema::products;
+
+        let mut conn = self.conn()?;
+
+        #[allow(clippy::type_complexity)]
+        let rows: Vec<(
+            String,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            f64,
+            Option<f64>,
+            Option<String>,
+            Option<Vec<u8>>,
+        )> = products::table
+            .filter(products::crawler_id.eq(crawler_id.get()))
+            .filter(products::embedding.is_not_null())
+            .select((
+                products::url,
+                products::name,
+                products::sku,
+                products::category,
+                products::units,
+                products::price,
+                products::amount,
+                products::description,
+                products::embedding,
+            ))
+            .load(&mut conn)?;
+
+        Ok(rows
             .into_iter()
-            .map(|db_product| {
-                let image_urls = images_by_product.remove(&db_product.id).unwrap_or_default();
-                let mut product: Product = Product::try_from(db_product)
-                    .map_err(|err| RepositoryError::ValidationError(err.to_string()))?;
-                product.images = image_urls
-                    .into_iter()
-                    .map(|url| {
-                        ImageUrl::new(url)
-                            .map_err(|err| RepositoryError::ValidationError(err.to_string()))
+            .filter_map(
+                |(url, name, sku, category, units, price, amount, description, embedding)| {
+                    Some(StoredProductEmbedding {
+                        url,
+                        name,
+                        sku,
+                        category,
+                        units,
+                        price,
+                        amount,
+                        description,
+                        embedding: embedding?,
                     })
-                    .collect::<RepositoryResult<Vec<_>>>()?;
-                Ok(product)
-            })
-            .collect::<RepositoryResult<Vec<_>>>()
+                },
+            )
+            .collect())
     }
 }
 
 impl ProductWriter for DieselRepository {
     fn create_products(&self, products: &[NewProduct]) -> RepositoryResult<usize> {
-        use pushkind_dantes::schema::products;
-
         if products.is_empty() {
             return Ok(0);
         }
 
         let mut conn = self.conn()?;
-        let inserted = conn.transaction(|conn| {
-            let mut inserted_rows = 0;
-            for product in products.iter() {
-                let db_product: DbNewProduct = product.clone().into();
-                let product_id = diesel::insert_into(products::table)
-                    .values(&db_product)
-                    .returning(products::id)
-                    .get_result::<i32>(conn)?;
-                replace_product_images(conn, product_id, &product.images)?;
-                inserted_rows += 1;
-            }
-            Ok::<usize, RepositoryError>(inserted_rows)
-        })?;
+        let mut inserted = 0;
+        // Each chunk commits on its own so a large catalog never holds the
+        // write lock for the whole insert.
+        for chunk in products.chunks(CREATE_PRODUCTS_CHUNK_SIZE) {
+            inserted += conn.transaction(|conn| insert_products(conn, chunk))?;
+        }
 
         Ok(inserted)
     }
 
     fn update_products(&self, products: &[NewProduct]) -> RepositoryResult<usize> {
-        use pushkind_dantes::schema::products;
-
         let mut conn = self.conn()?;
 
         if products.is_empty() {
@@ -125,15 +485,7 @@ impl ProductWriter for DieselRepository {
         let affected = conn.transaction(|conn| {
             let mut affected_rows = 0;
             for product in products.iter() {
-                let db_product: DbNewProduct = product.clone().into();
-                let product_id = diesel::insert_into(products::table)
-                    .values(&db_product)
-                    .on_conflict((products::crawler_id, products::url))
-                    .do_update()
-                    .set((&db_product, products::updated_at.eq(Utc::now().naive_utc())))
-                    .returning(products::id)
-                    .get_result::<i32>(conn)?;
-                replace_product_images(conn, product_id, &product.images)?;
+                upsert_product_row(conn, product)?;
                 affected_rows += 1;
             }
             Ok::<usize, RepositoryError>(affected_rows)
@@ -142,6 +494,14 @@ impl ProductWriter for DieselRepository {
         Ok(affected)
     }
 
+    fn upsert_product(&self, product: &NewProduct) -> RepositoryResult<ProductId> {
+        let mut conn = self.conn()?;
+
+        let product_id = conn.transaction(|conn| upsert_product_row(conn, product))?;
+
+        ProductId::new(product_id).map_err(|err| RepositoryError::ValidationError(err.to_string()))
+    }
+
     fn set_product_embedding(
         &self,
         product_id: ProductId,
@@ -161,31 +521,106 @@ impl ProductWriter for DieselRepository {
         Ok(affected)
     }
 
+    fn set_product_embeddings_by_url(
+        &self,
+        crawler_id: CrawlerId,
+        embeddings: &[(String, Vec<u8>)],
+    ) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
+
+        if embeddings.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = self.conn()?;
+
+        let updated = conn.transaction(|conn| {
+            let mut updated = 0;
+            for (url, blob) in embeddings {
+                updated += diesel::update(
+                    products::table
+                        .filter(products::crawler_id.eq(crawler_id.get()))
+                        .filter(products::url.eq(url)),
+                )
+                .set(products::embedding.eq(blob))
+                .execute(conn)?;
+            }
+            Ok::<usize, RepositoryError>(updated)
+        })?;
+
+        Ok(updated)
+    }
+
     fn delete_products(&self, crawler_id: CrawlerId) -> RepositoryResult<usize> {
-        use pushkind_dantes::schema::{product_benchmark, product_images, products};
+        let mut conn = self.conn()?;
+
+        let deleted = conn.transaction(|conn| delete_crawler_products(conn, crawler_id))?;
+
+        Ok(deleted)
+    }
+
+    fn delete_product_by_url(&self, crawler_id: CrawlerId, url: &str) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
+
+        self.with_transaction(|conn| {
+            // `LIKE` treats `_` in URLs as a wildcard, so candidates are
+            // checked again before deleting.
+            let ids: Vec<i32> = products::table
+                .filter(products::crawler_id.eq(crawler_id.get()))
+                .filter(
+                    products::url
+                        .eq(url)
+                        .or(products::url.like(format!("{url}#%"))),
+                )
+                .select((products::id, products::url))
+                .load::<(i32, String)>(conn)?
+                .into_iter()
+                .filter(|(_, stored)| {
+                    stored
+                        .strip_prefix(url)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('#'))
+                })
+                .map(|(id, _)| id)
+                .collect();
+
+            Ok(delete_products_by_id(conn, &ids)?)
+        })
+    }
+
+    fn replace_products(
+        &self,
+        crawler_id: CrawlerId,
+        products: &[NewProduct],
+    ) -> RepositoryResult<usize> {
+        self.with_transaction(|conn| {
+            delete_crawler_products(conn, crawler_id)?;
+            // Chunked like `create_products` to stay within SQLite's bind
+            // parameter limit, but in the one transaction.
+            let mut inserted = 0;
+            for chunk in products.chunks(CREATE_PRODUCTS_CHUNK_SIZE) {
+                inserted += insert_products(conn, chunk)?;
+            }
+            Ok(inserted)
+        })
+    }
+
+    fn delete_products_updated_before(
+        &self,
+        crawler_id: CrawlerId,
+        cutoff: NaiveDateTime,
+    ) -> RepositoryResult<usize> {
+        use pushkind_dantes::schema::products;
 
         let mut conn = self.conn()?;
 
         let deleted = conn.transaction(|conn| {
-            // Fetch product ids to cascade delete related benchmark associations
             let ids: Vec<i32> = products::table
                 .filter(products::crawler_id.eq(crawler_id.get()))
+                .filter(products::updated_at.lt(cutoff))
                 .select(products::id)
                 .load(conn)?;
 
-            if !ids.is_empty() {
-                diesel::delete(
-                    product_images::table.filter(product_images::product_id.eq_any(&ids)),
-                )
-                .execute(conn)?;
-                diesel::delete(
-                    product_benchmark::table.filter(product_benchmark::product_id.eq_any(&ids)),
-                )
-                .execute(conn)?;
-            }
-
-            diesel::delete(products::table.filter(products::crawler_id.eq(crawler_id.get())))
-                .execute(conn)
+            delete_products_by_id(conn, &ids)
         })?;
 
         Ok(deleted)
@@ -0,0 +1,26 @@
+mod common;
+
+use pushkind_crawlers::models::config::{CategoryMatchConfig, EmbeddingModelName};
+use pushkind_crawlers::processing::category::process_product_category_match_message;
+use pushkind_crawlers::processing::embedding::SharedEmbedder;
+use pushkind_crawlers::repository::{CrawlerWriter, DieselRepository};
+use pushkind_dantes::domain::types::HubId;
+
+#[tokio::test]
+async fn category_match_returns_no_stats_while_hub_is_processing() {
+    let test_db = common::TestDb::new("test_category_match_guard.db");
+    let crawler_id = test_db.insert_crawler(1, "busy");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.set_crawler_processing(crawler_id, true).unwrap();
+
+    let stats = process_product_category_match_message(
+        HubId::new(1).unwrap(),
+        DieselRepository::new(test_db.pool()),
+        CategoryMatchConfig::default(),
+        SharedEmbedder::new(EmbeddingModelName::default()),
+        false,
+    )
+    .await;
+
+    assert_eq!(stats, None);
+}
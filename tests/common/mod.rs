@@ -1,6 +1,80 @@
 //! Helpers for integration tests.
 
-use pushkind_common::db::{DbPool, establish_connection_pool};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use pushkind_common::db::DbPool;
+use pushkind_crawlers::db::establish_pool;
+use pushkind_crawlers::models::config::DbPoolConfig;
+use pushkind_dantes::domain::product::NewProduct;
+use pushkind_dantes::domain::types::{
+    BenchmarkId, CrawlerId, ProductName, ProductPrice, ProductSku, ProductUrl,
+};
+
+/// Tables used by the repository, mirroring the `pushkind_dantes` schema.
+const SCHEMA: &str = r#"
+CREATE TABLE crawlers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    hub_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    url TEXT NOT NULL,
+    selector TEXT NOT NULL UNIQUE,
+    processing BOOLEAN NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    num_products INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE categories (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    hub_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    embedding BLOB,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE products (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    crawler_id INTEGER NOT NULL REFERENCES crawlers(id),
+    name TEXT NOT NULL,
+    sku TEXT NOT NULL,
+    category TEXT,
+    units TEXT,
+    price DOUBLE NOT NULL,
+    amount DOUBLE,
+    description TEXT,
+    url TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    embedding BLOB,
+    category_id INTEGER REFERENCES categories(id),
+    category_assignment_source TEXT NOT NULL DEFAULT 'automatic',
+    UNIQUE (crawler_id, url)
+);
+CREATE TABLE product_images (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    product_id INTEGER NOT NULL REFERENCES products(id),
+    url TEXT NOT NULL
+);
+CREATE TABLE benchmarks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    hub_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    sku TEXT NOT NULL,
+    category TEXT NOT NULL,
+    units TEXT NOT NULL,
+    price DOUBLE NOT NULL,
+    amount DOUBLE NOT NULL,
+    description TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    embedding BLOB,
+    processing BOOLEAN NOT NULL DEFAULT 0,
+    num_products INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE product_benchmark (
+    product_id INTEGER NOT NULL REFERENCES products(id),
+    benchmark_id INTEGER NOT NULL REFERENCES benchmarks(id),
+    distance REAL NOT NULL
+);
+"#;
 
 /// Temporary database used in integration tests.
 pub struct TestDb {
@@ -13,11 +87,13 @@ impl TestDb {
     pub fn new(filename: &str) -> Self {
         std::fs::remove_file(filename).ok(); // Clean up old DB
 
-        let pool =
-            establish_connection_pool(filename).expect("Failed to establish SQLite connection.");
-        let _conn = pool
+        let pool = establish_pool(filename, &DbPoolConfig::default())
+            .expect("Failed to establish SQLite connection.");
+        let mut conn = pool
             .get()
             .expect("Failed to get SQLite connection from pool.");
+        conn.batch_execute(SCHEMA)
+            .expect("Failed to create test schema.");
         TestDb {
             filename: filename.to_string(),
             pool,
@@ -27,6 +103,74 @@ impl TestDb {
     pub fn pool(&self) -> DbPool {
         self.pool.clone()
     }
+
+    /// Runs raw SQL, e.g. to age rows or install a failing trigger.
+    #[allow(dead_code)]
+    pub fn execute(&self, sql: &str) {
+        let mut conn = self.pool.get().expect("Failed to get connection.");
+        conn.batch_execute(sql).expect("Failed to execute SQL.");
+    }
+
+    /// Inserts a crawler row and returns its id.
+    #[allow(dead_code)]
+    pub fn insert_crawler(&self, hub_id: i32, selector: &str) -> CrawlerId {
+        use pushkind_dantes::schema::crawlers;
+
+        let mut conn = self.pool.get().expect("Failed to get connection.");
+        let id = diesel::insert_into(crawlers::table)
+            .values((
+                crawlers::hub_id.eq(hub_id),
+                crawlers::name.eq(selector),
+                crawlers::url.eq(format!("https://{selector}.example/")),
+                crawlers::selector.eq(selector),
+            ))
+            .returning(crawlers::id)
+            .get_result::<i32>(&mut conn)
+            .expect("Failed to insert crawler.");
+        CrawlerId::new(id).expect("valid crawler id")
+    }
+
+    /// Inserts a benchmark row and returns its id.
+    #[allow(dead_code)]
+    pub fn insert_benchmark(&self, hub_id: i32, sku: &str) -> BenchmarkId {
+        use pushkind_dantes::schema::benchmarks;
+
+        let mut conn = self.pool.get().expect("Failed to get connection.");
+        let id = diesel::insert_into(benchmarks::table)
+            .values((
+                benchmarks::hub_id.eq(hub_id),
+                benchmarks::name.eq(format!("Benchmark {sku}")),
+                benchmarks::sku.eq(sku),
+                benchmarks::category.eq("Tea"),
+                benchmarks::units.eq("г"),
+                benchmarks::price.eq(100.0),
+                benchmarks::amount.eq(50.0),
+                benchmarks::description.eq("Benchmark description"),
+            ))
+            .returning(benchmarks::id)
+            .get_result::<i32>(&mut conn)
+            .expect("Failed to insert benchmark.");
+        BenchmarkId::new(id).expect("valid benchmark id")
+    }
+}
+
+/// Builds a minimal valid product for the given crawler.
+#[allow(dead_code)]
+pub fn sample_product(crawler_id: CrawlerId, index: usize) -> NewProduct {
+    NewProduct {
+        crawler_id,
+        sku: ProductSku::new(format!("SKU-{index}")).expect("valid sku"),
+        name: ProductName::new(format!("Product {index}")).expect("valid name"),
+        price: ProductPrice::new(10.0 + index as f64).expect("valid price"),
+        category: None,
+        units: None,
+        amount: None,
+        description: None,
+        url: Some(
+            ProductUrl::new(format!("https://store.example/products/{index}")).expect("valid url"),
+        ),
+        images: vec![],
+    }
 }
 
 impl Drop for TestDb {
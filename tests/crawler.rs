@@ -0,0 +1,292 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use pushkind_common::db::DbPool;
+use pushkind_crawlers::crawlers::{CrawlerError, CrawlerResult, WebstoreCrawler};
+use pushkind_crawlers::models::config::CrawlerConfig;
+use pushkind_crawlers::processing::crawler::{
+    CrawlerFactory, process_crawler_message, process_crawler_message_with, replace_catalog,
+};
+use pushkind_crawlers::repository::{
+    CrawlerReader, CrawlerWriter, DieselRepository, ProductReader, ProductWriter,
+};
+use pushkind_dantes::domain::product::NewProduct;
+use pushkind_dantes::domain::types::{CrawlerId, CrawlerSelectorValue, ProductPrice};
+use pushkind_dantes::domain::zmq::CrawlerSelector;
+
+/// Serves `sample_product`s from fixed URLs instead of fetching a store,
+/// noting whether the crawler was marked as processing while it ran.
+struct StubCrawler {
+    crawler_id: CrawlerId,
+    selector: CrawlerSelectorValue,
+    repo: DieselRepository,
+    saw_processing: Arc<AtomicBool>,
+}
+
+/// Creates a [`StubCrawler`] for the `stub` selector.
+struct StubFactory {
+    pool: DbPool,
+    saw_processing: Arc<AtomicBool>,
+}
+
+impl CrawlerFactory for StubFactory {
+    fn supported(&self) -> &[&str] {
+        &["stub"]
+    }
+
+    fn build(
+        &self,
+        selector: &str,
+        crawler_id: i32,
+        _config: &CrawlerConfig,
+    ) -> CrawlerResult<Option<Box<dyn WebstoreCrawler + Send + Sync>>> {
+        if selector != "stub" {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(StubCrawler {
+            crawler_id: CrawlerId::new(crawler_id).unwrap(),
+            selector: CrawlerSelectorValue::new(selector.to_string()).unwrap(),
+            repo: DieselRepository::new(self.pool.clone()),
+            saw_processing: self.saw_processing.clone(),
+        })))
+    }
+}
+
+#[async_trait]
+impl WebstoreCrawler for StubCrawler {
+    async fn get_product_urls(&self) -> Vec<String> {
+        (0..3)
+            .map(|index| format!("https://store.example/products/{index}"))
+            .collect()
+    }
+
+    async fn get_product(&self, url: &str) -> Vec<NewProduct> {
+        if self.repo.get_crawler(&self.selector).unwrap().processing {
+            self.saw_processing.store(true, Ordering::SeqCst);
+        }
+        let index = url.rsplit('/').next().unwrap().parse().unwrap();
+        vec![common::sample_product(self.crawler_id, index)]
+    }
+}
+
+/// Returns the stored embedding blobs of a crawler keyed by SKU.
+fn embeddings_by_sku(repo: &DieselRepository, crawler_id: CrawlerId) -> Vec<(String, Vec<u8>)> {
+    let mut rows = repo
+        .list_product_embeddings(crawler_id)
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.sku, row.embedding))
+        .collect::<Vec<_>>();
+    rows.sort();
+    rows
+}
+
+#[test]
+fn replace_catalog_preserves_embeddings_of_unchanged_products() {
+    let test_db = common::TestDb::new("test_replace_preserves_embeddings.db");
+    let crawler_id = test_db.insert_crawler(1, "preserve");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let products = (0..3)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    repo.create_products(&products).unwrap();
+    for product in repo.list_products(crawler_id).unwrap() {
+        repo.set_product_embedding(product.id, &[0.5, 0.25])
+            .unwrap();
+    }
+    let blob = embeddings_by_sku(&repo, crawler_id)[0].1.clone();
+
+    // SKU-1 changes price, so its prompt and embedding are stale; SKU-2 is
+    // gone and SKU-3 is new.
+    let mut recrawled = vec![
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+        common::sample_product(crawler_id, 3),
+    ];
+    recrawled[1].price = ProductPrice::new(99.0).unwrap();
+
+    let written = replace_catalog(&repo, crawler_id, &recrawled, true).unwrap();
+
+    assert_eq!(written, 3);
+    assert_eq!(
+        embeddings_by_sku(&repo, crawler_id),
+        vec![("SKU-0".to_string(), blob)]
+    );
+}
+
+#[test]
+fn replace_catalog_drops_embeddings_without_preservation() {
+    let test_db = common::TestDb::new("test_replace_drops_embeddings.db");
+    let crawler_id = test_db.insert_crawler(1, "replace");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let products = vec![common::sample_product(crawler_id, 0)];
+    repo.create_products(&products).unwrap();
+    let stored = repo.list_products(crawler_id).unwrap();
+    repo.set_product_embedding(stored[0].id, &[1.0]).unwrap();
+
+    replace_catalog(&repo, crawler_id, &products, false).unwrap();
+
+    assert!(embeddings_by_sku(&repo, crawler_id).is_empty());
+}
+
+#[test]
+fn replace_catalog_keeps_previous_catalog_when_insert_fails() {
+    let test_db = common::TestDb::new("test_replace_keeps_catalog.db");
+    let crawler_id = test_db.insert_crawler(1, "atomic");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let products = (0..2)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    repo.create_products(&products).unwrap();
+
+    // A repeated URL violates `UNIQUE (crawler_id, url)` mid-insert, after
+    // the old rows were already deleted inside the transaction.
+    let recrawled = vec![
+        common::sample_product(crawler_id, 2),
+        common::sample_product(crawler_id, 2),
+    ];
+
+    assert!(replace_catalog(&repo, crawler_id, &recrawled, false).is_err());
+
+    let mut skus = repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .map(|product| product.sku.as_str().to_string())
+        .collect::<Vec<_>>();
+    skus.sort();
+    assert_eq!(skus, vec!["SKU-0".to_string(), "SKU-1".to_string()]);
+}
+
+#[tokio::test]
+async fn process_crawler_message_reports_an_unknown_crawler() {
+    let test_db = common::TestDb::new("test_process_unknown_crawler.db");
+    test_db.insert_crawler(1, "unsupported");
+    let repo = DieselRepository::new(test_db.pool());
+    let selector = CrawlerSelectorValue::new("unsupported".to_string()).unwrap();
+
+    let result = process_crawler_message(
+        CrawlerSelector::Selector(selector.clone()),
+        DieselRepository::new(test_db.pool()),
+        HashMap::new(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(CrawlerError::UnknownCrawler(_))));
+    assert!(!repo.get_crawler(&selector).unwrap().processing);
+}
+
+#[tokio::test]
+async fn process_crawler_message_reports_a_running_crawler() {
+    let test_db = common::TestDb::new("test_process_running_crawler.db");
+    let crawler_id = test_db.insert_crawler(1, "rusteaco");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.set_crawler_processing(crawler_id, true).unwrap();
+    let selector = CrawlerSelectorValue::new("rusteaco".to_string()).unwrap();
+
+    let result = process_crawler_message(
+        CrawlerSelector::Selector(selector),
+        DieselRepository::new(test_db.pool()),
+        HashMap::new(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(CrawlerError::AlreadyRunning)));
+}
+
+#[tokio::test]
+async fn process_crawler_message_writes_the_products_of_an_injected_crawler() {
+    let test_db = common::TestDb::new("test_process_stub_crawler.db");
+    let crawler_id = test_db.insert_crawler(1, "stub");
+    let repo = DieselRepository::new(test_db.pool());
+    // A stale product the full crawl replaces.
+    repo.create_products(&[common::sample_product(crawler_id, 7)])
+        .unwrap();
+    let selector = CrawlerSelectorValue::new("stub".to_string()).unwrap();
+    let saw_processing = Arc::new(AtomicBool::new(false));
+
+    let factory = StubFactory {
+        pool: test_db.pool(),
+        saw_processing: saw_processing.clone(),
+    };
+    let stats = process_crawler_message_with(
+        CrawlerSelector::Selector(selector.clone()),
+        DieselRepository::new(test_db.pool()),
+        HashMap::new(),
+        &factory,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(stats.products_written, 3);
+    assert!(saw_processing.load(Ordering::SeqCst));
+
+    let mut skus = repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .map(|product| product.sku.as_str().to_string())
+        .collect::<Vec<_>>();
+    skus.sort();
+    assert_eq!(skus, vec!["SKU-0", "SKU-1", "SKU-2"]);
+
+    let crawler = repo.get_crawler(&selector).unwrap();
+    assert!(!crawler.processing);
+    assert_eq!(crawler.num_products, 3);
+}
+
+#[tokio::test]
+async fn failed_streaming_writes_keep_the_existing_catalog() {
+    let test_db = common::TestDb::new("test_process_failed_stream.db");
+    let crawler_id = test_db.insert_crawler(1, "stub");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 7),
+    ])
+    .unwrap();
+    repo.update_crawler_stats(crawler_id).unwrap();
+    // Existing rows predate the run, and every product write now fails.
+    test_db.execute(
+        "UPDATE products SET updated_at = '2020-01-01 00:00:00';
+         CREATE TRIGGER fail_insert BEFORE INSERT ON products
+         BEGIN SELECT RAISE(ABORT, 'disk I/O error'); END;
+         CREATE TRIGGER fail_update BEFORE UPDATE ON products
+         BEGIN SELECT RAISE(ABORT, 'disk I/O error'); END;",
+    );
+    let selector = CrawlerSelectorValue::new("stub".to_string()).unwrap();
+    let config = CrawlerConfig {
+        stream_batch_size: Some(2),
+        ..CrawlerConfig::default()
+    };
+
+    let factory = StubFactory {
+        pool: test_db.pool(),
+        saw_processing: Arc::new(AtomicBool::new(false)),
+    };
+    let result = process_crawler_message_with(
+        CrawlerSelector::Selector(selector.clone()),
+        DieselRepository::new(test_db.pool()),
+        HashMap::from([("stub".to_string(), config)]),
+        &factory,
+    )
+    .await;
+
+    assert!(matches!(result, Err(CrawlerError::Repository(_))));
+    let mut skus = repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .map(|product| product.sku.as_str().to_string())
+        .collect::<Vec<_>>();
+    skus.sort();
+    assert_eq!(skus, vec!["SKU-0", "SKU-7"]);
+    assert!(!repo.get_crawler(&selector).unwrap().processing);
+}
@@ -1,5 +1,10 @@
 mod common;
 
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use pushkind_crawlers::db::establish_pool;
+use pushkind_crawlers::models::config::DbPoolConfig;
+
 #[test]
 fn test_creates_and_removes_db_files() {
     let base = "test_in_memory_connection.db";
@@ -15,3 +20,62 @@ fn test_creates_and_removes_db_files() {
     assert!(!std::path::Path::new(&format!("{base}-shm")).exists());
     assert!(!std::path::Path::new(&format!("{base}-wal")).exists());
 }
+
+#[test]
+fn establish_pool_applies_configured_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("pool.db");
+    let config = DbPoolConfig {
+        max_size: 3,
+        connection_timeout_secs: 1,
+    };
+
+    let pool = establish_pool(database.to_str().unwrap(), &config).unwrap();
+
+    assert_eq!(pool.max_size(), 3);
+    assert!(pool.get().is_ok());
+}
+
+#[derive(QueryableByName)]
+struct JournalMode {
+    #[diesel(sql_type = Text)]
+    journal_mode: String,
+}
+
+#[derive(QueryableByName)]
+struct ForeignKeys {
+    #[diesel(sql_type = Integer)]
+    foreign_keys: i32,
+}
+
+#[derive(QueryableByName)]
+struct BusyTimeout {
+    #[diesel(sql_type = Integer)]
+    timeout: i32,
+}
+
+#[test]
+fn establish_pool_sets_the_shared_connection_pragmas() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("pragmas.db");
+    let config = DbPoolConfig {
+        max_size: 2,
+        connection_timeout_secs: 7,
+    };
+
+    let pool = establish_pool(database.to_str().unwrap(), &config).unwrap();
+    let mut conn = pool.get().unwrap();
+
+    let journal = diesel::sql_query("PRAGMA journal_mode")
+        .get_result::<JournalMode>(&mut conn)
+        .unwrap();
+    assert_eq!(journal.journal_mode, "wal");
+    let foreign_keys = diesel::sql_query("PRAGMA foreign_keys")
+        .get_result::<ForeignKeys>(&mut conn)
+        .unwrap();
+    assert_eq!(foreign_keys.foreign_keys, 1);
+    let busy = diesel::sql_query("PRAGMA busy_timeout")
+        .get_result::<BusyTimeout>(&mut conn)
+        .unwrap();
+    assert_eq!(busy.timeout, 7_000);
+}
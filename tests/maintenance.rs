@@ -0,0 +1,177 @@
+mod common;
+
+use pushkind_crawlers::processing::maintenance::process_maintenance_message;
+use pushkind_crawlers::processing::message::MaintenanceMessage;
+use pushkind_crawlers::repository::{
+    BenchmarkReader, BenchmarkWriter, CrawlerReader, CrawlerWriter, DieselRepository,
+    ProductReader, ProductWriter,
+};
+use pushkind_dantes::domain::types::{CrawlerSelectorValue, HubId, SimilarityDistance};
+
+#[tokio::test]
+async fn clear_crawler_processing_releases_only_the_targeted_crawler() {
+    let test_db = common::TestDb::new("test_clear_crawler_processing.db");
+    let stuck_id = test_db.insert_crawler(1, "stuck");
+    let running_id = test_db.insert_crawler(1, "still-running");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.set_crawler_processing(stuck_id, true).unwrap();
+    repo.set_crawler_processing(running_id, true).unwrap();
+    let stuck = CrawlerSelectorValue::new("stuck".to_string()).unwrap();
+    let running = CrawlerSelectorValue::new("still-running".to_string()).unwrap();
+
+    process_maintenance_message(
+        MaintenanceMessage::ClearCrawlerProcessing(stuck.clone()),
+        DieselRepository::new(test_db.pool()),
+    )
+    .await;
+
+    assert!(!repo.get_crawler(&stuck).unwrap().processing);
+    assert!(repo.get_crawler(&running).unwrap().processing);
+}
+
+#[tokio::test]
+async fn clear_crawler_products_keeps_the_crawler() {
+    let test_db = common::TestDb::new("test_clear_crawler_products.db");
+    let crawler_id = test_db.insert_crawler(1, "decommissioned");
+    let other_id = test_db.insert_crawler(1, "kept");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+        common::sample_product(other_id, 0),
+    ])
+    .unwrap();
+    repo.update_crawler_stats(crawler_id).unwrap();
+    let selector = CrawlerSelectorValue::new("decommissioned".to_string()).unwrap();
+
+    process_maintenance_message(
+        MaintenanceMessage::ClearCrawlerProducts(selector.clone()),
+        DieselRepository::new(test_db.pool()),
+    )
+    .await;
+
+    let crawler = repo.get_crawler(&selector).unwrap();
+    assert_eq!(crawler.num_products, 0);
+    assert!(!crawler.processing);
+    assert!(repo.list_products(crawler_id).unwrap().is_empty());
+    assert_eq!(repo.list_products(other_id).unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn clear_crawler_products_skips_a_running_crawl() {
+    let test_db = common::TestDb::new("test_clear_crawler_products_running.db");
+    let crawler_id = test_db.insert_crawler(1, "running");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[common::sample_product(crawler_id, 0)])
+        .unwrap();
+    repo.set_crawler_processing(crawler_id, true).unwrap();
+    let selector = CrawlerSelectorValue::new("running".to_string()).unwrap();
+
+    process_maintenance_message(
+        MaintenanceMessage::ClearCrawlerProducts(selector.clone()),
+        DieselRepository::new(test_db.pool()),
+    )
+    .await;
+
+    assert_eq!(repo.list_products(crawler_id).unwrap().len(), 1);
+    assert!(repo.get_crawler(&selector).unwrap().processing);
+}
+
+#[tokio::test]
+async fn rebuild_hub_stats_counts_stored_rows() {
+    let test_db = common::TestDb::new("test_rebuild_hub_stats.db");
+    let crawler_id = test_db.insert_crawler(1, "drifted");
+    let running_id = test_db.insert_crawler(1, "rebuild-running");
+    let other_hub_id = test_db.insert_crawler(2, "other-hub");
+    let benchmark_id = test_db.insert_benchmark(1, "B-1");
+    let running_benchmark_id = test_db.insert_benchmark(1, "B-running");
+    let repo = DieselRepository::new(test_db.pool());
+    // Rows written without the stats refresh a crawl or matching run does.
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+        common::sample_product(crawler_id, 2),
+        common::sample_product(running_id, 0),
+        common::sample_product(other_hub_id, 0),
+    ])
+    .unwrap();
+    let stored = repo.list_products(crawler_id).unwrap();
+    let distance = SimilarityDistance::new(0.9).unwrap();
+    for product in &stored[..2] {
+        repo.set_benchmark_association(benchmark_id, product.id, distance)
+            .unwrap();
+    }
+    // A matching run still writing its associations.
+    repo.set_benchmark_association(running_benchmark_id, stored[0].id, distance)
+        .unwrap();
+    repo.set_crawler_processing(running_id, true).unwrap();
+    assert_eq!(
+        repo.set_benchmark_processing(running_benchmark_id, true)
+            .unwrap(),
+        1
+    );
+
+    process_maintenance_message(
+        MaintenanceMessage::RebuildHubStats(HubId::new(1).unwrap()),
+        DieselRepository::new(test_db.pool()),
+    )
+    .await;
+
+    let crawlers = repo.list_crawlers(HubId::new(1).unwrap()).unwrap();
+    let drifted = crawlers.iter().find(|c| c.id == crawler_id).unwrap();
+    assert_eq!(drifted.num_products as usize, stored.len());
+    assert!(!drifted.processing);
+    let running = crawlers.iter().find(|c| c.id == running_id).unwrap();
+    assert_eq!(running.num_products, 0);
+    assert!(running.processing);
+    let other_hub = repo.list_crawlers(HubId::new(2).unwrap()).unwrap();
+    assert_eq!(other_hub[0].num_products, 0);
+    let benchmark = repo.get_benchmark(benchmark_id).unwrap();
+    assert_eq!(benchmark.num_products, 2);
+    assert!(!benchmark.processing);
+    let running_benchmark = repo.get_benchmark(running_benchmark_id).unwrap();
+    assert_eq!(running_benchmark.num_products, 0);
+    assert!(running_benchmark.processing);
+}
+
+#[tokio::test]
+async fn purge_orphaned_associations_keeps_rows_of_stored_products() {
+    use diesel::connection::SimpleConnection;
+
+    let test_db = common::TestDb::new("test_purge_orphaned_associations.db");
+    let crawler_id = test_db.insert_crawler(1, "orphans");
+    let benchmark_id = test_db.insert_benchmark(1, "B-1");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+    ])
+    .unwrap();
+    let distance = SimilarityDistance::new(0.9).unwrap();
+    for product in repo.list_products(crawler_id).unwrap() {
+        repo.set_benchmark_association(benchmark_id, product.id, distance)
+            .unwrap();
+    }
+    // A product deleted by hand, leaving its association behind.
+    let mut conn = test_db.pool().get().unwrap();
+    conn.batch_execute(&format!(
+        "PRAGMA foreign_keys = OFF;
+         INSERT INTO product_benchmark (product_id, benchmark_id, distance)
+         VALUES (9999, {}, 0.95);",
+        benchmark_id.get()
+    ))
+    .unwrap();
+    drop(conn);
+    repo.update_benchmark_stats(benchmark_id).unwrap();
+    assert_eq!(repo.get_benchmark(benchmark_id).unwrap().num_products, 3);
+
+    process_maintenance_message(
+        MaintenanceMessage::PurgeOrphanedAssociations,
+        DieselRepository::new(test_db.pool()),
+    )
+    .await;
+
+    assert_eq!(repo.purge_orphaned_associations().unwrap(), 0);
+    repo.update_benchmark_stats(benchmark_id).unwrap();
+    assert_eq!(repo.get_benchmark(benchmark_id).unwrap().num_products, 2);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use pushkind_crawlers::models::config::EmbeddingModelName;
+use pushkind_crawlers::processing::embedding::SharedEmbedder;
+use pushkind_crawlers::processing::precompute::{EmbedStats, process_embed_hub_products_message};
+use pushkind_crawlers::repository::{CrawlerReader, CrawlerWriter, DieselRepository};
+use pushkind_dantes::domain::types::{CrawlerSelectorValue, HubId};
+
+#[tokio::test]
+async fn embed_hub_products_skips_while_hub_is_processing() {
+    let test_db = common::TestDb::new("test_embed_hub_products_guard.db");
+    let crawler_id = test_db.insert_crawler(1, "busy");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.set_crawler_processing(crawler_id, true).unwrap();
+
+    let stats = process_embed_hub_products_message(
+        HubId::new(1).unwrap(),
+        DieselRepository::new(test_db.pool()),
+        SharedEmbedder::new(EmbeddingModelName::default()),
+    )
+    .await;
+
+    assert_eq!(stats, None);
+}
+
+#[tokio::test]
+async fn embed_hub_products_releases_guard_when_nothing_is_missing() {
+    let test_db = common::TestDb::new("test_embed_hub_products_empty.db");
+    test_db.insert_crawler(1, "idle");
+    let repo = DieselRepository::new(test_db.pool());
+
+    // Without products the embedding model is never loaded.
+    let stats = process_embed_hub_products_message(
+        HubId::new(1).unwrap(),
+        DieselRepository::new(test_db.pool()),
+        SharedEmbedder::new(EmbeddingModelName::default()),
+    )
+    .await;
+
+    assert_eq!(
+        stats,
+        Some(EmbedStats {
+            crawlers_loaded: 1,
+            ..EmbedStats::default()
+        })
+    );
+    let crawler = repo
+        .get_crawler(&CrawlerSelectorValue::new("idle".to_string()).unwrap())
+        .unwrap();
+    assert!(!crawler.processing);
+}
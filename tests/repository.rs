@@ -1 +1,980 @@
 mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeDelta};
+use pushkind_crawlers::repository::{
+    BenchmarkReader, BenchmarkWriter, CrawlerReader, CrawlerWriter, DieselRepository,
+    ProductCategoryCount, ProductCategoryReader, ProductCategoryWriter, ProductReader, ProductSort,
+    ProductWriter,
+};
+use pushkind_dantes::domain::types::{
+    CategoryId, CategoryName, CrawlerId, CrawlerSelectorValue, HubId, ImageUrl, ProductPrice,
+    ProductUrl, SimilarityDistance,
+};
+
+#[test]
+fn create_products_inserts_large_catalog_in_chunks() {
+    let test_db = common::TestDb::new("test_create_products_chunks.db");
+    let crawler_id = test_db.insert_crawler(1, "chunks");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut products = (0..1234)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    products[1000].images =
+        vec![ImageUrl::new("https://store.example/images/1000.jpg".to_string()).unwrap()];
+
+    let inserted = repo.create_products(&products).unwrap();
+
+    assert_eq!(inserted, 1234);
+    let stored = repo.list_products(crawler_id).unwrap();
+    assert_eq!(stored.len(), 1234);
+    let with_image = stored
+        .iter()
+        .find(|product| product.sku.as_str() == "SKU-1000")
+        .unwrap();
+    assert_eq!(with_image.images.len(), 1);
+}
+
+#[test]
+fn replace_products_replaces_a_catalog_larger_than_one_chunk() {
+    let test_db = common::TestDb::new("test_replace_products_chunks.db");
+    let crawler_id = test_db.insert_crawler(1, "chunks");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let old = (0..1100)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    repo.create_products(&old).unwrap();
+
+    let mut products = (1000..2234)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    products[1200].images =
+        vec![ImageUrl::new("https://store.example/images/2200.jpg".to_string()).unwrap()];
+
+    let inserted = repo.replace_products(crawler_id, &products).unwrap();
+
+    assert_eq!(inserted, 1234);
+    let stored = repo.list_products(crawler_id).unwrap();
+    assert_eq!(stored.len(), 1234);
+    assert!(stored.iter().all(|product| product.sku.as_str() != "SKU-0"));
+    let with_image = stored
+        .iter()
+        .find(|product| product.sku.as_str() == "SKU-2200")
+        .unwrap();
+    assert_eq!(with_image.images.len(), 1);
+}
+
+fn sorted_skus(
+    repo: &DieselRepository,
+    crawler_id: CrawlerId,
+    sort: ProductSort,
+    desc: bool,
+    limit: i64,
+    offset: i64,
+) -> Vec<String> {
+    repo.list_products_sorted(crawler_id, sort, desc, limit, offset)
+        .unwrap()
+        .into_iter()
+        .map(|product| product.sku.as_str().to_string())
+        .collect()
+}
+
+#[test]
+fn list_products_sorted_orders_and_paginates() {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::products;
+
+    let test_db = common::TestDb::new("test_list_products_sorted.db");
+    let crawler_id = test_db.insert_crawler(1, "sorted");
+    let repo = DieselRepository::new(test_db.pool());
+
+    // Prices rise with the index, names sort lexicographically ("Product 10"
+    // before "Product 2") and updated_at is reversed relative to the index.
+    let products = (0..12)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    repo.create_products(&products).unwrap();
+    let mut conn = test_db.pool().get().unwrap();
+    for index in 0..12 {
+        let updated_at = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + chrono::Duration::days(12 - index);
+        diesel::update(products::table.filter(products::sku.eq(format!("SKU-{index}"))))
+            .set(products::updated_at.eq(updated_at))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::Price, false, 3, 0),
+        vec!["SKU-0", "SKU-1", "SKU-2"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::Price, true, 3, 0),
+        vec!["SKU-11", "SKU-10", "SKU-9"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::Price, false, 3, 3),
+        vec!["SKU-3", "SKU-4", "SKU-5"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::Name, false, 4, 0),
+        vec!["SKU-0", "SKU-1", "SKU-10", "SKU-11"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::Name, true, 2, 1),
+        vec!["SKU-8", "SKU-7"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::UpdatedAt, false, 2, 0),
+        vec!["SKU-11", "SKU-10"]
+    );
+    assert_eq!(
+        sorted_skus(&repo, crawler_id, ProductSort::UpdatedAt, true, 2, 10),
+        vec!["SKU-10", "SKU-11"]
+    );
+    assert!(sorted_skus(&repo, crawler_id, ProductSort::Price, false, 5, 12).is_empty());
+}
+
+#[test]
+fn update_benchmark_stats_counts_distinct_products() {
+    let test_db = common::TestDb::new("test_benchmark_stats_distinct.db");
+    let crawler_id = test_db.insert_crawler(1, "stats");
+    let benchmark_id = test_db.insert_benchmark(1, "B-1");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let products = (0..2)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    repo.create_products(&products).unwrap();
+    let stored = repo.list_products(crawler_id).unwrap();
+    let distance = SimilarityDistance::new(0.9).unwrap();
+
+    // The first product is associated twice, as happened before associations
+    // were unique.
+    repo.set_benchmark_association(benchmark_id, stored[0].id, distance)
+        .unwrap();
+    repo.set_benchmark_association(benchmark_id, stored[0].id, distance)
+        .unwrap();
+    repo.set_benchmark_association(benchmark_id, stored[1].id, distance)
+        .unwrap();
+
+    repo.update_benchmark_stats(benchmark_id).unwrap();
+
+    let benchmark = repo.get_benchmark(benchmark_id).unwrap();
+    assert_eq!(benchmark.num_products, 2);
+    assert!(!benchmark.processing);
+}
+
+#[test]
+fn reset_product_category_source_returns_manual_product_to_matching() {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::{categories, products};
+
+    let test_db = common::TestDb::new("test_reset_category_source.db");
+    let crawler_id = test_db.insert_crawler(1, "manual");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+    ])
+    .unwrap();
+
+    let mut conn = test_db.pool().get().unwrap();
+    let category_id: i32 = diesel::insert_into(categories::table)
+        .values((categories::hub_id.eq(1), categories::name.eq("Green tea")))
+        .returning(categories::id)
+        .get_result(&mut conn)
+        .unwrap();
+    diesel::update(products::table.filter(products::sku.eq("SKU-0")))
+        .set((
+            products::category_id.eq(Some(category_id)),
+            products::category_assignment_source.eq("manual"),
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+    let manual = repo.list_manual_category_products(crawler_id).unwrap();
+    assert_eq!(manual.len(), 1);
+    let manual_id = manual[0].id;
+    let automatic_id = repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .find(|product| product.sku.as_str() == "SKU-1")
+        .unwrap()
+        .id;
+
+    // Manual rows are protected from automatic assignment until reset.
+    assert_eq!(
+        repo.set_product_category_automatic(manual_id, None)
+            .unwrap(),
+        0
+    );
+    assert_eq!(repo.reset_product_category_source(automatic_id).unwrap(), 0);
+    assert_eq!(repo.reset_product_category_source(manual_id).unwrap(), 1);
+
+    assert!(
+        repo.list_manual_category_products(crawler_id)
+            .unwrap()
+            .is_empty()
+    );
+    let (stored_category, source): (Option<i32>, String) = products::table
+        .filter(products::id.eq(manual_id.get()))
+        .select((products::category_id, products::category_assignment_source))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored_category, None);
+    assert_eq!(source, "automatic");
+    assert_eq!(
+        repo.set_product_category_automatic(manual_id, None)
+            .unwrap(),
+        1
+    );
+}
+
+#[test]
+fn list_products_without_embedding_skips_embedded_products() {
+    let test_db = common::TestDb::new("test_products_without_embedding.db");
+    let crawler_id = test_db.insert_crawler(1, "embeddings");
+    let other_crawler_id = test_db.insert_crawler(1, "other");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(
+        &(0..3)
+            .map(|index| common::sample_product(crawler_id, index))
+            .chain([common::sample_product(other_crawler_id, 9)])
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+    let embedded = repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .find(|product| product.sku.as_str() == "SKU-1")
+        .unwrap();
+    repo.set_product_embedding(embedded.id, &[0.5, 0.25])
+        .unwrap();
+
+    let mut skus = repo
+        .list_products_without_embedding(crawler_id)
+        .unwrap()
+        .into_iter()
+        .map(|product| product.sku.as_str().to_string())
+        .collect::<Vec<_>>();
+    skus.sort();
+
+    assert_eq!(skus, vec!["SKU-0", "SKU-2"]);
+}
+
+#[test]
+fn bulk_category_assignment_never_overwrites_manual_categories() {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::{categories, products};
+
+    let test_db = common::TestDb::new("test_bulk_category_assignment.db");
+    let crawler_id = test_db.insert_crawler(1, "bulk");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(
+        &(0..4)
+            .map(|index| common::sample_product(crawler_id, index))
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    let mut conn = test_db.pool().get().unwrap();
+    let category_ids: Vec<i32> = ["Green tea", "Black tea"]
+        .into_iter()
+        .map(|name| {
+            diesel::insert_into(categories::table)
+                .values((categories::hub_id.eq(1), categories::name.eq(name)))
+                .returning(categories::id)
+                .get_result(&mut conn)
+                .unwrap()
+        })
+        .collect();
+    diesel::update(products::table.filter(products::sku.eq("SKU-0")))
+        .set((
+            products::category_id.eq(Some(category_ids[1])),
+            products::category_assignment_source.eq("manual"),
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+    let stored = repo.list_products(crawler_id).unwrap();
+    let id_of = |sku: &str| {
+        stored
+            .iter()
+            .find(|product| product.sku.as_str() == sku)
+            .unwrap()
+            .id
+    };
+    let green = CategoryId::new(category_ids[0]).unwrap();
+    let black = CategoryId::new(category_ids[1]).unwrap();
+
+    let updated = repo
+        .set_product_categories_automatic(&[
+            (id_of("SKU-0"), Some(green)),
+            (id_of("SKU-1"), Some(green)),
+            (id_of("SKU-2"), Some(black)),
+            (id_of("SKU-3"), None),
+        ])
+        .unwrap();
+
+    assert_eq!(updated, 3);
+    let mut rows: Vec<(String, Option<i32>, String)> = products::table
+        .filter(products::crawler_id.eq(crawler_id.get()))
+        .select((
+            products::sku,
+            products::category_id,
+            products::category_assignment_source,
+        ))
+        .load(&mut conn)
+        .unwrap();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "SKU-0".to_string(),
+                Some(category_ids[1]),
+                "manual".to_string()
+            ),
+            (
+                "SKU-1".to_string(),
+                Some(category_ids[0]),
+                "automatic".to_string()
+            ),
+            (
+                "SKU-2".to_string(),
+                Some(category_ids[1]),
+                "automatic".to_string()
+            ),
+            ("SKU-3".to_string(), None, "automatic".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn list_product_summaries_returns_summary_fields_and_primary_image() {
+    let test_db = common::TestDb::new("test_list_product_summaries.db");
+    let crawler_id = test_db.insert_crawler(1, "summaries");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut products = (0..3)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    products[1].images = vec![
+        ImageUrl::new("https://store.example/images/1-front.jpg".to_string()).unwrap(),
+        ImageUrl::new("https://store.example/images/1-back.jpg".to_string()).unwrap(),
+    ];
+    repo.create_products(&products).unwrap();
+
+    let summaries = repo.list_product_summaries(crawler_id, 2, 0).unwrap();
+
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].name, "Product 0");
+    assert!((summaries[0].price - 10.0).abs() < f64::EPSILON);
+    assert_eq!(summaries[0].primary_image, None);
+    assert_eq!(summaries[1].name, "Product 1");
+    assert_eq!(
+        summaries[1].primary_image.as_deref(),
+        Some("https://store.example/images/1-front.jpg")
+    );
+
+    let rest = repo.list_product_summaries(crawler_id, 2, 2).unwrap();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].name, "Product 2");
+}
+
+/// Returns `(product_id, url)` of every stored image in insertion order.
+fn stored_images(test_db: &common::TestDb) -> Vec<(i32, String)> {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::product_images;
+
+    let mut conn = test_db.pool().get().unwrap();
+    product_images::table
+        .order_by(product_images::id)
+        .select((product_images::product_id, product_images::url))
+        .load(&mut conn)
+        .unwrap()
+}
+
+fn image(url: &str) -> ImageUrl {
+    ImageUrl::new(url.to_string()).unwrap()
+}
+
+#[test]
+fn product_counts_are_scoped_to_crawler_and_hub() {
+    let test_db = common::TestDb::new("test_product_counts.db");
+    let first = test_db.insert_crawler(1, "count-first");
+    let second = test_db.insert_crawler(1, "count-second");
+    let other_hub = test_db.insert_crawler(2, "count-other");
+    let empty = test_db.insert_crawler(3, "count-empty");
+    let repo = DieselRepository::new(test_db.pool());
+
+    for (crawler_id, count) in [(first, 3), (second, 2), (other_hub, 4)] {
+        let products = (0..count)
+            .map(|index| common::sample_product(crawler_id, index))
+            .collect::<Vec<_>>();
+        repo.create_products(&products).unwrap();
+    }
+
+    assert_eq!(repo.count_products(first).unwrap(), 3);
+    assert_eq!(repo.count_products(second).unwrap(), 2);
+    assert_eq!(repo.count_products(other_hub).unwrap(), 4);
+    assert_eq!(repo.count_products(empty).unwrap(), 0);
+    assert_eq!(repo.count_hub_products(HubId::new(1).unwrap()).unwrap(), 5);
+    assert_eq!(repo.count_hub_products(HubId::new(2).unwrap()).unwrap(), 4);
+    assert_eq!(repo.count_hub_products(HubId::new(3).unwrap()).unwrap(), 0);
+    assert_eq!(
+        repo.count_products(first).unwrap(),
+        repo.list_products(first).unwrap().len()
+    );
+}
+
+#[test]
+fn update_products_replaces_images_of_existing_product() {
+    let test_db = common::TestDb::new("test_update_products_replaces_images.db");
+    let crawler_id = test_db.insert_crawler(1, "images");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut product = common::sample_product(crawler_id, 0);
+    product.images = vec![
+        image("https://store.example/images/0-front.jpg"),
+        image("https://store.example/images/0-back.jpg"),
+    ];
+    repo.create_products(std::slice::from_ref(&product))
+        .unwrap();
+    let product_id = repo.list_products(crawler_id).unwrap()[0].id;
+    assert_eq!(stored_images(&test_db).len(), 2);
+
+    product.images = vec![image("https://store.example/images/0-new.jpg")];
+    repo.update_products(std::slice::from_ref(&product))
+        .unwrap();
+
+    // The upsert keeps the row, so the single remaining image still points
+    // at the original product id.
+    assert_eq!(
+        stored_images(&test_db),
+        vec![(
+            product_id.get(),
+            "https://store.example/images/0-new.jpg".to_string()
+        )]
+    );
+    let stored = repo.list_products(crawler_id).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, product_id);
+    assert_eq!(
+        stored[0]
+            .images
+            .iter()
+            .map(|url| url.as_str())
+            .collect::<Vec<_>>(),
+        vec!["https://store.example/images/0-new.jpg"]
+    );
+}
+
+#[test]
+fn upsert_product_updates_the_row_of_the_same_url() {
+    let test_db = common::TestDb::new("test_upsert_product.db");
+    let crawler_id = test_db.insert_crawler(1, "upsert");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut product = common::sample_product(crawler_id, 0);
+    product.images = vec![image("https://store.example/images/0.jpg")];
+    let product_id = repo.upsert_product(&product).unwrap();
+
+    product.price = ProductPrice::new(250.0).unwrap();
+    product.images.clear();
+    assert_eq!(repo.upsert_product(&product).unwrap(), product_id);
+
+    let stored = repo.list_products(crawler_id).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, product_id);
+    assert!((stored[0].price.get() - 250.0).abs() < f64::EPSILON);
+    assert!(stored_images(&test_db).is_empty());
+}
+
+#[test]
+fn update_products_without_images_clears_existing_images() {
+    let test_db = common::TestDb::new("test_update_products_clears_images.db");
+    let crawler_id = test_db.insert_crawler(1, "no-images");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut products = (0..2)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    products[0].images = vec![image("https://store.example/images/0.jpg")];
+    products[1].images = vec![image("https://store.example/images/1.jpg")];
+    repo.create_products(&products).unwrap();
+
+    products[0].images.clear();
+    repo.update_products(&products[..1]).unwrap();
+
+    // Only the updated product loses its images.
+    let remaining = stored_images(&test_db)
+        .into_iter()
+        .map(|(_, url)| url)
+        .collect::<Vec<_>>();
+    assert_eq!(remaining, vec!["https://store.example/images/1.jpg"]);
+}
+
+#[test]
+fn delete_products_updated_before_removes_only_stale_rows() {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::products;
+
+    let test_db = common::TestDb::new("test_delete_stale_products.db");
+    let crawler_id = test_db.insert_crawler(1, "stale");
+    let other_crawler_id = test_db.insert_crawler(1, "other");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut stale = common::sample_product(crawler_id, 0);
+    stale.images = vec![ImageUrl::new("https://store.example/images/0.jpg".to_string()).unwrap()];
+    repo.create_products(&[
+        stale,
+        common::sample_product(crawler_id, 1),
+        common::sample_product(other_crawler_id, 2),
+    ])
+    .unwrap();
+
+    let cutoff = chrono::NaiveDate::from_ymd_opt(2024, 6, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let mut conn = test_db.pool().get().unwrap();
+    diesel::update(products::table.filter(products::sku.eq_any(["SKU-0", "SKU-2"])))
+        .set(products::updated_at.eq(cutoff - chrono::Duration::days(1)))
+        .execute(&mut conn)
+        .unwrap();
+
+    assert_eq!(
+        repo.delete_products_updated_before(crawler_id, cutoff)
+            .unwrap(),
+        1
+    );
+
+    let remaining = repo.list_products(crawler_id).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].sku.as_str(), "SKU-1");
+    assert_eq!(repo.list_products(other_crawler_id).unwrap().len(), 1);
+}
+
+#[test]
+fn get_product_by_url_returns_the_stored_product_with_images() {
+    let test_db = common::TestDb::new("test_get_product_by_url.db");
+    let crawler_id = test_db.insert_crawler(1, "lookup");
+    let other_crawler_id = test_db.insert_crawler(1, "other");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut known = common::sample_product(crawler_id, 1);
+    known.images = vec![image("https://store.example/images/1.jpg")];
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        known,
+        common::sample_product(other_crawler_id, 2),
+    ])
+    .unwrap();
+
+    let product = repo
+        .get_product_by_url(crawler_id, "https://store.example/products/1")
+        .unwrap()
+        .expect("product should be found");
+    assert_eq!(product.sku.as_str(), "SKU-1");
+    assert_eq!(
+        product
+            .images
+            .iter()
+            .map(|image| image.as_str())
+            .collect::<Vec<_>>(),
+        vec!["https://store.example/images/1.jpg"]
+    );
+
+    assert!(
+        repo.get_product_by_url(crawler_id, "https://store.example/products/9")
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.get_product_by_url(crawler_id, "https://store.example/products/2")
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+fn delete_product_by_url_removes_its_benchmark_associations() {
+    let test_db = common::TestDb::new("test_delete_product_by_url.db");
+    let crawler_id = test_db.insert_crawler(1, "gone");
+    let other_crawler_id = test_db.insert_crawler(1, "other");
+    let benchmark_id = test_db.insert_benchmark(1, "B-1");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut gone = common::sample_product(crawler_id, 0);
+    gone.images = vec![image("https://store.example/images/0.jpg")];
+    repo.create_products(&[
+        gone,
+        common::sample_product(crawler_id, 1),
+        common::sample_product(other_crawler_id, 0),
+    ])
+    .unwrap();
+    let distance = SimilarityDistance::new(0.9).unwrap();
+    for product in repo
+        .list_products(crawler_id)
+        .unwrap()
+        .into_iter()
+        .chain(repo.list_products(other_crawler_id).unwrap())
+    {
+        repo.set_benchmark_association(benchmark_id, product.id, distance)
+            .unwrap();
+    }
+
+    let url = "https://store.example/products/0";
+    assert_eq!(repo.delete_product_by_url(crawler_id, url).unwrap(), 1);
+    assert_eq!(repo.delete_product_by_url(crawler_id, url).unwrap(), 0);
+
+    let remaining = repo.list_products(crawler_id).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].sku.as_str(), "SKU-1");
+    assert_eq!(repo.list_products(other_crawler_id).unwrap().len(), 1);
+    assert!(stored_images(&test_db).is_empty());
+
+    repo.update_benchmark_stats(benchmark_id).unwrap();
+    assert_eq!(repo.get_benchmark(benchmark_id).unwrap().num_products, 2);
+}
+
+#[test]
+fn delete_product_by_url_removes_variants_of_the_page() {
+    let test_db = common::TestDb::new("test_delete_product_variants_by_url.db");
+    let crawler_id = test_db.insert_crawler(1, "variants");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let products = [
+        "https://store.example/products/1",
+        "https://store.example/products/1#A",
+        "https://store.example/products/1#B",
+        "https://store.example/products/10",
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(index, url)| {
+        let mut product = common::sample_product(crawler_id, index);
+        product.url = Some(ProductUrl::new(url.to_string()).unwrap());
+        product
+    })
+    .collect::<Vec<_>>();
+    repo.create_products(&products).unwrap();
+
+    assert_eq!(
+        repo.delete_product_by_url(crawler_id, "https://store.example/products/1")
+            .unwrap(),
+        3
+    );
+
+    let remaining = repo.list_products(crawler_id).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(
+        remaining[0].url.as_ref().map(|url| url.as_str()),
+        Some("https://store.example/products/10")
+    );
+}
+
+#[test]
+fn list_products_updated_since_returns_only_changed_products() {
+    use diesel::prelude::*;
+    use pushkind_dantes::schema::products;
+
+    let test_db = common::TestDb::new("test_products_updated_since.db");
+    let crawler_id = test_db.insert_crawler(1, "delta");
+    let repo = DieselRepository::new(test_db.pool());
+    let mut changed = common::sample_product(crawler_id, 1);
+    changed.images = vec![ImageUrl::new("https://store.example/images/1.jpg".to_string()).unwrap()];
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        changed.clone(),
+        common::sample_product(crawler_id, 2),
+    ])
+    .unwrap();
+
+    let since = chrono::NaiveDate::from_ymd_opt(2024, 6, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let mut conn = test_db.pool().get().unwrap();
+    diesel::update(products::table.filter(products::crawler_id.eq(crawler_id.get())))
+        .set(products::updated_at.eq(since - chrono::Duration::days(1)))
+        .execute(&mut conn)
+        .unwrap();
+
+    repo.update_products(&[changed]).unwrap();
+
+    let delta = repo.list_products_updated_since(crawler_id, since).unwrap();
+    assert_eq!(delta.len(), 1);
+    assert_eq!(delta[0].sku.as_str(), "SKU-1");
+    assert_eq!(delta[0].images.len(), 1);
+}
+
+#[test]
+fn set_crawler_processing_lets_only_one_concurrent_request_claim_the_crawler() {
+    let test_db = common::TestDb::new("test_crawler_processing_cas.db");
+    let crawler_id = test_db.insert_crawler(1, "cas");
+    let selector = CrawlerSelectorValue::new("cas".to_string()).unwrap();
+    let first = DieselRepository::new(test_db.pool());
+    let second = DieselRepository::new(test_db.pool());
+
+    // Both requests read the crawler before either claims it, which is the
+    // interleaving a check-then-set allowed through.
+    assert!(!first.get_crawler(&selector).unwrap().processing);
+    assert!(!second.get_crawler(&selector).unwrap().processing);
+
+    assert_eq!(first.set_crawler_processing(crawler_id, true).unwrap(), 1);
+    assert_eq!(second.set_crawler_processing(crawler_id, true).unwrap(), 0);
+    assert!(first.get_crawler(&selector).unwrap().processing);
+
+    // Releasing the crawler lets the next request claim it again.
+    assert_eq!(second.set_crawler_processing(crawler_id, false).unwrap(), 1);
+    assert_eq!(second.set_crawler_processing(crawler_id, false).unwrap(), 0);
+    assert_eq!(second.set_crawler_processing(crawler_id, true).unwrap(), 1);
+}
+
+#[test]
+fn list_due_crawlers_returns_idle_crawlers_whose_interval_has_elapsed() {
+    let test_db = common::TestDb::new("test_list_due_crawlers.db");
+    let due = test_db.insert_crawler(1, "due");
+    let running = test_db.insert_crawler(1, "running");
+    let recent = test_db.insert_crawler(1, "recent");
+    test_db.insert_crawler(2, "unscheduled");
+    test_db.execute(
+        "UPDATE crawlers SET updated_at = '2026-01-01 00:00:00' \
+         WHERE selector IN ('due', 'running', 'unscheduled')",
+    );
+    test_db.execute(
+        "UPDATE crawlers SET updated_at = '2026-01-01 11:30:00' WHERE selector = 'recent'",
+    );
+    let repo = DieselRepository::new(test_db.pool());
+    repo.set_crawler_processing(running, true).unwrap();
+
+    let hour = Duration::from_secs(3600);
+    let intervals = HashMap::from([
+        ("due".to_string(), hour),
+        ("running".to_string(), hour),
+        ("recent".to_string(), hour),
+    ]);
+    let now = timestamp("2026-01-01 12:00:00");
+
+    let crawlers = repo.list_due_crawlers(now, &intervals).unwrap();
+
+    let ids = crawlers
+        .iter()
+        .map(|crawler| crawler.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![due]);
+
+    // A crawler becomes due exactly one interval after its last run.
+    let crawlers = repo
+        .list_due_crawlers(timestamp("2026-01-01 12:30:00"), &intervals)
+        .unwrap();
+    let ids = crawlers
+        .iter()
+        .map(|crawler| crawler.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![due, recent]);
+}
+
+#[test]
+fn list_due_crawlers_waits_a_full_interval_after_a_finished_run() {
+    let test_db = common::TestDb::new("test_list_due_crawlers_finished.db");
+    let crawler_id = test_db.insert_crawler(1, "finished");
+    test_db.execute("UPDATE crawlers SET updated_at = '2020-01-01 00:00:00'");
+    let repo = DieselRepository::new(test_db.pool());
+    let intervals = HashMap::from([("finished".to_string(), Duration::from_secs(3600))]);
+    let now = chrono::Utc::now().naive_utc();
+
+    assert_eq!(repo.list_due_crawlers(now, &intervals).unwrap().len(), 1);
+
+    // Finishing a run refreshes `updated_at`, which restarts the interval.
+    repo.update_crawler_stats(crawler_id).unwrap();
+
+    let later = now + TimeDelta::seconds(60);
+    assert!(
+        repo.list_due_crawlers(later, &intervals)
+            .unwrap()
+            .is_empty()
+    );
+    let next_run = now + TimeDelta::seconds(3600 + 60);
+    assert_eq!(
+        repo.list_due_crawlers(next_run, &intervals).unwrap().len(),
+        1
+    );
+}
+
+fn timestamp(value: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+}
+
+#[test]
+fn list_benchmarks_returns_only_the_hub_benchmarks() {
+    let test_db = common::TestDb::new("test_list_benchmarks.db");
+    let first = test_db.insert_benchmark(1, "B-1");
+    let second = test_db.insert_benchmark(1, "B-2");
+    test_db.insert_benchmark(2, "B-3");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let benchmarks = repo.list_benchmarks(HubId::new(1).unwrap()).unwrap();
+
+    assert_eq!(
+        benchmarks
+            .iter()
+            .map(|benchmark| benchmark.id)
+            .collect::<Vec<_>>(),
+        vec![first, second]
+    );
+    assert_eq!(
+        repo.list_benchmarks(HubId::new(2).unwrap()).unwrap().len(),
+        1
+    );
+    assert!(
+        repo.list_benchmarks(HubId::new(3).unwrap())
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn distinct_product_categories_are_counted_per_hub() {
+    let test_db = common::TestDb::new("test_distinct_product_categories.db");
+    let first = test_db.insert_crawler(1, "categories-first");
+    let second = test_db.insert_crawler(1, "categories-second");
+    let other_hub = test_db.insert_crawler(2, "categories-other");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let with_category = |crawler_id, index, category: Option<&str>| {
+        let mut product = common::sample_product(crawler_id, index);
+        product.category = category.map(|name| CategoryName::new(name.to_string()).unwrap());
+        product
+    };
+    repo.create_products(&[
+        with_category(first, 0, Some("Чай / Зелёный")),
+        with_category(first, 1, Some("Чай / Улун")),
+        with_category(first, 2, None),
+    ])
+    .unwrap();
+    repo.create_products(&[
+        with_category(second, 0, Some("Чай / Зелёный")),
+        with_category(second, 1, Some("Посуда")),
+    ])
+    .unwrap();
+    repo.create_products(&[with_category(other_hub, 0, Some("Кофе"))])
+        .unwrap();
+
+    let categories = repo
+        .list_distinct_product_categories(HubId::new(1).unwrap())
+        .unwrap();
+
+    assert_eq!(
+        categories,
+        vec![
+            ProductCategoryCount {
+                category: "Чай / Зелёный".to_string(),
+                products: 2,
+            },
+            ProductCategoryCount {
+                category: "Посуда".to_string(),
+                products: 1,
+            },
+            ProductCategoryCount {
+                category: "Чай / Улун".to_string(),
+                products: 1,
+            },
+        ]
+    );
+    assert!(
+        repo.list_distinct_product_categories(HubId::new(3).unwrap())
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn with_transaction_rolls_back_every_step_on_error() {
+    use diesel::prelude::*;
+    use pushkind_common::repository::errors::RepositoryError;
+    use pushkind_dantes::schema::products;
+
+    let test_db = common::TestDb::new("test_with_transaction.db");
+    let crawler_id = test_db.insert_crawler(1, "atomic");
+    let repo = DieselRepository::new(test_db.pool());
+    repo.create_products(&[common::sample_product(crawler_id, 0)])
+        .unwrap();
+
+    let result = repo.with_transaction(|conn| {
+        diesel::delete(products::table).execute(conn)?;
+        Err::<(), _>(RepositoryError::NotFound)
+    });
+
+    assert!(matches!(result, Err(RepositoryError::NotFound)));
+    assert_eq!(repo.count_products(crawler_id).unwrap(), 1);
+
+    let deleted = repo
+        .with_transaction(|conn| Ok(diesel::delete(products::table).execute(conn)?))
+        .unwrap();
+    assert_eq!(deleted, 1);
+    assert_eq!(repo.count_products(crawler_id).unwrap(), 0);
+}
+
+#[test]
+fn list_associations_returns_products_most_similar_first() {
+    let test_db = common::TestDb::new("test_list_associations.db");
+    let crawler_id = test_db.insert_crawler(1, "associations");
+    let benchmark_id = test_db.insert_benchmark(1, "B-1");
+    let other_benchmark_id = test_db.insert_benchmark(1, "B-2");
+    let repo = DieselRepository::new(test_db.pool());
+
+    let mut products = (0..3)
+        .map(|index| common::sample_product(crawler_id, index))
+        .collect::<Vec<_>>();
+    products[2].images = vec![image("https://store.example/images/2.jpg")];
+    repo.create_products(&products).unwrap();
+    let stored = repo.list_products(crawler_id).unwrap();
+    let similarity = |value| SimilarityDistance::new(value).unwrap();
+
+    repo.set_benchmark_association(benchmark_id, stored[0].id, similarity(0.82))
+        .unwrap();
+    repo.set_benchmark_association(benchmark_id, stored[2].id, similarity(0.95))
+        .unwrap();
+    // A duplicate row of an earlier run keeps only the best similarity.
+    repo.set_benchmark_association(benchmark_id, stored[0].id, similarity(0.81))
+        .unwrap();
+    repo.set_benchmark_association(other_benchmark_id, stored[1].id, similarity(0.99))
+        .unwrap();
+
+    let associations = repo.list_associations(benchmark_id).unwrap();
+
+    assert_eq!(
+        associations
+            .iter()
+            .map(|(product, similarity)| (product.sku.as_str(), *similarity))
+            .collect::<Vec<_>>(),
+        vec![("SKU-2", 0.95), ("SKU-0", 0.82)]
+    );
+    assert_eq!(
+        associations[0]
+            .0
+            .images
+            .iter()
+            .map(|image| image.as_str())
+            .collect::<Vec<_>>(),
+        vec!["https://store.example/images/2.jpg"]
+    );
+    assert!(
+        repo.list_associations(test_db.insert_benchmark(1, "B-3"))
+            .unwrap()
+            .is_empty()
+    );
+}
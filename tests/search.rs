@@ -0,0 +1,45 @@
+mod common;
+
+use pushkind_crawlers::processing::search::search_products_by_embedding;
+use pushkind_crawlers::repository::{DieselRepository, ProductReader, ProductWriter};
+use pushkind_dantes::domain::types::HubId;
+
+#[test]
+fn search_ranks_embedded_hub_products_and_skips_the_rest() {
+    let test_db = common::TestDb::new("test_search_products.db");
+    let crawler_id = test_db.insert_crawler(1, "search");
+    let other_hub_crawler_id = test_db.insert_crawler(2, "other-hub");
+    let repo = DieselRepository::new(test_db.pool());
+
+    repo.create_products(&[
+        common::sample_product(crawler_id, 0),
+        common::sample_product(crawler_id, 1),
+        common::sample_product(crawler_id, 2),
+        common::sample_product(crawler_id, 3),
+        common::sample_product(other_hub_crawler_id, 4),
+    ])
+    .unwrap();
+    let mut stored = repo.list_products(crawler_id).unwrap();
+    stored.sort_by_key(|product| product.id.get());
+    repo.set_product_embedding(stored[0].id, &[0.0, 1.0])
+        .unwrap();
+    repo.set_product_embedding(stored[1].id, &[0.6, 0.8])
+        .unwrap();
+    // A stale embedding of another model's dimension is not searchable.
+    repo.set_product_embedding(stored[2].id, &[1.0, 0.0, 0.0])
+        .unwrap();
+    let other = repo.list_products(other_hub_crawler_id).unwrap();
+    repo.set_product_embedding(other[0].id, &[0.6, 0.8])
+        .unwrap();
+
+    let matches =
+        search_products_by_embedding(&repo, HubId::new(1).unwrap(), &[0.6, 0.8], 10).unwrap();
+
+    let skus = matches
+        .iter()
+        .map(|found| found.product.sku.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(skus, vec!["SKU-1", "SKU-0"]);
+    assert!((matches[0].similarity - 1.0).abs() < 1e-4);
+    assert!((matches[1].similarity - 0.8).abs() < 1e-4);
+}